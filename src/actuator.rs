@@ -0,0 +1,139 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rand_distr::{Distribution, Normal};
+
+// turns a commanded steering angle or acceleration into the value the car actually gets;
+// the default actuator is ideal and passes commands through unchanged
+#[derive(Debug, Clone)]
+pub struct Actuator {
+    // first-order lag time constant in seconds, 0 responds instantly
+    pub time_constant: f64,
+    // largest change of the applied value per second: the steering rate, or the jerk
+    pub rate_limit: Option<f64>,
+    pub min: f64,
+    pub max: f64,
+    // commands smaller than this in magnitude are treated as zero
+    pub deadband: f64,
+    // standard deviation of gaussian noise added to the applied value
+    pub noise_std_dev: f64,
+    // the noise-free actuator position
+    pub position: f64,
+    rng: StdRng,
+}
+
+impl Default for Actuator {
+    fn default() -> Self {
+        Self::new(f64::NEG_INFINITY, f64::INFINITY)
+    }
+}
+
+impl Actuator {
+    // the limits are taken in either order, so a negative max_steer still gives a valid range
+    pub fn new(min: f64, max: f64) -> Self {
+        Self {
+            time_constant: 0.0,
+            rate_limit: None,
+            min: min.min(max),
+            max: max.max(min),
+            deadband: 0.0,
+            noise_std_dev: 0.0,
+            position: 0.0,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    // reseed the noise generator so runs can be reproduced
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    // advances the actuator by `dt` towards `command` and returns the applied value
+    pub fn apply(&mut self, command: f64, dt: f64) -> f64 {
+        let command = if command.abs() < self.deadband {
+            0.0
+        } else {
+            self.limit(command)
+        };
+        let mut target = if self.time_constant > 0.0 {
+            self.position + (command - self.position) * (1.0 - (-dt / self.time_constant).exp())
+        } else {
+            command
+        };
+        if let Some(rate) = self.rate_limit {
+            let step = (rate * dt).abs();
+            target = target.clamp(self.position - step, self.position + step);
+        }
+        self.position = target;
+        let noise = if self.noise_std_dev > 0.0 {
+            Normal::new(0.0, self.noise_std_dev)
+                .unwrap()
+                .sample(&mut self.rng)
+        } else {
+            0.0
+        };
+        self.limit(self.position + noise)
+    }
+
+    // keeps `value` within [min, max]; unlike `clamp` it does not panic when the public limits
+    // were set the wrong way round, in which case `max` wins
+    fn limit(&self, value: f64) -> f64 {
+        value.max(self.min).min(self.max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_passes_commands_through() {
+        let mut actuator = Actuator::default();
+        assert_eq!(actuator.apply(0.3, 0.1), 0.3);
+        assert_eq!(actuator.apply(-2.0, 0.1), -2.0);
+    }
+
+    #[test]
+    fn test_lag_rate_limit_and_deadband() {
+        let mut lagged = Actuator {
+            time_constant: 0.5,
+            ..Actuator::new(-1.0, 1.0)
+        };
+        // after one time constant the response reaches 1 - 1/e of the step
+        let mut value = 0.0;
+        for _ in 0..50 {
+            value = lagged.apply(1.0, 0.01);
+        }
+        assert!((value - (1.0 - (-1.0_f64).exp())).abs() < 1e-9);
+
+        let mut limited = Actuator {
+            rate_limit: Some(0.5),
+            deadband: 0.05,
+            ..Actuator::new(-0.4, 0.4)
+        };
+        assert_eq!(limited.apply(0.04, 0.1), 0.0);
+        assert!((limited.apply(1.0, 0.1) - 0.05).abs() < 1e-12);
+        for _ in 0..20 {
+            limited.apply(1.0, 0.1);
+        }
+        assert_eq!(limited.apply(1.0, 0.1), 0.4);
+    }
+
+    #[test]
+    fn test_reversed_limits_and_negative_rate_do_not_panic() {
+        // e.g. a car built with a negative max_steer
+        let mut reversed = Actuator::new(0.5, -0.5);
+        assert_eq!((reversed.min, reversed.max), (-0.5, 0.5));
+        assert_eq!(reversed.apply(1.0, 0.1), 0.5);
+
+        let mut negative_rate = Actuator {
+            rate_limit: Some(-0.5),
+            ..Actuator::new(-1.0, 1.0)
+        };
+        assert!((negative_rate.apply(1.0, 0.1) - 0.05).abs() < 1e-12);
+
+        let mut inverted = Actuator::new(-1.0, 1.0);
+        inverted.min = 1.0;
+        inverted.max = -1.0;
+        assert_eq!(inverted.apply(0.3, 0.1), -1.0);
+    }
+}
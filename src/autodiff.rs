@@ -0,0 +1,219 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use nalgebra::{SMatrix, SVector};
+
+// the scalar operations motion and measurement functions may use, so they can be evaluated
+// on plain f64 values or on dual numbers that carry derivatives along
+pub trait Real:
+    Copy
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    fn constant(value: f64) -> Self;
+    fn value(self) -> f64;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn tan(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn atan2(self, x: Self) -> Self;
+
+    // a bound that is hit has no derivative with respect to the clamped value
+    fn clamp_value(self, min: f64, max: f64) -> Self {
+        if self.value() < min {
+            Self::constant(min)
+        } else if self.value() > max {
+            Self::constant(max)
+        } else {
+            self
+        }
+    }
+}
+
+impl Real for f64 {
+    fn constant(value: f64) -> Self {
+        value
+    }
+    fn value(self) -> f64 {
+        self
+    }
+    fn sin(self) -> Self {
+        f64::sin(self)
+    }
+    fn cos(self) -> Self {
+        f64::cos(self)
+    }
+    fn tan(self) -> Self {
+        f64::tan(self)
+    }
+    fn sqrt(self) -> Self {
+        f64::sqrt(self)
+    }
+    fn atan2(self, x: Self) -> Self {
+        f64::atan2(self, x)
+    }
+}
+
+// a value together with its derivatives with respect to N inputs (forward-mode AD)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Dual<const N: usize> {
+    pub value: f64,
+    pub derivative: [f64; N],
+}
+
+impl<const N: usize> Dual<N> {
+    // the input with the given index, whose derivative with respect to itself is one
+    pub fn variable(value: f64, index: usize) -> Self {
+        let mut derivative = [0.0; N];
+        derivative[index] = 1.0;
+        Self { value, derivative }
+    }
+
+    // applies a function with the given value and first derivative via the chain rule
+    fn chain(self, value: f64, slope: f64) -> Self {
+        Self {
+            value,
+            derivative: self.derivative.map(|d| d * slope),
+        }
+    }
+}
+
+impl<const N: usize> Add for Dual<N> {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self {
+            value: self.value + other.value,
+            derivative: std::array::from_fn(|i| self.derivative[i] + other.derivative[i]),
+        }
+    }
+}
+
+impl<const N: usize> Sub for Dual<N> {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Self {
+            value: self.value - other.value,
+            derivative: std::array::from_fn(|i| self.derivative[i] - other.derivative[i]),
+        }
+    }
+}
+
+impl<const N: usize> Mul for Dual<N> {
+    type Output = Self;
+    // the product and quotient rules mix operators by design
+    #[allow(clippy::suspicious_arithmetic_impl)]
+    fn mul(self, other: Self) -> Self {
+        Self {
+            value: self.value * other.value,
+            derivative: std::array::from_fn(|i| {
+                self.derivative[i] * other.value + self.value * other.derivative[i]
+            }),
+        }
+    }
+}
+
+impl<const N: usize> Div for Dual<N> {
+    type Output = Self;
+    // the product and quotient rules mix operators by design
+    #[allow(clippy::suspicious_arithmetic_impl)]
+    fn div(self, other: Self) -> Self {
+        let value = self.value / other.value;
+        Self {
+            value,
+            derivative: std::array::from_fn(|i| {
+                (self.derivative[i] - value * other.derivative[i]) / other.value
+            }),
+        }
+    }
+}
+
+impl<const N: usize> Neg for Dual<N> {
+    type Output = Self;
+    fn neg(self) -> Self {
+        self.chain(-self.value, -1.0)
+    }
+}
+
+impl<const N: usize> Real for Dual<N> {
+    fn constant(value: f64) -> Self {
+        Self {
+            value,
+            derivative: [0.0; N],
+        }
+    }
+    fn value(self) -> f64 {
+        self.value
+    }
+    fn sin(self) -> Self {
+        self.chain(self.value.sin(), self.value.cos())
+    }
+    fn cos(self) -> Self {
+        self.chain(self.value.cos(), -self.value.sin())
+    }
+    fn tan(self) -> Self {
+        let tan = self.value.tan();
+        self.chain(tan, 1.0 + tan * tan)
+    }
+    fn sqrt(self) -> Self {
+        let sqrt = self.value.sqrt();
+        self.chain(sqrt, 0.5 / sqrt)
+    }
+    fn atan2(self, x: Self) -> Self {
+        let (y, r_sq) = (self, x.value * x.value + self.value * self.value);
+        Self {
+            value: y.value.atan2(x.value),
+            derivative: std::array::from_fn(|i| {
+                (x.value * y.derivative[i] - y.value * x.derivative[i]) / r_sq
+            }),
+        }
+    }
+}
+
+// value and Jacobian of `function` at `x`, by evaluating it once on dual numbers
+pub fn jacobian<const R: usize, const C: usize>(
+    function: impl Fn([Dual<C>; C]) -> [Dual<C>; R],
+    x: &SVector<f64, C>,
+) -> (SVector<f64, R>, SMatrix<f64, R, C>) {
+    let output = function(std::array::from_fn(|i| Dual::variable(x[i], i)));
+    (
+        SVector::from_fn(|i, _| output[i].value),
+        SMatrix::from_fn(|i, j| output[i].derivative[j]),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::numerical::{assert_jacobian, random_vector};
+    use nalgebra::Vector3;
+
+    fn mixed<T: Real>(v: [T; 3]) -> [T; 2] {
+        let [x, y, z] = v;
+        [
+            x.sin() * y + x / y - z.tan(),
+            (x * x + z * z).sqrt() + y.atan2(-x) * T::constant(2.0),
+        ]
+    }
+
+    #[test]
+    fn test_dual_numbers_match_central_differences() {
+        assert_jacobian(
+            "mixed",
+            |v: &Vector3<f64>| SVector::from(mixed([v[0], v[1], v[2]])),
+            |v| jacobian(mixed, v).1,
+            |rng| random_vector(rng, [(0.5, 3.0), (0.5, 3.0), (-1.0, 1.0)]),
+            1e-6,
+        );
+        let (value, _) = jacobian(mixed, &Vector3::new(1.0, 2.0, 0.5));
+        assert_eq!(value, SVector::from(mixed([1.0, 2.0, 0.5])));
+    }
+
+    #[test]
+    fn test_clamped_value_has_no_derivative() {
+        let x = Dual::<1>::variable(2.0, 0);
+        assert_eq!(x.clamp_value(-1.0, 1.0), Dual::constant(1.0));
+        assert_eq!(x.clamp_value(-3.0, 3.0), x);
+    }
+}
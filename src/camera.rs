@@ -0,0 +1,171 @@
+use std::f64::consts::FRAC_PI_2;
+
+// zoom limits, in pixels per meter
+const MIN_SCALE: f64 = 0.01;
+const MAX_SCALE: f64 = 200.0;
+// grid lines are spaced at a round number of meters that is at least this many pixels apart
+const MIN_GRID_PIXELS: f64 = 40.0;
+
+// maps world coordinates in meters, with y pointing up, to pixels of a screen whose y points down
+#[derive(Debug, Clone)]
+pub struct Camera {
+    // world point at the middle of the screen
+    pub center: (f64, f64),
+    pub pixels_per_meter: f64,
+    // world direction, in radians from the x axis, that points right on the screen
+    pub rotation: f64,
+    pub screen_width: f64,
+    pub screen_height: f64,
+    // vehicle the camera is centered on, if any, and whether its heading points up
+    pub follow: Option<usize>,
+    pub heading_up: bool,
+}
+
+impl Camera {
+    pub fn new(screen_width: u32, screen_height: u32) -> Self {
+        Self {
+            center: (0.0, 0.0),
+            pixels_per_meter: 1.0,
+            rotation: 0.0,
+            screen_width: screen_width as f64,
+            screen_height: screen_height as f64,
+            follow: None,
+            heading_up: false,
+        }
+    }
+
+    pub fn to_screen(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let (dx, dy) = (x - self.center.0, y - self.center.1);
+        let (sin, cos) = self.rotation.sin_cos();
+        let (right, up) = (dx * cos + dy * sin, -dx * sin + dy * cos);
+        (
+            self.screen_width / 2.0 + right * self.pixels_per_meter,
+            self.screen_height / 2.0 - up * self.pixels_per_meter,
+        )
+    }
+
+    pub fn to_world(&self, (u, v): (f64, f64)) -> (f64, f64) {
+        let right = (u - self.screen_width / 2.0) / self.pixels_per_meter;
+        let up = (self.screen_height / 2.0 - v) / self.pixels_per_meter;
+        let (sin, cos) = self.rotation.sin_cos();
+        (
+            self.center.0 + right * cos - up * sin,
+            self.center.1 + right * sin + up * cos,
+        )
+    }
+
+    // zooms by `factor` keeping the world point under `screen_point` in place
+    pub fn zoom_at(&mut self, screen_point: (f64, f64), factor: f64) {
+        let anchor = self.to_world(screen_point);
+        self.pixels_per_meter = (self.pixels_per_meter * factor).clamp(MIN_SCALE, MAX_SCALE);
+        let moved = self.to_world(screen_point);
+        self.center.0 += anchor.0 - moved.0;
+        self.center.1 += anchor.1 - moved.1;
+    }
+
+    // moves the view along with a drag of the mouse by (du, dv) pixels, which stops following
+    pub fn pan(&mut self, du: f64, dv: f64) {
+        let start = self.to_world((0.0, 0.0));
+        let end = self.to_world((du, dv));
+        self.center.0 -= end.0 - start.0;
+        self.center.1 -= end.1 - start.1;
+        self.follow = None;
+    }
+
+    // centers on a followed vehicle at `position`, turning its `yaw` up when heading up
+    pub fn track(&mut self, position: (f64, f64), yaw: f64) {
+        self.center = position;
+        self.rotation = if self.heading_up {
+            yaw - FRAC_PI_2
+        } else {
+            0.0
+        };
+    }
+
+    // world rectangle (min x, min y, max x, max y) covering the whole screen
+    pub fn visible_bounds(&self) -> (f64, f64, f64, f64) {
+        let corners = [
+            (0.0, 0.0),
+            (self.screen_width, 0.0),
+            (0.0, self.screen_height),
+            (self.screen_width, self.screen_height),
+        ]
+        .map(|corner| self.to_world(corner));
+        corners.iter().fold(
+            (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+            |(x0, y0, x1, y1), (x, y)| (x0.min(*x), y0.min(*y), x1.max(*x), y1.max(*y)),
+        )
+    }
+
+    // meters between grid lines: 1, 2 or 5 times a power of ten, the smallest that is still
+    // comfortably far apart on screen
+    pub fn grid_spacing(&self) -> f64 {
+        let minimum = MIN_GRID_PIXELS / self.pixels_per_meter;
+        let magnitude = 10f64.powf(minimum.log10().floor());
+        [1.0, 2.0, 5.0, 10.0]
+            .iter()
+            .map(|step| step * magnitude)
+            .find(|spacing| *spacing >= minimum)
+            .unwrap_or(10.0 * magnitude)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: (f64, f64), b: (f64, f64)) {
+        assert!(
+            (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn test_screen_and_world_round_trip_with_y_up() {
+        let mut camera = Camera::new(1280, 480);
+        camera.center = (100.0, 50.0);
+        camera.pixels_per_meter = 4.0;
+        assert_close(camera.to_screen((100.0, 50.0)), (640.0, 240.0));
+        // up in the world is up on the screen, where pixel rows grow downwards
+        assert_close(camera.to_screen((100.0, 60.0)), (640.0, 200.0));
+        camera.rotation = 0.7;
+        for point in [(0.0, 0.0), (123.0, -45.0), (100.0, 50.0)] {
+            assert_close(camera.to_world(camera.to_screen(point)), point);
+        }
+
+        // heading up puts the direction the vehicle faces at the top of the screen
+        camera.heading_up = true;
+        camera.track((10.0, 10.0), 0.3);
+        let ahead = (10.0 + 0.3f64.cos(), 10.0 + 0.3f64.sin());
+        assert_close(camera.to_screen(ahead), (640.0, 236.0));
+    }
+
+    #[test]
+    fn test_zoom_keeps_cursor_point_and_pan_follows_drag() {
+        let mut camera = Camera::new(800, 600);
+        camera.rotation = 0.4;
+        let cursor = (100.0, 500.0);
+        let under_cursor = camera.to_world(cursor);
+        camera.zoom_at(cursor, 3.0);
+        assert!((camera.pixels_per_meter - 3.0).abs() < 1e-12);
+        assert_close(camera.to_world(cursor), under_cursor);
+
+        camera.follow = Some(0);
+        let grabbed = camera.to_world((300.0, 300.0));
+        camera.pan(25.0, -40.0);
+        assert_close(camera.to_screen(grabbed), (325.0, 260.0));
+        assert_eq!(camera.follow, None);
+    }
+
+    #[test]
+    fn test_grid_spacing_is_round_and_at_least_minimum_pixels() {
+        let mut camera = Camera::new(800, 600);
+        for (scale, spacing) in [(1.0, 50.0), (10.0, 5.0), (0.3, 200.0), (45.0, 1.0)] {
+            camera.pixels_per_meter = scale;
+            assert!((camera.grid_spacing() - spacing).abs() < 1e-9, "{}", scale);
+        }
+    }
+}
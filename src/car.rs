@@ -2,12 +2,15 @@
 // use rand::prelude::*;
 // use rand_distr::{Distribution, Normal};
 // use nalgebra::{Matrix4, Vector4, Vector2, Vector3, Matrix2};
-use nalgebra::Matrix4;
+use nalgebra::{Matrix2, Matrix4, Matrix4x2, Vector6};
 
+use crate::autodiff::{jacobian, Real};
+
+use crate::actuator::Actuator;
 use crate::state::{CarState, get_time_stamp, CarColor};
 use crate::state::Rectangular;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct KinematicBicycleModel {
     pub dt: f64,
     pub wheelbase: f64,
@@ -43,11 +46,8 @@ impl KinematicBicycleModel {
         acceleration: f64,
         steering_angle: f64,
     ) -> CarState {
-        let steering_angle = steering_angle.max(-self.max_steer).min(self.max_steer);
-        let x = x + velocity * yaw.cos() * self.dt;
-        let y = y + velocity * yaw.sin() * self.dt;
-        let yaw = yaw + velocity / self.wheelbase * steering_angle * self.dt;
-        let velocity = velocity + acceleration * self.dt;
+        let [x, y, yaw, velocity] =
+            self._motion([x, y, yaw, velocity], [acceleration, steering_angle]);
         CarState {
             dt: self.dt,
             time_stamp: get_time_stamp(),
@@ -60,6 +60,44 @@ impl KinematicBicycleModel {
         }
     }
 
+    // the motion model over any scalar type, so that its Jacobians can be derived by
+    // automatic differentiation; state is (x, y, yaw, velocity), control (acceleration, steering)
+    pub fn _motion<T: Real>(&self, state: [T; 4], control: [T; 2]) -> [T; 4] {
+        let [x, y, yaw, velocity] = state;
+        let [acceleration, steering_angle] = control;
+        let steering_angle = steering_angle.clamp_value(-self.max_steer, self.max_steer);
+        let dt = T::constant(self.dt);
+        [
+            x + velocity * yaw.cos() * dt,
+            y + velocity * yaw.sin() * dt,
+            yaw + velocity / T::constant(self.wheelbase) * steering_angle.tan() * dt,
+            velocity + acceleration * dt,
+        ]
+    }
+
+    // state and control Jacobians of `_motion` by forward-mode automatic differentiation, which
+    // every estimator and the MPC linearise the model with; steering beyond max_steer is
+    // clamped, so it has no effect there
+    pub fn _autodiff_jacobians(
+        &self,
+        x: f64,
+        y: f64,
+        yaw: f64,
+        velocity: f64,
+        acceleration: f64,
+        steering_angle: f64,
+    ) -> (Matrix4<f64>, Matrix4x2<f64>) {
+        let input = Vector6::new(x, y, yaw, velocity, acceleration, steering_angle);
+        let (_, full) = jacobian(
+            |v| self._motion([v[0], v[1], v[2], v[3]], [v[4], v[5]]),
+            &input,
+        );
+        (
+            full.fixed_view::<4, 4>(0, 0).into_owned(),
+            full.fixed_view::<4, 2>(0, 4).into_owned(),
+        )
+    }
+
     pub fn _jacobian(
         &self,
         x: f64,
@@ -68,13 +106,25 @@ impl KinematicBicycleModel {
         velocity: f64,
         steering_angle: f64,
     ) -> Matrix4<f64> {
-        let mut jacobian = Matrix4::identity();
-        jacobian[(0, 2)] = -velocity * yaw.sin() * self.dt;
-        jacobian[(0, 3)] = yaw.cos() * self.dt;
-        jacobian[(1, 2)] = velocity * yaw.cos() * self.dt;
-        jacobian[(1, 3)] = yaw.sin() * self.dt;
-        jacobian[(2, 3)] = self.dt / self.wheelbase * steering_angle;
-        jacobian
+        self._autodiff_jacobians(x, y, yaw, velocity, 0.0, steering_angle).0
+    }
+
+    // derivative of the next state with respect to (acceleration, steering_angle), which only
+    // depends on the speed and steering angle
+    pub fn _control_jacobian(&self, velocity: f64, steering_angle: f64) -> Matrix4x2<f64> {
+        self._autodiff_jacobians(0.0, 0.0, 0.0, velocity, 0.0, steering_angle).1
+    }
+
+    // process noise Q = G M G^T caused by noisy controls, where G is the control Jacobian and
+    // M the covariance of (acceleration, steering_angle)
+    pub fn _control_process_noise(
+        &self,
+        velocity: f64,
+        steering_angle: f64,
+        control_covariance: &Matrix2<f64>,
+    ) -> Matrix4<f64> {
+        let g = self._control_jacobian(velocity, steering_angle);
+        g * control_covariance * g.transpose()
     }
 }
 
@@ -88,8 +138,14 @@ pub struct Car {
     // pub rectangular: Rectangular,
     pub width: f64,
     pub length: f64,
+    // the controls last passed to `step`
+    pub commanded_acceleration: f64,
+    pub commanded_steering_angle: f64,
+    // the controls the actuators actually applied in the last step
     pub acceleration: f64,
     pub steering_angle: f64,
+    pub throttle: Actuator,
+    pub steering: Actuator,
     pub model: KinematicBicycleModel,
     pub color: Option<CarColor>,
 }
@@ -121,14 +177,23 @@ impl Car {
             width,  //TODO remove width and length from here
             length, //TODO remove width and length from here
             color,
+            commanded_acceleration: 0.0,
+            commanded_steering_angle: 0.0,
             acceleration: 0.0,
             steering_angle: 0.0,
+            throttle: Actuator::default(),
+            steering: Actuator::new(-max_steer, max_steer),
             model: KinematicBicycleModel::_new(wheelbase, max_steer, delta_time),
         } 
             
     }
 
+    // the commands go through the actuators, so the applied controls can lag behind them
     pub fn step(&mut self, acceleration: f64, steering_angle: f64) -> Rectangular {
+        self.commanded_acceleration = acceleration;
+        self.commanded_steering_angle = steering_angle;
+        let acceleration = self.throttle.apply(acceleration, self.model.dt);
+        let steering_angle = self.steering.apply(steering_angle, self.model.dt);
         self.state = self.model._update(
             self.state.x,
             self.state.y,
@@ -137,8 +202,123 @@ impl Car {
             acceleration,
             steering_angle,
         );
+        self.state.width = Some(self.width);
+        self.state.length = Some(self.length);
         self.acceleration = acceleration;
         self.steering_angle = steering_angle;
         self.state.to_rectangular( self.color)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::numerical::{assert_jacobian, random_vector};
+    use nalgebra::{SMatrix, SVector, Vector2};
+
+    // hand-derived Jacobians of the model, kept as an oracle for the automatic ones
+    fn analytic_jacobian(
+        model: &KinematicBicycleModel,
+        yaw: f64,
+        velocity: f64,
+        steering_angle: f64,
+    ) -> Matrix4<f64> {
+        let mut jacobian = Matrix4::identity();
+        jacobian[(0, 2)] = -velocity * yaw.sin() * model.dt;
+        jacobian[(0, 3)] = yaw.cos() * model.dt;
+        jacobian[(1, 2)] = velocity * yaw.cos() * model.dt;
+        jacobian[(1, 3)] = yaw.sin() * model.dt;
+        let steering_angle = steering_angle.max(-model.max_steer).min(model.max_steer);
+        jacobian[(2, 3)] = model.dt / model.wheelbase * steering_angle.tan();
+        jacobian
+    }
+
+    fn analytic_control_jacobian(
+        model: &KinematicBicycleModel,
+        velocity: f64,
+        steering_angle: f64,
+    ) -> Matrix4x2<f64> {
+        let mut jacobian = Matrix4x2::zeros();
+        if steering_angle.abs() < model.max_steer {
+            jacobian[(2, 1)] = velocity / model.wheelbase * model.dt / steering_angle.cos().powi(2);
+        }
+        jacobian[(3, 0)] = model.dt;
+        jacobian
+    }
+
+    #[test]
+    fn test_jacobians_match_central_differences() {
+        let model = KinematicBicycleModel::_new(2.5, 0.5, 0.1);
+        // (x, y, yaw, velocity, acceleration, steering_angle) to the next (x, y, yaw, velocity)
+        let update = |v: &SVector<f64, 6>| {
+            let next = model._update(v[0], v[1], v[2], v[3], v[4], v[5]);
+            SVector::<f64, 4>::new(next.x, next.y, next.yaw, next.velocity)
+        };
+        let analytic = |v: &SVector<f64, 6>| {
+            let mut jacobian = SMatrix::<f64, 4, 6>::zeros();
+            jacobian
+                .fixed_view_mut::<4, 4>(0, 0)
+                .copy_from(&analytic_jacobian(&model, v[2], v[3], v[5]));
+            jacobian
+                .fixed_view_mut::<4, 2>(0, 4)
+                .copy_from(&analytic_control_jacobian(&model, v[3], v[5]));
+            jacobian
+        };
+        assert_jacobian(
+            "bicycle model",
+            update,
+            analytic,
+            |rng| {
+                random_vector(
+                    rng,
+                    [
+                        (-100.0, 100.0),
+                        (-100.0, 100.0),
+                        (-3.0, 3.0),
+                        (-5.0, 20.0),
+                        (-3.0, 3.0),
+                        (-0.49, 0.49),
+                    ],
+                )
+            },
+            1e-6,
+        );
+        // saturated steering does not move the car any further
+        assert_eq!(analytic_control_jacobian(&model, 5.0, 0.7)[(2, 1)], 0.0);
+        assert_eq!(model._control_jacobian(5.0, 0.7)[(2, 1)], 0.0);
+    }
+
+    #[test]
+    fn test_autodiff_matches_hand_written_jacobians() {
+        let model = KinematicBicycleModel::_new(2.5, 0.5, 0.1);
+        for &(yaw, velocity, steering_angle) in
+            [(0.3, 4.0, 0.2), (-2.0, 10.0, -0.45), (3.0, -1.0, 0.7)].iter()
+        {
+            let (state, control) =
+                model._autodiff_jacobians(1.0, -2.0, yaw, velocity, 0.5, steering_angle);
+            assert!(
+                (state - analytic_jacobian(&model, yaw, velocity, steering_angle)).norm() < 1e-12
+            );
+            assert!(
+                (control - analytic_control_jacobian(&model, velocity, steering_angle)).norm()
+                    < 1e-12
+            );
+            // the wrappers are the two halves, which do not depend on position or acceleration
+            assert_eq!(model._jacobian(1.0, -2.0, yaw, velocity, steering_angle), state);
+            assert_eq!(model._control_jacobian(velocity, steering_angle), control);
+        }
+    }
+
+    #[test]
+    fn test_control_noise_grows_heading_and_speed_variance() {
+        let model = KinematicBicycleModel::_new(2.0, 0.5, 0.1);
+        let noise = model._control_process_noise(
+            10.0,
+            0.0,
+            &Matrix2::from_diagonal(&Vector2::new(0.04, 0.01)),
+        );
+        assert!((noise[(3, 3)] - 0.04 * 0.01).abs() < 1e-12);
+        assert!((noise[(2, 2)] - 0.01 * (10.0 / 2.0 * 0.1_f64).powi(2)).abs() < 1e-12);
+        assert_eq!(noise[(0, 0)], 0.0);
+    }
+}
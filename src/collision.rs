@@ -0,0 +1,296 @@
+use crate::state::{wrap_angle, CarState, Rectangular, ReferencePoint};
+
+// result of an overlap test between two oriented boxes
+#[derive(Debug, Copy, Clone)]
+pub struct Contact {
+    // depth along `normal` that separates the two boxes
+    pub depth: f64,
+    // unit axis pointing from the first box towards the second
+    pub normal: (f64, f64),
+}
+
+// a collision found between two entries of a scene, indexed in the order they were given
+#[derive(Debug, Copy, Clone)]
+pub struct Collision {
+    pub first: usize,
+    pub second: usize,
+    pub contact: Contact,
+}
+
+fn edge_normals(corners: &[(f64, f64); 4]) -> [(f64, f64); 2] {
+    // a rectangle only has two distinct edge directions
+    let mut normals = [(0.0, 0.0); 2];
+    for (i, normal) in normals.iter_mut().enumerate() {
+        let (x1, y1) = corners[i];
+        let (x2, y2) = corners[i + 1];
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        let norm = (dx * dx + dy * dy).sqrt().max(f64::EPSILON);
+        *normal = (-dy / norm, dx / norm);
+    }
+    normals
+}
+
+fn project(corners: &[(f64, f64); 4], axis: (f64, f64)) -> (f64, f64) {
+    corners
+        .iter()
+        .map(|(x, y)| x * axis.0 + y * axis.1)
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), p| {
+            (min.min(p), max.max(p))
+        })
+}
+
+fn centroid(corners: &[(f64, f64); 4]) -> (f64, f64) {
+    let (sx, sy) = corners
+        .iter()
+        .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+    (sx / 4.0, sy / 4.0)
+}
+
+// separating axis test, returning the minimum translation needed to separate the boxes
+pub fn penetration(a: &Rectangular, b: &Rectangular) -> Option<Contact> {
+    let corners_a = a.corners();
+    let corners_b = b.corners();
+    let mut best: Option<Contact> = None;
+
+    for axis in edge_normals(&corners_a)
+        .iter()
+        .chain(edge_normals(&corners_b).iter())
+    {
+        let (min_a, max_a) = project(&corners_a, *axis);
+        let (min_b, max_b) = project(&corners_b, *axis);
+        let depth = max_a.min(max_b) - min_a.max(min_b);
+        if depth <= 0.0 {
+            return None;
+        }
+        if best.is_none_or(|contact| depth < contact.depth) {
+            best = Some(Contact {
+                depth,
+                normal: *axis,
+            });
+        }
+    }
+
+    // orient the normal from a to b
+    best.map(|mut contact| {
+        let (ax, ay) = centroid(&corners_a);
+        let (bx, by) = centroid(&corners_b);
+        if (bx - ax) * contact.normal.0 + (by - ay) * contact.normal.1 < 0.0 {
+            contact.normal = (-contact.normal.0, -contact.normal.1);
+        }
+        contact
+    })
+}
+
+pub fn overlaps(a: &Rectangular, b: &Rectangular) -> bool {
+    penetration(a, b).is_some()
+}
+
+fn point_segment_distance(p: (f64, f64), s1: (f64, f64), s2: (f64, f64)) -> f64 {
+    let (dx, dy) = (s2.0 - s1.0, s2.1 - s1.1);
+    let length_sq = dx * dx + dy * dy;
+    let t = if length_sq > 0.0 {
+        (((p.0 - s1.0) * dx + (p.1 - s1.1) * dy) / length_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let (cx, cy) = (s1.0 + t * dx, s1.1 + t * dy);
+    ((p.0 - cx).powi(2) + (p.1 - cy).powi(2)).sqrt()
+}
+
+// shortest distance between the outlines of two boxes, zero when they overlap
+pub fn distance(a: &Rectangular, b: &Rectangular) -> f64 {
+    if overlaps(a, b) {
+        return 0.0;
+    }
+    let corners_a = a.corners();
+    let corners_b = b.corners();
+    let mut min_distance = f64::INFINITY;
+    // for disjoint convex polygons the closest pair always involves a vertex
+    for i in 0..4 {
+        let (a1, a2) = (corners_a[i], corners_a[(i + 1) % 4]);
+        let (b1, b2) = (corners_b[i], corners_b[(i + 1) % 4]);
+        for j in 0..4 {
+            min_distance = min_distance
+                .min(point_segment_distance(corners_b[j], a1, a2))
+                .min(point_segment_distance(corners_a[j], b1, b2));
+        }
+    }
+    min_distance
+}
+
+fn interpolate(start: &CarState, end: &CarState, t: f64) -> CarState {
+    let mut state = *start;
+    state.x = start.x + (end.x - start.x) * t;
+    state.y = start.y + (end.y - start.y) * t;
+    state.yaw = start.yaw + wrap_angle(end.yaw - start.yaw) * t;
+    state.velocity = start.velocity + (end.velocity - start.velocity) * t;
+    state
+}
+
+fn footprint_rect(state: &CarState) -> Rectangular {
+    state
+        .to_footprint(ReferencePoint::Center)
+        .to_rectangular(None)
+}
+
+// number of samples for a swept check: enough that, relative to each other, the boxes move no
+// further than a quarter of the smallest side between two samples, so a thin box cannot tunnel
+// through the other. Only the relative motion counts: two cars driving side by side need one
+// sample, two closing head-on twice as many as one car driving into a parked one
+fn swept_steps(
+    a_start: &CarState,
+    a_end: &CarState,
+    b_start: &CarState,
+    b_end: &CarState,
+) -> usize {
+    let footprint = |state: &CarState| state.to_footprint(ReferencePoint::Center);
+    let (a, b) = (footprint(a_start), footprint(b_start));
+    let translation = ((a_end.x - a_start.x) - (b_end.x - b_start.x))
+        .hypot((a_end.y - a_start.y) - (b_end.y - b_start.y));
+    // a turning box sweeps its corners around its center by up to the angle times the radius
+    let rotation = |start: &CarState, end: &CarState, radius: f64| {
+        wrap_angle(end.yaw - start.yaw).abs() * radius
+    };
+    let rotation = rotation(a_start, a_end, 0.5 * a.length.hypot(a.width))
+        + rotation(b_start, b_end, 0.5 * b.length.hypot(b.width));
+    let smallest_side = a
+        .length
+        .min(a.width)
+        .min(b.length)
+        .min(b.width)
+        .max(f64::EPSILON);
+    ((translation + rotation) / (0.25 * smallest_side))
+        .ceil()
+        .clamp(1.0, 10_000.0) as usize
+}
+
+// continuous check between two cars moving from `*_start` to `*_end` over one step;
+// returns the fraction of the step at which they first touch
+pub fn swept_collision(
+    a_start: &CarState,
+    a_end: &CarState,
+    b_start: &CarState,
+    b_end: &CarState,
+) -> Option<f64> {
+    let steps = swept_steps(a_start, a_end, b_start, b_end);
+    (0..=steps).map(|i| i as f64 / steps as f64).find(|t| {
+        overlaps(
+            &footprint_rect(&interpolate(a_start, a_end, *t)),
+            &footprint_rect(&interpolate(b_start, b_end, *t)),
+        )
+    })
+}
+
+// pairwise overlap test over every car and obstacle footprint in a scene
+pub fn detect_collisions(boxes: &[Rectangular]) -> Vec<Collision> {
+    let mut collisions = Vec::new();
+    for first in 0..boxes.len() {
+        for second in (first + 1)..boxes.len() {
+            if let Some(contact) = penetration(&boxes[first], &boxes[second]) {
+                collisions.push(Collision {
+                    first,
+                    second,
+                    contact,
+                });
+            }
+        }
+    }
+    collisions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Footprint;
+    use std::f64::consts::PI;
+
+    fn rect(x: f64, y: f64, heading: f64) -> Rectangular {
+        Footprint::new(x, y, heading, 40.0, 20.0, ReferencePoint::Center).to_rectangular(None)
+    }
+
+    fn car_at(x: f64, y: f64, yaw: f64) -> CarState {
+        let mut state = CarState::new();
+        state.x = x;
+        state.y = y;
+        state.yaw = yaw;
+        state.length = Some(40.0);
+        state.width = Some(20.0);
+        state
+    }
+
+    #[test]
+    fn test_overlap_and_penetration() {
+        let a = rect(0.0, 0.0, 0.0);
+        let b = rect(35.0, 0.0, 0.0);
+        let contact = penetration(&a, &b).unwrap();
+        assert!((contact.depth - 5.0).abs() < 1e-9);
+        assert!((contact.normal.0 - 1.0).abs() < 1e-9);
+
+        // rotated box whose bounding boxes overlap but the boxes themselves do not
+        let c = rect(38.0, 25.0, PI / 4.0);
+        assert!(!overlaps(&a, &c));
+        assert!(distance(&a, &c) > 0.0);
+    }
+
+    #[test]
+    fn test_distance() {
+        let a = rect(0.0, 0.0, 0.0);
+        let b = rect(50.0, 0.0, 0.0);
+        assert!((distance(&a, &b) - 10.0).abs() < 1e-9);
+        assert_eq!(distance(&a, &rect(10.0, 5.0, 1.0)), 0.0);
+    }
+
+    #[test]
+    fn test_swept_collision_catches_tunnelling() {
+        // b crosses a completely within one step, so the end poses alone miss it
+        let a = car_at(0.0, 0.0, 0.0);
+        let b_start = car_at(-100.0, 0.0, 0.0);
+        let b_end = car_at(100.0, 0.0, 0.0);
+        assert!(!overlaps(&footprint_rect(&a), &footprint_rect(&b_end)));
+        let t = swept_collision(&a, &a, &b_start, &b_end).unwrap();
+        assert!(t > 0.0 && t < 0.5);
+
+        let c_start = car_at(-100.0, 50.0, 0.0);
+        let c_end = car_at(100.0, 50.0, 0.0);
+        assert!(swept_collision(&a, &a, &c_start, &c_end).is_none());
+    }
+
+    #[test]
+    fn test_swept_steps_follow_relative_motion() {
+        let parked = car_at(0.0, 0.0, 0.0);
+        let one_moving = swept_steps(
+            &parked,
+            &parked,
+            &car_at(-100.0, 0.0, 0.0),
+            &car_at(100.0, 0.0, 0.0),
+        );
+        let head_on = swept_steps(
+            &car_at(100.0, 0.0, 0.0),
+            &car_at(-100.0, 0.0, 0.0),
+            &car_at(-100.0, 0.0, 0.0),
+            &car_at(100.0, 0.0, 0.0),
+        );
+        let side_by_side = swept_steps(
+            &car_at(0.0, 0.0, 0.0),
+            &car_at(200.0, 0.0, 0.0),
+            &car_at(0.0, 30.0, 0.0),
+            &car_at(200.0, 30.0, 0.0),
+        );
+        // 200 m of relative travel at a quarter of the 20 m width per sample
+        assert_eq!(one_moving, 40);
+        assert_eq!(head_on, 2 * one_moving);
+        assert_eq!(side_by_side, 1);
+    }
+
+    #[test]
+    fn test_detect_collisions() {
+        let boxes = [
+            rect(0.0, 0.0, 0.0),
+            rect(200.0, 0.0, 0.0),
+            rect(30.0, 10.0, 0.3),
+        ];
+        let collisions = detect_collisions(&boxes);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!((collisions[0].first, collisions[0].second), (0, 2));
+    }
+}
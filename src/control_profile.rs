@@ -0,0 +1,318 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::path::Path;
+
+use crate::controller::Controller;
+use crate::state::CarState;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    // hold each value until the next breakpoint
+    Constant,
+    // ramp linearly between breakpoints
+    Linear,
+}
+
+// a value over simulation time defined by (time, value) breakpoints;
+// before the first and after the last breakpoint the end values are held
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub points: Vec<(f64, f64)>,
+    pub interpolation: Interpolation,
+}
+
+impl Schedule {
+    pub fn new(mut points: Vec<(f64, f64)>, interpolation: Interpolation) -> Self {
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self {
+            points,
+            interpolation,
+        }
+    }
+
+    pub fn constant(value: f64) -> Self {
+        Self::new(vec![(0.0, value)], Interpolation::Constant)
+    }
+
+    pub fn sample(&self, time: f64) -> f64 {
+        let (Some(first), Some(last)) = (self.points.first(), self.points.last()) else {
+            return 0.0;
+        };
+        if time <= first.0 {
+            return first.1;
+        }
+        if time >= last.0 {
+            return last.1;
+        }
+        // index of the first breakpoint strictly after `time`
+        let next = self.points.partition_point(|(t, _)| *t <= time);
+        let (t0, v0) = self.points[next - 1];
+        let (t1, v1) = self.points[next];
+        match self.interpolation {
+            Interpolation::Constant => v0,
+            Interpolation::Linear => v0 + (v1 - v0) * (time - t0) / (t1 - t0),
+        }
+    }
+}
+
+// time-indexed (acceleration, steering_angle) commands for `Car::step`
+#[derive(Debug, Clone)]
+pub enum ControlProfile {
+    Schedule {
+        acceleration: Schedule,
+        steering: Schedule,
+    },
+    // steering sine whose frequency sweeps linearly from `start_frequency` to `end_frequency` Hz
+    SineSweep {
+        start_time: f64,
+        duration: f64,
+        amplitude: f64,
+        start_frequency: f64,
+        end_frequency: f64,
+        acceleration: f64,
+    },
+    // one full steering sine period, which turns away and back to the original heading
+    LaneChange {
+        start_time: f64,
+        duration: f64,
+        amplitude: f64,
+        acceleration: f64,
+    },
+    // constant steering held until the heading has turned by pi
+    UTurn {
+        start_time: f64,
+        duration: f64,
+        steering_angle: f64,
+        acceleration: f64,
+    },
+    // (time, acceleration, steering_angle) samples, replayed with zero-order hold
+    Replay {
+        samples: Vec<(f64, f64, f64)>,
+    },
+}
+
+impl ControlProfile {
+    // steering amplitude that shifts a car at constant `speed` sideways by `offset` over `duration`,
+    // using the small angle approximation of the kinematic bicycle model
+    pub fn lane_change(
+        start_time: f64,
+        duration: f64,
+        offset: f64,
+        speed: f64,
+        wheelbase: f64,
+    ) -> Self {
+        let amplitude = 2.0 * PI * wheelbase * offset / (speed * speed * duration * duration);
+        ControlProfile::LaneChange {
+            start_time,
+            duration,
+            amplitude,
+            acceleration: 0.0,
+        }
+    }
+
+    // a U-turn at constant `speed`, steering with `steering_angle` (negative turns right)
+    pub fn u_turn(start_time: f64, speed: f64, wheelbase: f64, steering_angle: f64) -> Self {
+        let yaw_rate = speed * steering_angle.abs().tan() / wheelbase;
+        ControlProfile::UTurn {
+            start_time,
+            duration: PI / yaw_rate,
+            steering_angle,
+            acceleration: 0.0,
+        }
+    }
+
+    // reads `time,acceleration,steering_angle` rows; a header line is allowed
+    pub fn load_recording(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read recording '{}': {}", path.display(), e))?;
+        Self::parse_recording(&text)
+            .map_err(|e| format!("invalid recording '{}': {}", path.display(), e))
+    }
+
+    pub fn parse_recording(text: &str) -> Result<Self, String> {
+        let mut samples = Vec::new();
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("time") || line.starts_with('#') {
+                continue;
+            }
+            let values: Vec<f64> = line
+                .split(',')
+                .map(|value| value.trim().parse::<f64>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("line {}: {}", line_number + 1, e))?;
+            if values.len() != 3 {
+                return Err(format!(
+                    "line {}: expected time,acceleration,steering_angle",
+                    line_number + 1
+                ));
+            }
+            samples.push((values[0], values[1], values[2]));
+        }
+        samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(ControlProfile::Replay { samples })
+    }
+
+    pub fn sample(&self, time: f64) -> (f64, f64) {
+        match self {
+            ControlProfile::Schedule {
+                acceleration,
+                steering,
+            } => (acceleration.sample(time), steering.sample(time)),
+            ControlProfile::SineSweep {
+                start_time,
+                duration,
+                amplitude,
+                start_frequency,
+                end_frequency,
+                acceleration,
+            } => {
+                let t = time - start_time;
+                if t < 0.0 || t > *duration {
+                    return (*acceleration, 0.0);
+                }
+                // phase of a linear chirp
+                let rate = (end_frequency - start_frequency) / duration;
+                let phase = 2.0 * PI * (start_frequency * t + 0.5 * rate * t * t);
+                (*acceleration, amplitude * phase.sin())
+            }
+            ControlProfile::LaneChange {
+                start_time,
+                duration,
+                amplitude,
+                acceleration,
+            } => {
+                let t = time - start_time;
+                if t < 0.0 || t > *duration {
+                    return (*acceleration, 0.0);
+                }
+                (*acceleration, amplitude * (2.0 * PI * t / duration).sin())
+            }
+            ControlProfile::UTurn {
+                start_time,
+                duration,
+                steering_angle,
+                acceleration,
+            } => {
+                let t = time - start_time;
+                if t < 0.0 || t >= *duration {
+                    return (*acceleration, 0.0);
+                }
+                (*acceleration, *steering_angle)
+            }
+            ControlProfile::Replay { samples } => {
+                let held = samples.partition_point(|(t, _, _)| *t <= time);
+                match held.checked_sub(1) {
+                    Some(index) => (samples[index].1, samples[index].2),
+                    None => (0.0, 0.0),
+                }
+            }
+        }
+    }
+}
+
+impl Controller for ControlProfile {
+    fn control(&mut self, time: f64, _state: &CarState) -> (f64, f64) {
+        self.sample(time)
+    }
+}
+
+// wraps a controller and keeps every command it issued, so a run can be replayed later
+pub struct ControlRecorder {
+    pub inner: Box<dyn Controller>,
+    pub samples: Vec<(f64, f64, f64)>,
+}
+
+impl ControlRecorder {
+    pub fn new(inner: Box<dyn Controller>) -> Self {
+        Self {
+            inner,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("time,acceleration,steering_angle\n");
+        for (time, acceleration, steering_angle) in self.samples.iter() {
+            csv.push_str(&format!("{},{},{}\n", time, acceleration, steering_angle));
+        }
+        csv
+    }
+
+    pub fn to_profile(&self) -> ControlProfile {
+        ControlProfile::Replay {
+            samples: self.samples.clone(),
+        }
+    }
+}
+
+impl Controller for ControlRecorder {
+    fn control(&mut self, time: f64, state: &CarState) -> (f64, f64) {
+        let (acceleration, steering_angle) = self.inner.control(time, state);
+        self.samples.push((time, acceleration, steering_angle));
+        (acceleration, steering_angle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::Car;
+
+    #[test]
+    fn test_schedule_interpolation() {
+        let points = vec![(1.0, 0.0), (3.0, 2.0), (4.0, -1.0)];
+        let linear = Schedule::new(points.clone(), Interpolation::Linear);
+        let constant = Schedule::new(points, Interpolation::Constant);
+        assert_eq!(linear.sample(0.0), 0.0);
+        assert!((linear.sample(2.0) - 1.0).abs() < 1e-12);
+        assert_eq!(constant.sample(2.0), 0.0);
+        assert_eq!(constant.sample(3.5), 2.0);
+        assert_eq!(linear.sample(10.0), -1.0);
+    }
+
+    fn drive(profile: &mut ControlProfile, speed: f64, seconds: f64) -> Car {
+        let mut car = Car::new(0.0, 0.0, 0.0, 20.0, 40.0, speed, 2.0, 0.5, 0.01, None);
+        let steps = (seconds / 0.01).round() as usize;
+        for step in 0..steps {
+            let (acceleration, steering_angle) = profile.control(step as f64 * 0.01, &car.state);
+            car.step(acceleration, steering_angle);
+        }
+        car
+    }
+
+    #[test]
+    fn test_lane_change_and_u_turn() {
+        let mut lane_change = ControlProfile::lane_change(1.0, 4.0, 3.5, 10.0, 2.0);
+        let car = drive(&mut lane_change, 10.0, 6.0);
+        assert!(car.state.yaw.abs() < 1e-3);
+        assert!((car.state.y - 3.5).abs() < 0.2, "y = {}", car.state.y);
+
+        let mut u_turn = ControlProfile::u_turn(0.0, 5.0, 2.0, 0.3);
+        let car = drive(&mut u_turn, 5.0, 10.0);
+        assert!((car.state.yaw - PI).abs() < 0.02, "yaw = {}", car.state.yaw);
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let sweep = ControlProfile::SineSweep {
+            start_time: 0.0,
+            duration: 5.0,
+            amplitude: 0.2,
+            start_frequency: 0.1,
+            end_frequency: 1.0,
+            acceleration: 0.5,
+        };
+        let mut recorder = ControlRecorder::new(Box::new(sweep));
+        let state = CarState::new();
+        for step in 0..50 {
+            recorder.control(step as f64 * 0.1, &state);
+        }
+        let replay = ControlProfile::parse_recording(&recorder.to_csv()).unwrap();
+        for (time, acceleration, steering_angle) in recorder.samples.iter() {
+            let (a, s) = replay.sample(*time + 0.05);
+            assert!((a - acceleration).abs() < 1e-12 && (s - steering_angle).abs() < 1e-12);
+        }
+        assert!(ControlProfile::parse_recording("0.0,1.0").is_err());
+    }
+}
@@ -0,0 +1,261 @@
+use crate::path::ReferencePath;
+use crate::state::{wrap_angle, CarState};
+
+// produces the (acceleration, steering_angle) command for `Car::step` at simulation time `time`,
+// given the state the controller is allowed to see (ground truth or an estimate)
+pub trait Controller {
+    fn control(&mut self, time: f64, state: &CarState) -> (f64, f64);
+}
+
+// the same command at every step
+#[derive(Debug, Copy, Clone)]
+pub struct ConstantControl {
+    pub acceleration: f64,
+    pub steering_angle: f64,
+}
+
+impl ConstantControl {
+    pub fn new(acceleration: f64, steering_angle: f64) -> Self {
+        Self {
+            acceleration,
+            steering_angle,
+        }
+    }
+}
+
+impl Controller for ConstantControl {
+    fn control(&mut self, _time: f64, _state: &CarState) -> (f64, f64) {
+        (self.acceleration, self.steering_angle)
+    }
+}
+
+// which state a vehicle's controller is fed with
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum ControlSource {
+    #[default]
+    GroundTruth,
+    Estimate,
+}
+
+// PID controller with output clamping and conditional integration against windup
+#[derive(Debug, Copy, Clone)]
+pub struct Pid {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+    pub output_min: f64,
+    pub output_max: f64,
+    integral: f64,
+    previous_error: Option<f64>,
+}
+
+impl Pid {
+    pub fn new(kp: f64, ki: f64, kd: f64, output_min: f64, output_max: f64) -> Self {
+        Self {
+            kp,
+            ki,
+            kd,
+            output_min,
+            output_max,
+            integral: 0.0,
+            previous_error: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.previous_error = None;
+    }
+
+    pub fn update(&mut self, error: f64, dt: f64) -> f64 {
+        let derivative = match self.previous_error {
+            Some(previous) if dt > 0.0 => (error - previous) / dt,
+            _ => 0.0,
+        };
+        self.previous_error = Some(error);
+        let unclamped =
+            self.kp * error + self.ki * (self.integral + error * dt) + self.kd * derivative;
+        let output = unclamped.clamp(self.output_min, self.output_max);
+        // only integrate while the output is not saturated, or when it pulls back out of saturation
+        if output == unclamped || unclamped.signum() != error.signum() {
+            self.integral += error * dt;
+        }
+        output
+    }
+}
+
+// geometric pure pursuit: steer the rear axle onto the circle through a look-ahead point
+#[derive(Debug, Copy, Clone)]
+pub struct PurePursuit {
+    pub lookahead_distance: f64,
+    // extra look-ahead per m/s of speed
+    pub lookahead_gain: f64,
+    pub wheelbase: f64,
+    pub max_steer: f64,
+}
+
+impl PurePursuit {
+    pub fn steer(&self, path: &ReferencePath, state: &CarState) -> f64 {
+        let Some(projection) = path.nearest(state.x, state.y) else {
+            return 0.0;
+        };
+        let lookahead = self.lookahead_distance + self.lookahead_gain * state.velocity.abs();
+        let (gx, gy) = path.point_at(projection.s + lookahead);
+        let distance = (gx - state.x).hypot(gy - state.y).max(f64::EPSILON);
+        let alpha = (gy - state.y).atan2(gx - state.x) - state.yaw;
+        (2.0 * self.wheelbase * alpha.sin() / distance)
+            .atan()
+            .clamp(-self.max_steer, self.max_steer)
+    }
+}
+
+// Stanley: correct heading error plus cross-track error measured at the front axle
+#[derive(Debug, Copy, Clone)]
+pub struct Stanley {
+    pub gain: f64,
+    // keeps the cross-track term bounded at low speed
+    pub softening: f64,
+    pub wheelbase: f64,
+    pub max_steer: f64,
+}
+
+impl Stanley {
+    pub fn steer(&self, path: &ReferencePath, state: &CarState) -> f64 {
+        let front_x = state.x + self.wheelbase * state.yaw.cos();
+        let front_y = state.y + self.wheelbase * state.yaw.sin();
+        let Some(projection) = path.nearest(front_x, front_y) else {
+            return 0.0;
+        };
+        let heading_error = wrap_angle(projection.heading - state.yaw);
+        let cross_track =
+            (-self.gain * projection.lateral_error).atan2(self.softening + state.velocity.abs());
+        (heading_error + cross_track).clamp(-self.max_steer, self.max_steer)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum LateralController {
+    PurePursuit(PurePursuit),
+    Stanley(Stanley),
+}
+
+impl LateralController {
+    pub fn steer(&self, path: &ReferencePath, state: &CarState) -> f64 {
+        match self {
+            LateralController::PurePursuit(controller) => controller.steer(path, state),
+            LateralController::Stanley(controller) => controller.steer(path, state),
+        }
+    }
+}
+
+// follows a reference path at a target speed, coming to a stop at its end
+pub struct PathFollower {
+    pub path: ReferencePath,
+    pub lateral: LateralController,
+    pub speed: Pid,
+    pub target_speed: f64,
+    last_time: Option<f64>,
+}
+
+impl PathFollower {
+    pub fn new(
+        path: ReferencePath,
+        lateral: LateralController,
+        speed: Pid,
+        target_speed: f64,
+    ) -> Self {
+        Self {
+            path,
+            lateral,
+            speed,
+            target_speed,
+            last_time: None,
+        }
+    }
+}
+
+impl Controller for PathFollower {
+    fn control(&mut self, time: f64, state: &CarState) -> (f64, f64) {
+        let dt = self.last_time.map_or(0.0, |last| time - last);
+        self.last_time = Some(time);
+        let remaining = self
+            .path
+            .nearest(state.x, state.y)
+            .map_or(0.0, |projection| self.path.length() - projection.s);
+        let target_speed = if remaining > 1e-6 {
+            self.target_speed
+        } else {
+            0.0
+        };
+        let acceleration = self.speed.update(target_speed - state.velocity, dt);
+        (acceleration, self.lateral.steer(&self.path, state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::Car;
+
+    fn follow(lateral: LateralController, path: &ReferencePath, seconds: f64) -> (Car, f64) {
+        let mut car = Car::new(0.0, 3.0, 0.3, 1.8, 4.5, 0.0, 2.5, 0.6, 0.05, None);
+        let mut follower = PathFollower::new(
+            path.clone(),
+            lateral,
+            Pid::new(1.0, 0.1, 0.0, -3.0, 2.0),
+            8.0,
+        );
+        let mut worst_late_error: f64 = 0.0;
+        let steps = (seconds / 0.05) as usize;
+        for step in 0..steps {
+            let (acceleration, steering_angle) = follower.control(step as f64 * 0.05, &car.state);
+            car.step(acceleration, steering_angle);
+            if step > steps / 2 {
+                let error = path
+                    .nearest(car.state.x, car.state.y)
+                    .unwrap()
+                    .lateral_error;
+                worst_late_error = worst_late_error.max(error.abs());
+            }
+        }
+        (car, worst_late_error)
+    }
+
+    #[test]
+    fn test_pid_tracks_speed_without_windup() {
+        let mut pid = Pid::new(1.0, 0.5, 0.0, -1.0, 1.0);
+        let mut speed = 0.0;
+        for _ in 0..400 {
+            speed += pid.update(10.0 - speed, 0.1) * 0.1;
+        }
+        assert!((speed - 10.0).abs() < 0.05, "speed = {}", speed);
+    }
+
+    #[test]
+    fn test_pure_pursuit_and_stanley_converge_to_path() {
+        let path = ReferencePath::new(vec![(0.0, 0.0), (100.0, 0.0), (150.0, 40.0)]);
+        let pure_pursuit = LateralController::PurePursuit(PurePursuit {
+            lookahead_distance: 4.0,
+            lookahead_gain: 0.5,
+            wheelbase: 2.5,
+            max_steer: 0.6,
+        });
+        let stanley = LateralController::Stanley(Stanley {
+            gain: 1.0,
+            softening: 1.0,
+            wheelbase: 2.5,
+            max_steer: 0.6,
+        });
+        for lateral in [pure_pursuit, stanley] {
+            let (_, error) = follow(lateral, &path, 12.0);
+            assert!(error < 0.3, "{:?}: lateral error {}", lateral, error);
+        }
+        // the follower stops at the end of the path
+        let (car, _) = follow(stanley, &path, 60.0);
+        assert!(
+            car.state.velocity.abs() < 0.5,
+            "velocity = {}",
+            car.state.velocity
+        );
+    }
+}
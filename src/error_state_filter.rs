@@ -0,0 +1,740 @@
+use nalgebra::{Matrix3, Matrix4, SMatrix, SVector};
+
+use crate::autodiff::{jacobian, Real};
+use crate::kalman_filter::{is_positive_definite, Estimator, EstimatorKind, KalmanFilter, Nis};
+use crate::sensor_measurement::Calibration;
+use crate::sensors::GPS::XYZValues;
+use crate::sensors::IMU::{IMU9Axis, ImuBiases};
+use crate::state::{wrap_angle, CarState};
+use crate::stationary::StaticAlignment;
+
+pub const STATE_SIZE: usize = 13;
+type StateMatrix = SMatrix<f64, STATE_SIZE, STATE_SIZE>;
+type StateVector = SVector<f64, STATE_SIZE>;
+
+// layout of the state: the nominal (x, y, yaw, velocity), the augmented IMU biases, the
+// augmented calibration parameters and the offset of the GPS clock from the IMU clock with the
+// rate at which it drifts
+const BIASES: usize = 4;
+const CALIBRATION: usize = 7;
+const ENCODER_SCALE: usize = 7;
+const MOUNTING_YAW: usize = 10;
+const TIME_OFFSET: usize = 11;
+const CLOCK_DRIFT: usize = 12;
+
+// prior variances of the (encoder scale, GPS lever arm forward and left, IMU mounting yaw):
+// a few percent of scale, a meter or so of antenna offset and a few degrees of mounting yaw
+pub const DEFAULT_CALIBRATION_PRIOR: [f64; 4] = [0.01, 1.0, 1.0, 0.01];
+// prior variance of the GPS time offset, half a second
+pub const DEFAULT_TIME_OFFSET_PRIOR: f64 = 0.25;
+// samples at rest after which the static alignment is done even if the vehicle does not move
+const ALIGNMENT_SAMPLES: usize = 50;
+
+// an accelerometer reading in the sensor frame and the yaw rate reading of one IMU sample
+#[derive(Debug, Copy, Clone)]
+pub struct ImuReading {
+    pub forward: f64,
+    pub lateral: f64,
+    pub yaw_rate: f64,
+}
+
+// the acceleration in the vehicle frame implied by a reading, after removing the biases and
+// rotating the sensor axes by the mounting yaw
+fn vehicle_acceleration<T: Real>(state: &[T; STATE_SIZE], reading: &ImuReading) -> (T, T) {
+    let forward = T::constant(reading.forward) - state[BIASES];
+    let lateral = T::constant(reading.lateral) - state[BIASES + 1];
+    let (sin, cos) = (state[MOUNTING_YAW].sin(), state[MOUNTING_YAW].cos());
+    (forward * cos - lateral * sin, forward * sin + lateral * cos)
+}
+
+// the state after integrating one IMU sample over `dt`; the time offset grows with the clock
+// drift, the biases and the rest of the calibration are random walks and so keep their value
+pub fn imu_motion<T: Real>(
+    state: [T; STATE_SIZE],
+    reading: &ImuReading,
+    dt: f64,
+) -> [T; STATE_SIZE] {
+    let (forward_acceleration, _) = vehicle_acceleration(&state, reading);
+    let [x, y, yaw, velocity, ..] = state;
+    let gyro_bias = state[BIASES + 2];
+    let dt = T::constant(dt);
+    let mut next = state;
+    next[0] = x + velocity * yaw.cos() * dt;
+    next[1] = y + velocity * yaw.sin() * dt;
+    next[2] = yaw + (T::constant(reading.yaw_rate) - gyro_bias) * dt;
+    next[3] = velocity + forward_acceleration * dt;
+    next[TIME_OFFSET] = state[TIME_OFFSET] + state[CLOCK_DRIFT] * dt;
+    next
+}
+
+// a car that does not slip sideways only accelerates laterally by v * yaw rate, so the lateral
+// acceleration implied by a reading minus that is a zero pseudo-measurement
+pub fn lateral_residual<T: Real>(state: [T; STATE_SIZE], reading: &ImuReading) -> [T; 1] {
+    let (_, lateral) = vehicle_acceleration(&state, reading);
+    let velocity = state[3];
+    let gyro_bias = state[BIASES + 2];
+    [lateral - velocity * (T::constant(reading.yaw_rate) - gyro_bias)]
+}
+
+// the GPS measures the antenna position, offset from the reference point by the lever arm, at
+// the time of its stamp; `delay` is how long before the current IMU time that stamp is, and the
+// offset of the GPS clock adds to it. The state is moved back over the delay to first order
+pub fn delayed_gps_measurement<T: Real>(
+    state: [T; STATE_SIZE],
+    delay: f64,
+    yaw_rate: f64,
+) -> [T; 3] {
+    let [x, y, yaw, velocity, ..] = state;
+    let delay = T::constant(delay) + state[TIME_OFFSET];
+    let yaw_rate = T::constant(yaw_rate) - state[BIASES + 2];
+    let (sin, cos) = (yaw.sin(), yaw.cos());
+    let x = x - velocity * cos * delay;
+    let y = y - velocity * sin * delay;
+    let yaw = yaw - yaw_rate * delay;
+    let (forward, left) = (state[CALIBRATION + 1], state[CALIBRATION + 2]);
+    let (sin, cos) = (yaw.sin(), yaw.cos());
+    [
+        x + forward * cos - left * sin,
+        y + forward * sin + left * cos,
+        yaw,
+    ]
+}
+
+// the wheel encoder reports the speed scaled by its scale factor
+pub fn scaled_odometry<T: Real>(state: [T; STATE_SIZE]) -> [T; 1] {
+    [state[ENCODER_SCALE] * state[3]]
+}
+
+// a vehicle at rest has no speed and its gyro reads just the bias
+pub fn stationary_measurement<T: Real>(state: [T; STATE_SIZE]) -> [T; 2] {
+    [state[3], state[BIASES + 2]]
+}
+
+// what a static alignment measures: the heading of the sensor axes and the biases, which at rest
+// are all the accelerometer and the gyro read
+pub fn alignment_measurement<T: Real>(state: [T; STATE_SIZE]) -> [T; 4] {
+    [
+        state[2] + state[MOUNTING_YAW],
+        state[BIASES],
+        state[BIASES + 1],
+        state[BIASES + 2],
+    ]
+}
+
+// error-state (indirect) EKF: a nominal state is dead-reckoned from IMU samples while the
+// filter only tracks the small error of that state, (dx, dy, dyaw, dv) augmented with the
+// errors of the IMU biases and of the sensor calibration. GPS and odometry estimate the error,
+// which is then injected into the nominal state and reset to zero
+pub struct ErrorStateFilter {
+    pub state: CarState,
+    pub bias: ImuBiases,
+    pub calibration: Calibration,
+    // covariance of the error state
+    pub covariance: StateMatrix,
+    // variances of the (acceleration, yaw rate) readings
+    pub imu_noise: [f64; 2],
+    // variances per second of the random walk of the (accelerometer, gyro) biases
+    pub imu_bias_walk: [f64; 2],
+    // variance per second of the slow drift of each calibration parameter
+    pub calibration_walk: f64,
+    // variance per second of the change of the GPS time offset beyond its modelled drift
+    pub time_offset_walk: f64,
+    // estimated seconds per second the GPS clock gains on the IMU clock
+    pub clock_drift: f64,
+    pub gps_noise: Matrix3<f64>,
+    // variance of the odometry speed
+    pub odometry_noise: f64,
+    // variance of the speed of a vehicle found to be at rest
+    pub zero_velocity_noise: f64,
+    estimates_calibration: bool,
+    estimates_time_offset: bool,
+    // IMU time and yaw rate reading of the latest sample
+    clock: f64,
+    yaw_rate: f64,
+    dt: f64,
+    // samples of the first standstill, collected for the static alignment; None once aligned or
+    // when the alignment is skipped
+    alignment: Option<Vec<IMU9Axis>>,
+    pub covariance_faults: Vec<f64>,
+    pub nis: Nis,
+}
+
+impl ErrorStateFilter {
+    // takes over the state, covariance and GPS noise of an EKF, with unknown IMU biases and the
+    // nominal calibration, which is not estimated until `estimate_calibration` is called
+    pub fn from_filter(filter: &KalmanFilter) -> Self {
+        let mut covariance = StateMatrix::zeros();
+        covariance
+            .fixed_view_mut::<4, 4>(0, 0)
+            .copy_from(&filter.covariance);
+        covariance[(BIASES, BIASES)] = 0.1;
+        covariance[(BIASES + 1, BIASES + 1)] = 0.1;
+        covariance[(BIASES + 2, BIASES + 2)] = 0.01;
+        Self {
+            state: filter.state,
+            bias: ImuBiases::default(),
+            calibration: Calibration::default(),
+            covariance,
+            imu_noise: [0.01, 0.001],
+            imu_bias_walk: [1e-5, 1e-6],
+            calibration_walk: 0.0,
+            time_offset_walk: 0.0,
+            clock_drift: 0.0,
+            gps_noise: filter.gps_noise,
+            odometry_noise: 0.01,
+            zero_velocity_noise: 1e-4,
+            estimates_calibration: false,
+            estimates_time_offset: false,
+            clock: filter.state.time_stamp,
+            yaw_rate: 0.0,
+            dt: filter.model.dt,
+            alignment: Some(Vec::new()),
+            covariance_faults: Vec::new(),
+            nis: Nis::default(),
+        }
+    }
+
+    // keeps the biases at their current value instead of estimating them, which removes the
+    // augmented states from the filter
+    pub fn fix_biases(&mut self) {
+        self.fix_states::<3>(BIASES);
+        self.imu_bias_walk = [0.0, 0.0];
+    }
+
+    // starts estimating the calibration, with prior variances for the (encoder scale, GPS
+    // lever arm forward and left, IMU mounting yaw)
+    pub fn estimate_calibration(&mut self, prior: [f64; 4]) {
+        self.fix_states::<4>(CALIBRATION);
+        for (i, variance) in prior.iter().enumerate() {
+            self.covariance[(CALIBRATION + i, CALIBRATION + i)] = *variance;
+        }
+        self.calibration_walk = 1e-8;
+        self.estimates_calibration = true;
+    }
+
+    // starts estimating the offset of the GPS clock from the IMU clock and its drift, with the
+    // prior variance of the offset; the drift is assumed to be within a few parts per thousand
+    pub fn estimate_time_offset(&mut self, prior: f64) {
+        self.fix_states::<2>(TIME_OFFSET);
+        self.covariance[(TIME_OFFSET, TIME_OFFSET)] = prior;
+        self.covariance[(CLOCK_DRIFT, CLOCK_DRIFT)] = 1e-5;
+        self.time_offset_walk = 1e-7;
+        self.estimates_time_offset = true;
+    }
+
+    // trusts the initial heading and biases instead of aligning at the first standstill
+    pub fn skip_static_alignment(&mut self) {
+        self.alignment = None;
+    }
+
+    fn fix_states<const N: usize>(&mut self, start: usize) {
+        self.covariance
+            .fixed_view_mut::<N, STATE_SIZE>(start, 0)
+            .fill(0.0);
+        self.covariance
+            .fixed_view_mut::<STATE_SIZE, N>(0, start)
+            .fill(0.0);
+    }
+
+    fn nominal(&self) -> StateVector {
+        StateVector::from([
+            self.state.x,
+            self.state.y,
+            self.state.yaw,
+            self.state.velocity,
+            self.bias.accel_forward,
+            self.bias.accel_lateral,
+            self.bias.gyro_z,
+            self.calibration.encoder_scale,
+            self.calibration.gps_lever_arm[0],
+            self.calibration.gps_lever_arm[1],
+            self.calibration.imu_mounting_yaw,
+            self.calibration.gps_time_offset,
+            self.clock_drift,
+        ])
+    }
+
+    // integrates one IMU sample and corrects with its lateral reading; the accelerometer axes
+    // are reported in the world frame, so they are rotated back by the nominal heading
+    pub fn propagate(&mut self, sample: &IMU9Axis) {
+        if self
+            .alignment
+            .as_ref()
+            .is_some_and(|samples| !samples.is_empty())
+        {
+            self.align();
+        }
+        let (sin, cos) = self.state.yaw.sin_cos();
+        let reading = ImuReading {
+            forward: sample.acce_x * cos + sample.acce_y * sin,
+            lateral: -sample.acce_x * sin + sample.acce_y * cos,
+            yaw_rate: sample.gyro_z,
+        };
+        let dt = self.dt;
+        let (nominal, f) = jacobian(|state| imu_motion(state, &reading, dt), &self.nominal());
+        let mut noise = StateMatrix::zeros();
+        noise[(3, 3)] = self.imu_noise[0] * dt * dt;
+        noise[(2, 2)] = self.imu_noise[1] * dt * dt;
+        noise[(BIASES, BIASES)] = self.imu_bias_walk[0] * dt;
+        noise[(BIASES + 1, BIASES + 1)] = self.imu_bias_walk[0] * dt;
+        noise[(BIASES + 2, BIASES + 2)] = self.imu_bias_walk[1] * dt;
+        for i in CALIBRATION..TIME_OFFSET {
+            if self.covariance[(i, i)] > 0.0 {
+                noise[(i, i)] = self.calibration_walk * dt;
+            }
+        }
+        if self.covariance[(TIME_OFFSET, TIME_OFFSET)] > 0.0 {
+            noise[(TIME_OFFSET, TIME_OFFSET)] = self.time_offset_walk * dt;
+        }
+        self.covariance = f * self.covariance * f.transpose() + noise;
+        self.state.x = nominal[0];
+        self.state.y = nominal[1];
+        self.state.yaw = nominal[2];
+        self.state.velocity = nominal[3];
+        self.state.time_stamp += dt;
+        self.calibration.gps_time_offset = nominal[TIME_OFFSET];
+        self.clock = sample.time_stamp;
+        self.yaw_rate = sample.gyro_z;
+        self.record();
+
+        let (expected, h) = jacobian(|state| lateral_residual(state, &reading), &self.nominal());
+        let noise = SMatrix::<f64, 1, 1>::new(self.imu_noise[0]);
+        self.nis.imu = self.correct(&h, &-expected, &noise);
+    }
+
+    // an IMU sample taken at rest. While aligning, the samples are only collected and the state
+    // is held; afterwards the sample is integrated and the speed and yaw rate, which are known
+    // to be zero, are corrected for
+    pub fn update_stationary(&mut self, sample: &IMU9Axis) {
+        if let Some(samples) = self.alignment.as_mut() {
+            samples.push(*sample);
+            let aligned = samples.len() >= ALIGNMENT_SAMPLES;
+            self.state.time_stamp += self.dt;
+            self.clock = sample.time_stamp;
+            self.yaw_rate = sample.gyro_z;
+            let (expected, h) =
+                jacobian(|state| [stationary_measurement(state)[0]], &self.nominal());
+            let noise = SMatrix::<f64, 1, 1>::new(self.zero_velocity_noise);
+            self.correct(&h, &-expected, &noise);
+            if aligned {
+                self.align();
+            }
+            return;
+        }
+        self.propagate(sample);
+        let (expected, h) = jacobian(stationary_measurement, &self.nominal());
+        let innovation = SVector::<f64, 2>::new(0.0, sample.gyro_z) - expected;
+        let noise = SMatrix::<f64, 2, 2>::from_diagonal(&SVector::<f64, 2>::new(
+            self.zero_velocity_noise,
+            self.imu_noise[1],
+        ));
+        self.nis.imu = self.correct(&h, &innovation, &noise);
+    }
+
+    // corrects the heading and the biases with the means of the samples collected at rest. The
+    // accelerometer reports in the world frame, so its mean is rotated back by the heading the
+    // magnetometer found
+    fn align(&mut self) {
+        let Some(alignment) = self
+            .alignment
+            .take()
+            .and_then(|samples| StaticAlignment::from_samples(&samples))
+        else {
+            return;
+        };
+        let (sin, cos) = (alignment.heading - self.calibration.imu_mounting_yaw).sin_cos();
+        let [x, y] = alignment.acceleration;
+        let (expected, h) = jacobian(alignment_measurement, &self.nominal());
+        let mut innovation = SVector::<f64, 4>::new(
+            alignment.heading,
+            x * cos + y * sin,
+            -x * sin + y * cos,
+            alignment.yaw_rate,
+        ) - expected;
+        innovation[0] = wrap_angle(innovation[0]);
+        // the rotation mixes the variances of the world axes, so the larger one is taken for both
+        let acceleration_variance =
+            alignment.acceleration_variance[0].max(alignment.acceleration_variance[1]);
+        let noise = SMatrix::<f64, 4, 4>::from_diagonal(&SVector::<f64, 4>::new(
+            alignment.heading_variance,
+            acceleration_variance,
+            acceleration_variance,
+            alignment.yaw_rate_variance,
+        ));
+        self.correct(&h, &innovation, &noise);
+    }
+
+    // correct with a GPS fix in local coordinates (x, y, and heading in z), stamped by the GPS
+    // clock
+    pub fn update_gps(&mut self, measurement: &XYZValues) {
+        let (delay, yaw_rate) = (self.clock - measurement.time_stamp, self.yaw_rate);
+        let (expected, h) = jacobian(
+            |state| delayed_gps_measurement(state, delay, yaw_rate),
+            &self.nominal(),
+        );
+        let mut innovation =
+            SVector::<f64, 3>::new(measurement.x, measurement.y, measurement.z) - expected;
+        innovation[2] = wrap_angle(innovation[2]);
+        let noise = self.gps_noise;
+        self.nis.gps = self.correct(&h, &innovation, &noise);
+    }
+
+    // correct with the speed reported by the wheel encoder
+    pub fn update_odometry(&mut self, speed: f64) {
+        let (expected, h) = jacobian(scaled_odometry, &self.nominal());
+        let innovation = SVector::<f64, 1>::new(speed) - expected;
+        let noise = SMatrix::<f64, 1, 1>::new(self.odometry_noise);
+        self.nis.odometry = self.correct(&h, &innovation, &noise);
+    }
+
+    // estimates the error from the innovation, injects it into the nominal state and resets
+    // it; with additive errors the reset Jacobian is the identity, so P carries over unchanged.
+    // Returns the normalized innovation squared, or None when no correction was made
+    fn correct<const M: usize>(
+        &mut self,
+        h: &SMatrix<f64, M, STATE_SIZE>,
+        innovation: &SVector<f64, M>,
+        noise: &SMatrix<f64, M, M>,
+    ) -> Option<f64> {
+        let s = h * self.covariance * h.transpose() + noise;
+        let s_inv = s.try_inverse()?;
+        let gain = self.covariance * h.transpose() * s_inv;
+        let error = gain * innovation;
+        let i_kh = StateMatrix::identity() - gain * h;
+        self.covariance =
+            i_kh * self.covariance * i_kh.transpose() + gain * noise * gain.transpose();
+
+        self.state.x += error[0];
+        self.state.y += error[1];
+        self.state.yaw += error[2];
+        self.state.velocity += error[3];
+        self.bias.accel_forward += error[BIASES];
+        self.bias.accel_lateral += error[BIASES + 1];
+        self.bias.gyro_z += error[BIASES + 2];
+        self.calibration.encoder_scale += error[ENCODER_SCALE];
+        self.calibration.gps_lever_arm[0] += error[CALIBRATION + 1];
+        self.calibration.gps_lever_arm[1] += error[CALIBRATION + 2];
+        self.calibration.imu_mounting_yaw += error[MOUNTING_YAW];
+        self.calibration.gps_time_offset += error[TIME_OFFSET];
+        self.clock_drift += error[CLOCK_DRIFT];
+        self.record();
+        Some((innovation.transpose() * s_inv * innovation)[0])
+    }
+
+    fn record(&mut self) {
+        if !is_positive_definite(&self.covariance.fixed_view::<4, 4>(0, 0).into_owned()) {
+            self.covariance_faults.push(self.state.time_stamp);
+        }
+    }
+}
+
+impl Estimator for ErrorStateFilter {
+    fn name(&self) -> &'static str {
+        EstimatorKind::ErrorState.name()
+    }
+    // the IMU drives the propagation instead of the commanded controls
+    fn predict(&mut self, _acceleration: f64, _steering_angle: f64) {}
+    fn update_imu(&mut self, sample: &IMU9Axis) {
+        self.propagate(sample);
+    }
+    fn update_stationary(&mut self, sample: &IMU9Axis) {
+        ErrorStateFilter::update_stationary(self, sample);
+    }
+    fn update_gps(&mut self, measurement: &XYZValues) {
+        ErrorStateFilter::update_gps(self, measurement);
+    }
+    fn update_odometry(&mut self, speed: f64) {
+        ErrorStateFilter::update_odometry(self, speed);
+    }
+    fn state(&self) -> CarState {
+        self.state
+    }
+    fn covariance(&self) -> Matrix4<f64> {
+        self.covariance.fixed_view::<4, 4>(0, 0).into_owned()
+    }
+    fn imu_biases(&self) -> Option<ImuBiases> {
+        Some(self.bias)
+    }
+    fn calibration(&self) -> Option<Calibration> {
+        (self.estimates_calibration || self.estimates_time_offset).then_some(self.calibration)
+    }
+    fn covariance_faults(&self) -> &[f64] {
+        &self.covariance_faults
+    }
+    fn nis(&self) -> Nis {
+        self.nis
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::numerical::{assert_jacobian, random_vector};
+    use crate::sensor_measurement::{SensorClock, SensorSet, SensorTiming};
+
+    #[test]
+    fn test_model_jacobians() {
+        let mut bounds = [(-1.0, 1.0); STATE_SIZE];
+        bounds[0] = (-100.0, 100.0);
+        bounds[1] = (-100.0, 100.0);
+        bounds[2] = (-3.0, 3.0);
+        bounds[3] = (0.0, 20.0);
+        bounds[ENCODER_SCALE] = (0.8, 1.2);
+        let reading = ImuReading {
+            forward: 0.3,
+            lateral: -0.2,
+            yaw_rate: 0.1,
+        };
+        assert_jacobian(
+            "imu motion",
+            |v: &StateVector| StateVector::from(imu_motion((*v).into(), &reading, 0.1)),
+            |v| jacobian(|state| imu_motion(state, &reading, 0.1), v).1,
+            |rng| random_vector(rng, bounds),
+            1e-6,
+        );
+        assert_jacobian(
+            "lateral residual",
+            |v: &StateVector| SVector::from(lateral_residual((*v).into(), &reading)),
+            |v| jacobian(|state| lateral_residual(state, &reading), v).1,
+            |rng| random_vector(rng, bounds),
+            1e-6,
+        );
+        assert_jacobian(
+            "delayed gps measurement",
+            |v: &StateVector| SVector::from(delayed_gps_measurement((*v).into(), 0.3, 0.1)),
+            |v| jacobian(|state| delayed_gps_measurement(state, 0.3, 0.1), v).1,
+            |rng| random_vector(rng, bounds),
+            1e-6,
+        );
+        assert_jacobian(
+            "scaled odometry",
+            |v: &StateVector| SVector::from(scaled_odometry((*v).into())),
+            |v| jacobian(scaled_odometry, v).1,
+            |rng| random_vector(rng, bounds),
+            1e-6,
+        );
+        assert_jacobian(
+            "stationary measurement",
+            |v: &StateVector| SVector::from(stationary_measurement((*v).into())),
+            |v| jacobian(stationary_measurement, v).1,
+            |rng| random_vector(rng, bounds),
+            1e-6,
+        );
+        assert_jacobian(
+            "alignment measurement",
+            |v: &StateVector| SVector::from(alignment_measurement((*v).into())),
+            |v| jacobian(alignment_measurement, v).1,
+            |rng| random_vector(rng, bounds),
+            1e-6,
+        );
+    }
+
+    // what the simulated sensors get wrong and which of it the filter estimates
+    #[derive(Default)]
+    struct Setup {
+        fix_biases: bool,
+        miscalibration: Option<Calibration>,
+        gps_clock: SensorClock,
+        gps_latency: f64,
+        estimate_time_offset: bool,
+    }
+
+    // drives a simulated sensor set along a weaving path with GPS every second and odometry
+    // every step, and returns the filter together with the sensors holding the truth
+    fn run(setup: Setup) -> (ErrorStateFilter, SensorSet, CarState) {
+        let mut truth = CarState {
+            velocity: 5.0,
+            dt: 0.1,
+            time_stamp: 0.0,
+            ..CarState::new()
+        };
+        let mut sensors = SensorSet::new(&truth);
+        sensors.seed(7);
+        sensors.gps.noise_ratio = 0.01;
+        sensors.gps_timing = SensorTiming::new(Some(1.0), setup.gps_latency, Vec::new());
+        sensors.gps_clock = setup.gps_clock;
+        sensors.imu.bias = ImuBiases {
+            accel_forward: 0.2,
+            accel_lateral: -0.1,
+            gyro_z: 0.02,
+        };
+        sensors.imu.acce_bias_walk = 0.002;
+        sensors.imu.gyro_bias_walk = 0.0002;
+        if let Some(calibration) = setup.miscalibration {
+            sensors.encoder.scale_factor = calibration.encoder_scale;
+            sensors.gps.lever_arm = calibration.gps_lever_arm;
+            sensors.imu.mounting_yaw = calibration.imu_mounting_yaw;
+        }
+        sensors.from_carstate(&truth);
+        let mut ekf = KalmanFilter::new(&truth, 2.0, 0.5, 0.1);
+        ekf.gps_noise = Matrix3::identity() * 1e-4;
+        let mut filter = ErrorStateFilter::from_filter(&ekf);
+        filter.imu_bias_walk = [1e-5, 1e-7];
+        if setup.fix_biases {
+            filter.fix_biases();
+        }
+        if setup.miscalibration.is_some() {
+            filter.estimate_calibration(DEFAULT_CALIBRATION_PRIOR);
+        }
+        if setup.estimate_time_offset {
+            filter.estimate_time_offset(DEFAULT_TIME_OFFSET_PRIOR);
+        }
+        for k in 0..1500 {
+            let acceleration = 0.5 * (0.05 * k as f64).sin();
+            let yaw_rate = 0.2 * (0.02 * k as f64).cos();
+            truth.x += truth.velocity * truth.yaw.cos() * 0.1;
+            truth.y += truth.velocity * truth.yaw.sin() * 0.1;
+            truth.yaw += yaw_rate * 0.1;
+            truth.velocity += acceleration * 0.1;
+            truth.time_stamp += 0.1;
+            sensors.from_carstate(&truth);
+            filter.propagate(&sensors.imu.get_imu_data(None));
+            filter.update_odometry(sensors.encoder.get_velocity());
+            for fix in sensors.take_gps_fixes(truth.time_stamp) {
+                filter.update_gps(&fix);
+            }
+        }
+        (filter, sensors, truth)
+    }
+
+    #[test]
+    fn test_estimates_drifting_imu_biases() {
+        let (filter, sensors, truth) = run(Setup::default());
+        let error = filter.bias.difference(&sensors.imu.bias);
+        assert!(error.accel_forward.abs() < 0.02, "{:?}", error);
+        assert!(error.accel_lateral.abs() < 0.02, "{:?}", error);
+        assert!(error.gyro_z.abs() < 0.002, "{:?}", error);
+        assert!((filter.state.x - truth.x).abs() < 0.1);
+        assert!((filter.state.y - truth.y).abs() < 0.1);
+        assert!(filter.covariance_faults.is_empty());
+
+        // without the augmented states the biases stay unknown and the heading drifts between fixes
+        let (fixed, _, _) = run(Setup {
+            fix_biases: true,
+            ..Setup::default()
+        });
+        assert_eq!(fixed.bias, ImuBiases::default());
+        assert!((fixed.state.yaw - truth.yaw).abs() > (filter.state.yaw - truth.yaw).abs());
+    }
+
+    // a biased IMU at rest for 10 s with the filter's initial heading off by 0.1 rad, with the
+    // filter either told about the standstill or treating every sample as taken while moving;
+    // returns the filter with the true heading and IMU biases
+    fn stand_still(use_stationary: bool) -> (ErrorStateFilter, f64, ImuBiases) {
+        let truth = CarState {
+            yaw: 0.7,
+            velocity: 0.0,
+            dt: 0.1,
+            time_stamp: 0.0,
+            ..CarState::new()
+        };
+        let mut sensors = SensorSet::new(&truth);
+        sensors.seed(5);
+        sensors.imu.bias = ImuBiases {
+            accel_forward: 0.2,
+            accel_lateral: -0.1,
+            gyro_z: 0.02,
+        };
+        let ekf = KalmanFilter::new(
+            &CarState {
+                yaw: truth.yaw + 0.1,
+                ..truth
+            },
+            2.0,
+            0.5,
+            0.1,
+        );
+        let mut filter = ErrorStateFilter::from_filter(&ekf);
+        if !use_stationary {
+            filter.skip_static_alignment();
+        }
+        let mut truth = truth;
+        for _ in 0..100 {
+            truth.time_stamp += 0.1;
+            sensors.from_carstate(&truth);
+            let sample = sensors.imu.get_imu_data(None);
+            if use_stationary && sensors.stationary.is_stationary() {
+                filter.update_stationary(&sample);
+            } else {
+                filter.propagate(&sample);
+            }
+            filter.update_odometry(sensors.encoder.get_velocity());
+        }
+        (filter, truth.yaw, sensors.imu.bias)
+    }
+
+    #[test]
+    fn test_static_alignment_and_zero_velocity_updates() {
+        let (filter, yaw, bias) = stand_still(true);
+        assert!(filter.alignment.is_none());
+        assert!(
+            (filter.state.yaw - yaw).abs() < 0.01,
+            "{}",
+            filter.state.yaw - yaw
+        );
+        let error = filter.bias.difference(&bias);
+        assert!(error.accel_forward.abs() < 0.02, "{:?}", error);
+        assert!(error.accel_lateral.abs() < 0.02, "{:?}", error);
+        assert!(error.gyro_z.abs() < 0.002, "{:?}", error);
+        assert!(filter.state.velocity.abs() < 0.01);
+        assert!(filter.state.x.hypot(filter.state.y) < 0.05);
+
+        // integrating the gyro bias turns the heading further away while standing
+        let (unaware, yaw, _) = stand_still(false);
+        assert!((unaware.state.yaw - yaw).abs() > 0.1);
+    }
+
+    #[test]
+    fn test_calibration_converges_to_injected_miscalibration() {
+        let miscalibration = Calibration {
+            encoder_scale: 1.05,
+            gps_lever_arm: [1.5, -0.5],
+            imu_mounting_yaw: 0.05,
+            gps_time_offset: 0.0,
+        };
+        let (filter, sensors, truth) = run(Setup {
+            miscalibration: Some(miscalibration),
+            ..Setup::default()
+        });
+        let error = filter.calibration.difference(&sensors.calibration());
+        assert!(error.encoder_scale.abs() < 0.005, "{:?}", error);
+        assert!(error.gps_lever_arm[0].abs() < 0.1, "{:?}", error);
+        assert!(error.gps_lever_arm[1].abs() < 0.1, "{:?}", error);
+        assert!(error.imu_mounting_yaw.abs() < 0.01, "{:?}", error);
+        assert!((filter.state.x - truth.x).abs() < 0.2);
+        assert!((filter.state.y - truth.y).abs() < 0.2);
+    }
+
+    #[test]
+    fn test_recovers_fixed_and_drifting_gps_time_offset() {
+        for gps_clock in [
+            SensorClock {
+                offset: -0.3,
+                drift: 0.0,
+            },
+            SensorClock {
+                offset: -0.2,
+                drift: -0.001,
+            },
+        ] {
+            let (filter, sensors, truth) = run(Setup {
+                gps_clock,
+                gps_latency: 0.2,
+                estimate_time_offset: true,
+                ..Setup::default()
+            });
+            let error = filter.calibration.difference(&sensors.calibration());
+            assert!(error.gps_time_offset.abs() < 0.02, "{:?}", error);
+            assert!((filter.state.x - truth.x).abs() < 0.1);
+            assert!((filter.state.y - truth.y).abs() < 0.1);
+
+            // trusting the stamps leaves the fixes misplaced along the track
+            let (unaware, _, _) = run(Setup {
+                gps_clock,
+                gps_latency: 0.2,
+                ..Setup::default()
+            });
+            assert!((unaware.state.x - truth.x).abs() > (filter.state.x - truth.x).abs());
+        }
+    }
+}
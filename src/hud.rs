@@ -0,0 +1,217 @@
+use std::fmt;
+
+use crate::interactive::Playback;
+use crate::kalman_filter::Nis;
+use crate::state::{wrap_angle, CarState};
+use crate::world::{Vehicle, World};
+
+// weight of the newest frame in the smoothed frame rate
+const FRAME_RATE_SMOOTHING: f64 = 0.1;
+
+// frames per second, smoothed so the display does not flicker
+#[derive(Debug, Clone, Default)]
+pub struct FrameRate {
+    frames_per_second: Option<f64>,
+}
+
+impl FrameRate {
+    // records a frame drawn `elapsed` seconds after the previous one
+    pub fn tick(&mut self, elapsed: f64) {
+        if elapsed <= 0.0 {
+            return;
+        }
+        let rate = 1.0 / elapsed;
+        self.frames_per_second = Some(match self.frames_per_second {
+            Some(smoothed) => smoothed + FRAME_RATE_SMOOTHING * (rate - smoothed),
+            None => rate,
+        });
+    }
+
+    pub fn frames_per_second(&self) -> Option<f64> {
+        self.frames_per_second
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GpsHealth {
+    // seconds since the latest fix was taken
+    Ok(f64),
+    Outage,
+    // no fix taken yet
+    Waiting,
+}
+
+impl fmt::Display for GpsHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpsHealth::Ok(age) => write!(f, "ok, last fix {:.1} s ago", age),
+            GpsHealth::Outage => write!(f, "OUTAGE"),
+            GpsHealth::Waiting => write!(f, "waiting for a fix"),
+        }
+    }
+}
+
+// what the heads-up display shows about one vehicle
+#[derive(Debug, Clone)]
+pub struct VehicleStatus {
+    pub id: usize,
+    pub estimator: &'static str,
+    pub truth: CarState,
+    pub estimate: CarState,
+    // distance and heading difference between the estimate and the truth
+    pub position_error: f64,
+    pub heading_error: f64,
+    pub nis: Nis,
+    pub gps: GpsHealth,
+    pub stationary: bool,
+    pub manual: bool,
+}
+
+impl VehicleStatus {
+    pub fn new(vehicle: &Vehicle, time: f64) -> Self {
+        let (truth, estimate) = (vehicle.car.state, vehicle.estimator.state());
+        let sensors = &vehicle.sensors;
+        let gps = if sensors.gps_in_outage() {
+            GpsHealth::Outage
+        } else {
+            match sensors.gps_timing.last_sample() {
+                Some(taken) => GpsHealth::Ok(time - taken),
+                None => GpsHealth::Waiting,
+            }
+        };
+        Self {
+            id: vehicle.id,
+            estimator: vehicle.estimator.name(),
+            truth,
+            estimate,
+            position_error: (estimate.x - truth.x).hypot(estimate.y - truth.y),
+            heading_error: wrap_angle(estimate.yaw - truth.yaw),
+            nis: vehicle.estimator.nis(),
+            gps,
+            stationary: sensors.stationary.is_stationary(),
+            manual: vehicle.manual_control.is_some(),
+        }
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let state = |state: &CarState| {
+            format!(
+                "x {:8.2} y {:8.2} yaw {:7.1} deg v {:6.2} m/s",
+                state.x,
+                state.y,
+                state.yaw.to_degrees(),
+                state.velocity
+            )
+        };
+        let nis = |value: Option<f64>| value.map_or("-".to_string(), |nis| format!("{:.2}", nis));
+        let mut title = format!("vehicle {} ({})", self.id, self.estimator);
+        if self.manual {
+            title.push_str(", driven manually");
+        }
+        if self.stationary {
+            title.push_str(", stationary");
+        }
+        vec![
+            title,
+            format!("  truth     {}", state(&self.truth)),
+            format!("  estimate  {}", state(&self.estimate)),
+            format!(
+                "  error {:.2} m {:.1} deg, NIS gps {} odometry {} imu {}",
+                self.position_error,
+                self.heading_error.to_degrees(),
+                nis(self.nis.gps),
+                nis(self.nis.odometry),
+                nis(self.nis.imu)
+            ),
+            format!("  gps {}", self.gps),
+        ]
+    }
+}
+
+// the lines of the heads-up display: the clock and playback state, then every vehicle
+pub fn hud_lines(
+    world: &World,
+    step: usize,
+    steps: usize,
+    playback: &Playback,
+    frame_rate: &FrameRate,
+    grid_spacing: f64,
+) -> Vec<String> {
+    let frames = frame_rate
+        .frames_per_second()
+        .map_or("-".to_string(), |rate| format!("{:.0}", rate));
+    let mut header = format!(
+        "t {:.2} s, step {}/{}, speed x{}, {} fps, grid {} m",
+        world.time, step, steps, playback.speed, frames, grid_spacing
+    );
+    if playback.paused {
+        header.push_str(", PAUSED");
+    }
+    let mut lines = vec![header];
+    for vehicle in world.vehicles.iter() {
+        lines.extend(VehicleStatus::new(vehicle, world.time).lines());
+    }
+    for collision in world.collisions.iter() {
+        lines.push(format!(
+            "COLLISION {:?} <-> {:?}, depth {:.2} m",
+            collision.first, collision.second, collision.contact.depth
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::Car;
+    use crate::controller::ConstantControl;
+    use crate::sensor_measurement::SensorTiming;
+
+    #[test]
+    fn test_frame_rate_is_smoothed() {
+        let mut frame_rate = FrameRate::default();
+        assert_eq!(frame_rate.frames_per_second(), None);
+        frame_rate.tick(1.0 / 60.0);
+        assert!((frame_rate.frames_per_second().unwrap() - 60.0).abs() < 1e-9);
+        // a single slow frame only moves it a little
+        frame_rate.tick(1.0 / 10.0);
+        assert!((frame_rate.frames_per_second().unwrap() - 55.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_vehicle_status_reports_errors_and_gps_outage() {
+        let mut world = World::new(0.1);
+        let car = Car::new(0.0, 0.0, 0.0, 20.0, 40.0, 5.0, 2.0, 0.5, 0.1, None);
+        world.add_vehicle(car, Box::new(ConstantControl::new(0.0, 0.0)));
+        world.vehicles[0].sensors.gps_timing = SensorTiming::new(None, 0.0, vec![(0.55, 1.0)]);
+        for _ in 0..8 {
+            world.step();
+        }
+        let status = VehicleStatus::new(&world.vehicles[0], world.time);
+        assert_eq!(status.gps, GpsHealth::Outage);
+        assert!(status.nis.gps.is_some());
+        let (truth, estimate) = (status.truth, status.estimate);
+        assert!(
+            (status.position_error - (estimate.x - truth.x).hypot(estimate.y - truth.y)).abs()
+                < 1e-12
+        );
+        assert!(status.lines().iter().any(|line| line.contains("OUTAGE")));
+
+        for _ in 0..5 {
+            world.step();
+        }
+        let status = VehicleStatus::new(&world.vehicles[0], world.time);
+        assert!(matches!(status.gps, GpsHealth::Ok(age) if age.abs() < 1e-9));
+
+        let lines = hud_lines(
+            &world,
+            13,
+            100,
+            &Playback::new(),
+            &FrameRate::default(),
+            10.0,
+        );
+        assert_eq!(lines.len(), 1 + status.lines().len());
+        assert!(lines[0].contains("step 13/100"));
+    }
+}
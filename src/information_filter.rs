@@ -0,0 +1,489 @@
+use std::ops::{Add, AddAssign};
+
+use nalgebra::{DMatrix, DVector, Matrix2, Matrix3, Matrix4, MatrixXx4, Vector3, Vector4};
+
+use crate::car::KinematicBicycleModel;
+use crate::kalman_filter::{
+    gps_measurement_jacobian, is_positive_definite, Estimator, EstimatorKind, KalmanFilter, Nis,
+};
+use crate::sensors::GPS::XYZValues;
+use crate::state::{wrap_angle, CarState};
+
+// a Gaussian in information form: matrix Y = P^-1 and vector y = P^-1 x. A measurement
+// contributes H^T R^-1 H and H^T R^-1 z' (z' the measurement linearised about the prior), so
+// independent measurements fuse by plain addition in any order
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Information {
+    pub matrix: Matrix4<f64>,
+    pub vector: Vector4<f64>,
+}
+
+impl Information {
+    pub fn zero() -> Self {
+        Self {
+            matrix: Matrix4::zeros(),
+            vector: Vector4::zeros(),
+        }
+    }
+
+    pub fn from_estimate(state: &Vector4<f64>, covariance: &Matrix4<f64>) -> Option<Self> {
+        let matrix = covariance.try_inverse()?;
+        Some(Self {
+            matrix,
+            vector: matrix * state,
+        })
+    }
+
+    // the mean and covariance, if the information matrix is invertible
+    pub fn estimate(&self) -> Option<(Vector4<f64>, Matrix4<f64>)> {
+        let covariance = self.matrix.try_inverse()?;
+        Some((covariance * self.vector, covariance))
+    }
+
+    pub fn scale(&self, weight: f64) -> Self {
+        Self {
+            matrix: self.matrix * weight,
+            vector: self.vector * weight,
+        }
+    }
+}
+
+impl Add for Information {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self {
+            matrix: self.matrix + other.matrix,
+            vector: self.vector + other.vector,
+        }
+    }
+}
+
+impl AddAssign for Information {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl std::iter::Sum for Information {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |total, item| total + item)
+    }
+}
+
+// fuses two estimates whose cross-correlation is unknown, e.g. ones that may share the same
+// measurements: Y = w Ya + (1 - w) Yb with w in [0, 1] chosen to minimise the trace of the
+// fused covariance. The result is consistent for any correlation, unlike adding the two
+pub fn covariance_intersection(a: &Information, b: &Information) -> (Information, f64) {
+    let fused = |weight: f64| a.scale(weight) + b.scale(1.0 - weight);
+    let cost = |weight: f64| {
+        fused(weight)
+            .matrix
+            .try_inverse()
+            .map_or(f64::INFINITY, |covariance| covariance.trace())
+    };
+    // the trace is convex in the weight, so a golden-section search finds the minimum
+    let ratio = 0.5 * (5.0_f64.sqrt() - 1.0);
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..60 {
+        let left = high - ratio * (high - low);
+        let right = low + ratio * (high - low);
+        if cost(left) <= cost(right) {
+            high = right;
+        } else {
+            low = left;
+        }
+    }
+    let weight = [0.0, 0.5 * (low + high), 1.0]
+        .into_iter()
+        .min_by(|x, y| cost(*x).total_cmp(&cost(*y)))
+        .unwrap();
+    (fused(weight), weight)
+}
+
+// variance added to every state when a singular covariance has to be inverted
+const SINGULAR_COVARIANCE_JITTER: f64 = 1e-9;
+
+// EKF in information form: predictions go through the covariance form, while measurement
+// updates only add information, which makes batches of simultaneous fixes cheap to fuse
+pub struct InformationFilter {
+    pub state: CarState,
+    pub information: Information,
+    model: KinematicBicycleModel,
+    pub process_noise: Matrix4<f64>,
+    pub control_noise: Matrix2<f64>,
+    pub gps_noise: Matrix3<f64>,
+    pub covariance_faults: Vec<f64>,
+    pub nis: Nis,
+}
+
+impl InformationFilter {
+    // takes over the state, covariance and noise settings of an EKF. A singular covariance,
+    // certain along some direction, has no information form: it is reported as a covariance
+    // fault and inverted with a little variance added, which leaves that direction very precise
+    pub fn from_filter(filter: &KalmanFilter) -> Self {
+        let state = filter.state.to_vector4();
+        let mut covariance_faults = Vec::new();
+        let information = Information::from_estimate(&state, &filter.covariance)
+            .or_else(|| {
+                covariance_faults.push(filter.state.time_stamp);
+                let jitter = Matrix4::identity() * SINGULAR_COVARIANCE_JITTER;
+                Information::from_estimate(&state, &(filter.covariance + jitter))
+            })
+            .unwrap_or_else(Information::zero);
+        Self {
+            state: filter.state,
+            information,
+            model: filter.model.clone(),
+            process_noise: filter.process_noise,
+            control_noise: filter.control_noise,
+            gps_noise: filter.gps_noise,
+            covariance_faults,
+            nis: Nis::default(),
+        }
+    }
+
+    pub fn covariance(&self) -> Matrix4<f64> {
+        self.information
+            .matrix
+            .try_inverse()
+            .unwrap_or_else(|| Matrix4::from_element(f64::NAN))
+    }
+
+    pub fn predict(&mut self, acceleration: f64, steering_angle: f64) {
+        let s = &self.state;
+        let (f, _) = self.model._autodiff_jacobians(
+            s.x,
+            s.y,
+            s.yaw,
+            s.velocity,
+            acceleration,
+            steering_angle,
+        );
+        let control_noise =
+            self.model
+                ._control_process_noise(s.velocity, steering_angle, &self.control_noise);
+        let predicted =
+            self.model
+                ._update(s.x, s.y, s.yaw, s.velocity, acceleration, steering_angle);
+        let covariance = f * self.covariance() * f.transpose() + control_noise + self.process_noise;
+        self.state.x = predicted.x;
+        self.state.y = predicted.y;
+        self.state.yaw = predicted.yaw;
+        self.state.velocity = predicted.velocity;
+        self.state.time_stamp += self.model.dt;
+        match Information::from_estimate(&self.state.to_vector4(), &covariance) {
+            Some(information) => self.information = information,
+            None => self.covariance_faults.push(self.state.time_stamp),
+        }
+    }
+
+    // the information a GPS fix adds, linearised about the current estimate
+    pub fn gps_contribution(&self, measurement: &XYZValues) -> Information {
+        let prior = self.state.to_vector4();
+        let (expected, h) = gps_measurement_jacobian(&prior);
+        let mut innovation = Vector3::new(measurement.x, measurement.y, measurement.z) - expected;
+        innovation[2] = wrap_angle(innovation[2]);
+        let Some(noise_inv) = self.gps_noise.try_inverse() else {
+            return Information::zero();
+        };
+        let ht_r_inv = h.transpose() * noise_inv;
+        Information {
+            matrix: ht_r_inv * h,
+            vector: ht_r_inv * (innovation + h * prior),
+        }
+    }
+
+    // normalized innovation squared of GPS fixes against the current estimate, which needs the
+    // covariance form. The fixes of a batch share the prior error, so their innovations are
+    // stacked and normalised together; that has three degrees of freedom per fix
+    pub fn gps_nis(&self, measurements: &[XYZValues]) -> Option<f64> {
+        if measurements.is_empty() {
+            return None;
+        }
+        let rows = 3 * measurements.len();
+        let (expected, h) = gps_measurement_jacobian(&self.state.to_vector4());
+        let mut innovation = DVector::zeros(rows);
+        let mut stacked_h = MatrixXx4::zeros(rows);
+        let mut noise = DMatrix::zeros(rows, rows);
+        for (i, measurement) in measurements.iter().enumerate() {
+            let mut residual = Vector3::new(measurement.x, measurement.y, measurement.z) - expected;
+            residual[2] = wrap_angle(residual[2]);
+            innovation.fixed_rows_mut::<3>(3 * i).copy_from(&residual);
+            stacked_h.fixed_rows_mut::<3>(3 * i).copy_from(&h);
+            noise
+                .fixed_view_mut::<3, 3>(3 * i, 3 * i)
+                .copy_from(&self.gps_noise);
+        }
+        let s = &stacked_h * self.covariance() * stacked_h.transpose() + noise;
+        let s_inv = s.try_inverse()?;
+        Some(innovation.dot(&(s_inv * &innovation)))
+    }
+
+    // adds the contributions of independent measurements taken at the current time
+    pub fn fuse(&mut self, contributions: &[Information]) {
+        if contributions.is_empty() {
+            return;
+        }
+        self.information += contributions.iter().copied().sum();
+        self.recover_state();
+    }
+
+    // replaces the estimate with the covariance intersection of it and `other`, for when the
+    // two may share information and simply adding them would count it twice
+    pub fn intersect(&mut self, other: &Information) -> f64 {
+        let (fused, weight) = covariance_intersection(&self.information, other);
+        self.information = fused;
+        self.recover_state();
+        weight
+    }
+
+    fn recover_state(&mut self) {
+        let Some((mean, covariance)) = self.information.estimate() else {
+            self.covariance_faults.push(self.state.time_stamp);
+            return;
+        };
+        self.state.x = mean[0];
+        self.state.y = mean[1];
+        self.state.yaw = mean[2];
+        self.state.velocity = mean[3];
+        if !is_positive_definite(&covariance) {
+            self.covariance_faults.push(self.state.time_stamp);
+        }
+    }
+}
+
+impl Estimator for InformationFilter {
+    fn name(&self) -> &'static str {
+        EstimatorKind::Information.name()
+    }
+    fn predict(&mut self, acceleration: f64, steering_angle: f64) {
+        InformationFilter::predict(self, acceleration, steering_angle);
+    }
+    fn update_gps(&mut self, measurement: &XYZValues) {
+        self.nis.gps = self.gps_nis(std::slice::from_ref(measurement));
+        self.fuse(&[self.gps_contribution(measurement)]);
+    }
+    fn update_gps_fixes(&mut self, measurements: &[XYZValues]) {
+        if !measurements.is_empty() {
+            self.nis.gps = self.gps_nis(measurements);
+        }
+        let contributions: Vec<Information> = measurements
+            .iter()
+            .map(|measurement| self.gps_contribution(measurement))
+            .collect();
+        self.fuse(&contributions);
+    }
+    fn state(&self) -> CarState {
+        self.state
+    }
+    fn covariance(&self) -> Matrix4<f64> {
+        InformationFilter::covariance(self)
+    }
+    fn covariance_faults(&self) -> &[f64] {
+        &self.covariance_faults
+    }
+    fn nis(&self) -> Nis {
+        self.nis
+    }
+}
+
+// one filter of a decentralised network: it fuses its own sensors locally and shares what they
+// contributed, so every node ends up with the estimate a central filter would have
+pub struct FusionNode {
+    pub filter: InformationFilter,
+    // local contributions not yet sent to the other nodes
+    outbox: Vec<Information>,
+}
+
+impl FusionNode {
+    pub fn new(filter: InformationFilter) -> Self {
+        Self {
+            filter,
+            outbox: Vec::new(),
+        }
+    }
+
+    pub fn predict(&mut self, acceleration: f64, steering_angle: f64) {
+        self.filter.predict(acceleration, steering_angle);
+    }
+
+    pub fn observe_gps(&mut self, measurement: &XYZValues) {
+        let contribution = self.filter.gps_contribution(measurement);
+        self.filter.fuse(&[contribution]);
+        self.outbox.push(contribution);
+    }
+}
+
+// every node receives the contributions the others gathered since the last exchange; the
+// nodes must share the same prediction steps for the sum to stay exact
+pub fn exchange_information(nodes: &mut [FusionNode]) {
+    let shared: Vec<Information> = nodes
+        .iter()
+        .map(|node| node.outbox.iter().copied().sum())
+        .collect();
+    for (index, node) in nodes.iter_mut().enumerate() {
+        let received: Vec<Information> = shared
+            .iter()
+            .enumerate()
+            .filter(|(other, _)| *other != index)
+            .map(|(_, information)| *information)
+            .collect();
+        node.filter.fuse(&received);
+        node.outbox.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensors::GPS::fix;
+
+    fn initial() -> CarState {
+        CarState {
+            velocity: 5.0,
+            ..CarState::new()
+        }
+    }
+
+    #[test]
+    fn test_matches_ekf_and_fuses_batches_additively() {
+        let mut ekf = KalmanFilter::new(&initial(), 2.0, 0.5, 0.1);
+        let mut information = InformationFilter::from_filter(&ekf);
+        let mut batched = InformationFilter::from_filter(&ekf);
+        for k in 0..50 {
+            let fixes = [
+                fix(0.5 * k as f64, 0.1 * k as f64, 0.02),
+                fix(0.5 * k as f64 + 0.1, 0.1 * k as f64 - 0.1, 0.03),
+            ];
+            ekf.predict(0.1, 0.05);
+            information.predict(0.1, 0.05);
+            batched.predict(0.1, 0.05);
+            ekf.update_gps(&fixes[0]);
+            information.update_gps(&fixes[0]);
+            // linearised at the same prior, the order of the contributions does not matter
+            let contributions = fixes.map(|measurement| batched.gps_contribution(&measurement));
+            batched.fuse(&[contributions[1], contributions[0]]);
+            let mut sequential = InformationFilter::from_filter(&ekf);
+            sequential.fuse(&[sequential.gps_contribution(&fixes[1])]);
+            ekf.update_gps(&fixes[1]);
+            information.update_gps(&fixes[1]);
+            assert!((sequential.covariance() - ekf.covariance).amax() < 1e-9);
+        }
+        assert!((information.covariance() - ekf.covariance).amax() < 1e-9);
+        assert!((information.state.to_vector4() - ekf.state.to_vector4()).amax() < 1e-9);
+        assert!((batched.covariance() - ekf.covariance).amax() < 1e-9);
+        assert!((batched.state.to_vector4() - ekf.state.to_vector4()).amax() < 1e-9);
+        assert!(information.covariance_faults.is_empty());
+    }
+
+    #[test]
+    fn test_batch_nis_covers_every_fix() {
+        let mut ekf = KalmanFilter::new(&initial(), 2.0, 0.5, 0.1);
+        let mut batched = InformationFilter::from_filter(&ekf);
+        for k in 0..20 {
+            let fixes = [
+                fix(0.5 * k as f64 + 0.3, 0.1 * k as f64, 0.02),
+                fix(0.5 * k as f64 - 0.2, 0.1 * k as f64 + 0.4, -0.03),
+            ];
+            ekf.predict(0.1, 0.05);
+            batched.predict(0.1, 0.05);
+            // the measurement is linear, so the joint NIS is what the fixes add one after another
+            let mut sequential = 0.0;
+            for measurement in fixes.iter() {
+                ekf.update_gps(measurement);
+                sequential += ekf.nis.gps.unwrap();
+            }
+            batched.update_gps_fixes(&fixes);
+            let nis = batched.nis.gps.unwrap();
+            assert!(
+                (nis - sequential).abs() < 1e-6 * sequential.max(1.0),
+                "{} {}",
+                nis,
+                sequential
+            );
+        }
+    }
+
+    #[test]
+    fn test_singular_initial_covariance_is_reported_not_fatal() {
+        let mut ekf = KalmanFilter::new(&initial(), 2.0, 0.5, 0.1);
+        // the heading is known exactly
+        ekf.covariance[(2, 2)] = 0.0;
+        let mut information = InformationFilter::from_filter(&ekf);
+        assert_eq!(information.covariance_faults.len(), 1);
+        for k in 0..20 {
+            information.predict(0.0, 0.0);
+            information.update_gps(&fix(0.5 * k as f64, 0.0, 0.0));
+        }
+        assert!(information
+            .state
+            .to_vector4()
+            .iter()
+            .all(|value| value.is_finite()));
+        assert!(information
+            .covariance()
+            .iter()
+            .all(|value| value.is_finite()));
+    }
+
+    #[test]
+    fn test_decentralised_nodes_match_central_filter() {
+        let ekf = KalmanFilter::new(&initial(), 2.0, 0.5, 0.1);
+        let mut central = InformationFilter::from_filter(&ekf);
+        let mut nodes: Vec<FusionNode> = (0..3)
+            .map(|_| FusionNode::new(InformationFilter::from_filter(&ekf)))
+            .collect();
+        for k in 0..30 {
+            central.predict(0.2, 0.1);
+            let mut contributions = Vec::new();
+            for (index, node) in nodes.iter_mut().enumerate() {
+                node.predict(0.2, 0.1);
+                let measurement = fix(0.5 * k as f64, 0.2 * index as f64, 0.01 * k as f64);
+                contributions.push(central.gps_contribution(&measurement));
+                node.observe_gps(&measurement);
+            }
+            central.fuse(&contributions);
+            exchange_information(&mut nodes);
+            for node in &nodes {
+                assert!(
+                    (node.filter.information.matrix - central.information.matrix).amax() < 1e-6
+                );
+                assert!(
+                    (node.filter.state.to_vector4() - central.state.to_vector4()).amax() < 1e-9
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_covariance_intersection_does_not_double_count() {
+        let a = Information::from_estimate(
+            &Vector4::new(1.0, 2.0, 0.1, 5.0),
+            &Matrix4::from_diagonal(&Vector4::new(1.0, 4.0, 0.1, 1.0)),
+        )
+        .unwrap();
+        let b = Information::from_estimate(
+            &Vector4::new(1.5, 1.0, 0.2, 4.0),
+            &Matrix4::from_diagonal(&Vector4::new(4.0, 1.0, 0.1, 1.0)),
+        )
+        .unwrap();
+
+        // fusing an estimate with itself adds nothing, where the sum would halve the covariance
+        let (same, _) = covariance_intersection(&a, &a);
+        assert!((same.matrix - a.matrix).amax() < 1e-9);
+        assert!((same.vector - a.vector).amax() < 1e-9);
+
+        let (fused, weight) = covariance_intersection(&a, &b);
+        assert!(weight > 0.0 && weight < 1.0);
+        let (_, covariance) = fused.estimate().unwrap();
+        let (_, covariance_a) = a.estimate().unwrap();
+        let (_, covariance_b) = b.estimate().unwrap();
+        assert!(covariance.trace() < covariance_a.trace().min(covariance_b.trace()));
+        // never more confident than the independent fusion
+        let excess = (a + b).matrix - fused.matrix;
+        assert!(excess
+            .symmetric_eigenvalues()
+            .iter()
+            .all(|value| *value > -1e-9));
+    }
+}
@@ -0,0 +1,171 @@
+// state of the interactive window that does not depend on the windowing library: how fast the
+// simulation runs and what a person driving a car is asking for
+
+// limits of the simulation speed, in steps per rendered frame
+const MIN_SPEED: f64 = 1.0 / 16.0;
+const MAX_SPEED: f64 = 64.0;
+// commands while an arrow key is held: acceleration in m/s^2 and the fraction of the maximum
+// steering angle
+const TELEOP_ACCELERATION: f64 = 2.0;
+const TELEOP_STEERING: f64 = 0.5;
+
+// pause, single steps and speed of the simulation
+#[derive(Debug, Clone)]
+pub struct Playback {
+    pub paused: bool,
+    // steps per frame; below one, steps are taken only every few frames
+    pub speed: f64,
+    // fraction of a step carried over to the next frame
+    carry: f64,
+    step_requested: bool,
+}
+
+impl Playback {
+    pub fn new() -> Self {
+        Self {
+            paused: false,
+            speed: 1.0,
+            carry: 0.0,
+            step_requested: false,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    // pauses, then advances one step with the next frame
+    pub fn request_step(&mut self) {
+        self.paused = true;
+        self.step_requested = true;
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed * 2.0).min(MAX_SPEED);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = (self.speed / 2.0).max(MIN_SPEED);
+    }
+
+    // how many steps to simulate before drawing the next frame
+    pub fn frame_steps(&mut self) -> usize {
+        if self.paused {
+            return std::mem::take(&mut self.step_requested) as usize;
+        }
+        self.carry += self.speed;
+        let steps = self.carry.floor();
+        self.carry -= steps;
+        steps as usize
+    }
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the mouse cursor and whether the button that pans the view is held down
+#[derive(Debug, Clone, Default)]
+pub struct Drag {
+    pub cursor: (f64, f64),
+    pub held: bool,
+}
+
+impl Drag {
+    pub fn press(&mut self) {
+        self.held = true;
+    }
+
+    pub fn release(&mut self) {
+        self.held = false;
+    }
+
+    // moves the cursor to `position`; returns how far it moved while the button is held
+    pub fn move_to(&mut self, position: (f64, f64)) -> Option<(f64, f64)> {
+        let moved = (position.0 - self.cursor.0, position.1 - self.cursor.1);
+        self.cursor = position;
+        self.held.then_some(moved)
+    }
+}
+
+// manual driving of one vehicle from the arrow keys that are held down
+#[derive(Debug, Clone, Default)]
+pub struct Teleop {
+    // the vehicle being driven, None while every vehicle follows its controller
+    pub vehicle: Option<usize>,
+    pub throttle: bool,
+    pub brake: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl Teleop {
+    // (acceleration, steering_angle) for a car that can steer up to `max_steer`
+    pub fn command(&self, max_steer: f64) -> (f64, f64) {
+        let axis = |positive: bool, negative: bool| positive as i32 as f64 - negative as i32 as f64;
+        (
+            TELEOP_ACCELERATION * axis(self.throttle, self.brake),
+            TELEOP_STEERING * max_steer * axis(self.left, self.right),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playback_speed_pause_and_single_step() {
+        let mut playback = Playback::new();
+        assert_eq!(playback.frame_steps(), 1);
+        playback.faster();
+        assert_eq!(playback.frame_steps(), 2);
+        playback.slower();
+        playback.slower();
+        playback.slower();
+        // a quarter of a step per frame
+        let steps: Vec<usize> = (0..8).map(|_| playback.frame_steps()).collect();
+        assert_eq!(steps, [0, 0, 0, 1, 0, 0, 0, 1]);
+
+        playback.toggle_pause();
+        assert_eq!(playback.frame_steps(), 0);
+        playback.request_step();
+        assert_eq!(playback.frame_steps(), 1);
+        assert_eq!(playback.frame_steps(), 0);
+        assert!(playback.paused);
+
+        for _ in 0..20 {
+            playback.faster();
+        }
+        assert_eq!(playback.speed, MAX_SPEED);
+    }
+
+    #[test]
+    fn test_drag_pans_only_while_held() {
+        let mut drag = Drag::default();
+        assert_eq!(drag.move_to((10.0, 10.0)), None);
+        drag.press();
+        assert_eq!(drag.move_to((15.0, 7.0)), Some((5.0, -3.0)));
+        drag.release();
+        assert_eq!(drag.move_to((40.0, 40.0)), None);
+        assert_eq!(drag.cursor, (40.0, 40.0));
+    }
+
+    #[test]
+    fn test_teleop_commands_from_held_keys() {
+        let mut teleop = Teleop::default();
+        assert_eq!(teleop.command(0.5), (0.0, 0.0));
+        teleop.throttle = true;
+        teleop.left = true;
+        assert_eq!(teleop.command(0.5), (TELEOP_ACCELERATION, 0.25));
+        // opposite keys cancel out
+        teleop.brake = true;
+        teleop.right = true;
+        assert_eq!(teleop.command(0.5), (0.0, 0.0));
+        teleop.throttle = false;
+        teleop.left = false;
+        assert_eq!(teleop.command(0.5), (-TELEOP_ACCELERATION, -0.25));
+    }
+}
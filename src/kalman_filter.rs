@@ -1,15 +1,556 @@
-use nalgebra::Matrix4;
+use nalgebra::{Matrix2, Matrix3, Matrix3x4, Matrix4, Vector2, Vector3, Vector4};
 
-use crate::car::{Car, KinematicBicycleModel};
-use crate::state::{CarState, Rectangular};
+use crate::autodiff::{jacobian, Real};
+use crate::car::KinematicBicycleModel;
+use crate::error_state_filter::{
+    ErrorStateFilter, DEFAULT_CALIBRATION_PRIOR, DEFAULT_TIME_OFFSET_PRIOR,
+};
+use crate::information_filter::InformationFilter;
+use crate::sensor_measurement::Calibration;
+use crate::sensors::GPS::XYZValues;
+use crate::sensors::IMU::{ImuBiases, IMU9Axis};
+use crate::square_root_filter::SquareRootFilter;
+use crate::state::{wrap_angle, CarColor, CarState, Rectangular};
+
+// common interface of the state estimators a vehicle can run
+pub trait Estimator {
+    fn name(&self) -> &'static str;
+    fn predict(&mut self, acceleration: f64, steering_angle: f64);
+    fn update_gps(&mut self, measurement: &XYZValues);
+    // fixes that became available in the same step; applied one after another unless the
+    // estimator can fuse them jointly
+    fn update_gps_fixes(&mut self, measurements: &[XYZValues]) {
+        for measurement in measurements {
+            self.update_gps(measurement);
+        }
+    }
+    // estimators driven by inertial data propagate with each IMU sample
+    fn update_imu(&mut self, _sample: &IMU9Axis) {}
+    // an IMU sample taken while the vehicle stood still; estimators that cannot make use of that
+    // take it as any other sample
+    fn update_stationary(&mut self, sample: &IMU9Axis) {
+        self.update_imu(sample);
+    }
+    // a measured forward speed, e.g. from the wheel encoder
+    fn update_odometry(&mut self, _speed: f64) {}
+    fn state(&self) -> CarState;
+    fn covariance(&self) -> Matrix4<f64>;
+    // the estimate after every step revised with all measurements up to now, for estimators
+    // that keep what a smoother needs
+    fn smoothed_trajectory(&self) -> Option<Vec<CarState>> {
+        None
+    }
+    // the estimated IMU biases, for estimators that carry them as states
+    fn imu_biases(&self) -> Option<ImuBiases> {
+        None
+    }
+    // the estimated sensor calibration, for estimators that carry it as states
+    fn calibration(&self) -> Option<Calibration> {
+        None
+    }
+    // times at which the covariance was found not to be positive definite
+    fn covariance_faults(&self) -> &[f64] {
+        &[]
+    }
+    // how surprising the latest measurement of each sensor was, for estimators that form
+    // innovations
+    fn nis(&self) -> Nis {
+        Nis::default()
+    }
+}
+
+// normalized innovation squared, y^T S^-1 y, of the latest update with each sensor; a consistent
+// filter averages the dimension of the measurement, 3 for GPS and 1 for odometry and the IMU
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Nis {
+    pub gps: Option<f64>,
+    pub odometry: Option<f64>,
+    pub imu: Option<f64>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EstimatorKind {
+    Ekf,
+    SquareRoot,
+    Information,
+    ErrorState,
+    DeadReckoning,
+}
+
+// which estimator to run and how to tune it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EstimatorConfig {
+    pub kind: EstimatorKind,
+    // diagonal of the process noise for (x, y, yaw, velocity)
+    pub process_noise: [f64; 4],
+    // variances of the applied (acceleration, steering_angle) around the commanded ones
+    pub control_noise: [f64; 2],
+    // diagonal of the GPS noise for (x, y, yaw)
+    pub gps_noise: [f64; 3],
+    // EKF only: use the Joseph form for the covariance update
+    pub joseph_form: bool,
+    // EKF only: average the covariance with its transpose after every step
+    pub enforce_symmetry: bool,
+    // error-state EKF only: variances of the IMU (forward acceleration, yaw rate) readings
+    pub imu_noise: [f64; 2],
+    // error-state EKF only: random walk variances per second of the (accelerometer, gyro) biases
+    pub imu_bias_walk: [f64; 2],
+    // error-state EKF only: variance of the wheel encoder speed
+    pub odometry_noise: f64,
+    // error-state EKF only: augment the state with the IMU biases, or assume there are none
+    pub estimate_imu_biases: bool,
+    // error-state EKF only: augment the state with the encoder scale, GPS lever arm and IMU
+    // mounting yaw, or trust their nominal values
+    pub estimate_calibration: bool,
+    // error-state EKF only: augment the state with the offset and drift of the GPS clock
+    // relative to the IMU clock, or trust the stamps
+    pub estimate_time_offset: bool,
+    // error-state EKF only: correct the heading and the biases with the IMU samples of the first
+    // standstill
+    pub static_alignment: bool,
+}
+
+impl Default for EstimatorConfig {
+    fn default() -> Self {
+        Self {
+            kind: EstimatorKind::Ekf,
+            process_noise: [0.01, 0.01, 0.001, 0.1],
+            control_noise: [0.01, 0.0001],
+            gps_noise: [0.01, 0.01, 0.01],
+            joseph_form: false,
+            enforce_symmetry: false,
+            imu_noise: [0.01, 0.001],
+            imu_bias_walk: [1e-5, 1e-6],
+            odometry_noise: 0.01,
+            estimate_imu_biases: true,
+            estimate_calibration: false,
+            estimate_time_offset: false,
+            static_alignment: true,
+        }
+    }
+}
+
+impl EstimatorConfig {
+    pub fn build(
+        &self,
+        initial: &CarState,
+        wheelbase: f64,
+        max_steer: f64,
+        delta_time: f64,
+    ) -> Box<dyn Estimator> {
+        let mut filter = KalmanFilter::new(initial, wheelbase, max_steer, delta_time);
+        filter.process_noise = Matrix4::from_diagonal(&Vector4::from(self.process_noise));
+        filter.control_noise = Matrix2::from_diagonal(&Vector2::from(self.control_noise));
+        filter.gps_noise = Matrix3::from_diagonal(&Vector3::from(self.gps_noise));
+        filter.joseph_form = self.joseph_form;
+        filter.enforce_symmetry = self.enforce_symmetry;
+        match self.kind {
+            EstimatorKind::Ekf => Box::new(filter),
+            EstimatorKind::SquareRoot => Box::new(SquareRootFilter::from_filter(&filter)),
+            EstimatorKind::Information => Box::new(InformationFilter::from_filter(&filter)),
+            EstimatorKind::ErrorState => {
+                let mut error_state = ErrorStateFilter::from_filter(&filter);
+                error_state.imu_noise = self.imu_noise;
+                error_state.imu_bias_walk = self.imu_bias_walk;
+                error_state.odometry_noise = self.odometry_noise;
+                if !self.estimate_imu_biases {
+                    error_state.fix_biases();
+                }
+                if self.estimate_calibration {
+                    error_state.estimate_calibration(DEFAULT_CALIBRATION_PRIOR);
+                }
+                if self.estimate_time_offset {
+                    error_state.estimate_time_offset(DEFAULT_TIME_OFFSET_PRIOR);
+                }
+                if !self.static_alignment {
+                    error_state.skip_static_alignment();
+                }
+                Box::new(error_state)
+            }
+            EstimatorKind::DeadReckoning => Box::new(DeadReckoning { filter }),
+        }
+    }
+}
+
+impl EstimatorKind {
+    pub const ALL: [EstimatorKind; 5] = [
+        EstimatorKind::Ekf,
+        EstimatorKind::SquareRoot,
+        EstimatorKind::Information,
+        EstimatorKind::ErrorState,
+        EstimatorKind::DeadReckoning,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EstimatorKind::Ekf => "ekf",
+            EstimatorKind::SquareRoot => "sqrt-ekf",
+            EstimatorKind::Information => "information",
+            EstimatorKind::ErrorState => "es-ekf",
+            EstimatorKind::DeadReckoning => "dead-reckoning",
+        }
+    }
+}
+
+impl std::str::FromStr for EstimatorKind {
+    type Err = String;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match EstimatorKind::ALL.iter().find(|kind| kind.name() == name) {
+            Some(kind) => Ok(*kind),
+            None => Err(format!(
+                "unknown estimator '{}', expected one of: {}",
+                name,
+                EstimatorKind::ALL.map(|kind| kind.name()).join(", ")
+            )),
+        }
+    }
+}
+
+// the GPS measures position and heading directly: h(x, y, yaw, velocity) = (x, y, yaw)
+pub fn gps_measurement<T: Real>(state: [T; 4]) -> [T; 3] {
+    [state[0], state[1], state[2]]
+}
+
+// expected GPS reading and its Jacobian at `state`, by automatic differentiation
+pub fn gps_measurement_jacobian(state: &Vector4<f64>) -> (Vector3<f64>, Matrix3x4<f64>) {
+    jacobian(gps_measurement, state)
+}
+
+// whether a Cholesky factorisation exists, which needs a symmetric positive definite matrix
+pub fn is_positive_definite(matrix: &Matrix4<f64>) -> bool {
+    (matrix - matrix.transpose()).amax() <= 1e-9 * matrix.amax().max(1.0)
+        && matrix.cholesky().is_some()
+}
+
+// what the Rauch-Tung-Striebel smoother needs from one predict and the updates that followed it
+#[derive(Debug, Copy, Clone)]
+struct SmootherStep {
+    jacobian: Matrix4<f64>,
+    predicted: Vector4<f64>,
+    predicted_covariance: Matrix4<f64>,
+    filtered: Vector4<f64>,
+    filtered_covariance: Matrix4<f64>,
+    time_stamp: f64,
+}
 
 pub struct KalmanFilter {
     pub rectangular: Rectangular,
     pub state: CarState,
     pub covariance: Matrix4<f64>,
-    model: KinematicBicycleModel,
+    pub(crate) model: KinematicBicycleModel,
+    // (time, x, y, yaw, velocity, trace of the covariance) after every predict/update
     pub history: Vec<(f64, f64, f64, f64, f64, f64)>,
+    // added to the predicted covariance every step, on top of the control noise
+    pub process_noise: Matrix4<f64>,
+    // covariance of the (acceleration, steering_angle) inputs, mapped into the state by the
+    // control Jacobian
+    pub control_noise: Matrix2<f64>,
+    pub gps_noise: Matrix3<f64>,
+    // P = (I - KH) P (I - KH)^T + K R K^T instead of P = (I - KH) P, which stays symmetric
+    // and positive semi-definite under rounding
+    pub joseph_form: bool,
+    pub enforce_symmetry: bool,
+    pub covariance_faults: Vec<f64>,
+    pub nis: Nis,
+    smoother_steps: Vec<SmootherStep>,
 }
 
 // estimate the state of the car based on the sensor measurement
-impl KalmanFilter {}
+impl KalmanFilter {
+    pub fn new(initial: &CarState, wheelbase: f64, max_steer: f64, delta_time: f64) -> Self {
+        let mut state = *initial;
+        state.dt = delta_time;
+        Self {
+            rectangular: state.to_rectangular(Some(CarColor::Green)),
+            state,
+            covariance: Matrix4::from_diagonal(&Vector4::new(1.0, 1.0, 0.1, 1.0)),
+            model: KinematicBicycleModel::_new(wheelbase, max_steer, delta_time),
+            history: Vec::new(),
+            process_noise: Matrix4::from_diagonal(&Vector4::new(0.01, 0.01, 0.001, 0.1)),
+            control_noise: Matrix2::from_diagonal(&Vector2::new(0.01, 0.0001)),
+            gps_noise: Matrix3::from_diagonal(&Vector3::new(0.01, 0.01, 0.01)),
+            joseph_form: false,
+            enforce_symmetry: false,
+            covariance_faults: Vec::new(),
+            nis: Nis::default(),
+            smoother_steps: Vec::new(),
+        }
+    }
+
+    pub fn predict(&mut self, acceleration: f64, steering_angle: f64) {
+        let (jacobian, _) = self.model._autodiff_jacobians(
+            self.state.x,
+            self.state.y,
+            self.state.yaw,
+            self.state.velocity,
+            acceleration,
+            steering_angle,
+        );
+        let control_noise = self.model._control_process_noise(
+            self.state.velocity,
+            steering_angle,
+            &self.control_noise,
+        );
+        let predicted = self.model._update(
+            self.state.x,
+            self.state.y,
+            self.state.yaw,
+            self.state.velocity,
+            acceleration,
+            steering_angle,
+        );
+        self.state.x = predicted.x;
+        self.state.y = predicted.y;
+        self.state.yaw = predicted.yaw;
+        self.state.velocity = predicted.velocity;
+        self.state.time_stamp += self.model.dt;
+        self.covariance =
+            jacobian * self.covariance * jacobian.transpose() + control_noise + self.process_noise;
+        self.smoother_steps.push(SmootherStep {
+            jacobian,
+            predicted: self.state.to_vector4(),
+            predicted_covariance: self.covariance,
+            filtered: self.state.to_vector4(),
+            filtered_covariance: self.covariance,
+            time_stamp: self.state.time_stamp,
+        });
+        self.record();
+    }
+
+    // fixed-interval Rauch-Tung-Striebel smoothing of the estimates after every predict: going
+    // backwards, each is corrected by how much the smoothed next state differs from what was
+    // predicted from it
+    pub fn smooth(&self) -> Vec<CarState> {
+        let Some(last) = self.smoother_steps.last() else {
+            return Vec::new();
+        };
+        let mut smoothed = vec![last.filtered; self.smoother_steps.len()];
+        for k in (0..self.smoother_steps.len() - 1).rev() {
+            let (step, next) = (&self.smoother_steps[k], &self.smoother_steps[k + 1]);
+            let Some(predicted_inverse) = next.predicted_covariance.try_inverse() else {
+                smoothed[k] = step.filtered;
+                continue;
+            };
+            let gain = step.filtered_covariance * next.jacobian.transpose() * predicted_inverse;
+            let mut difference = smoothed[k + 1] - next.predicted;
+            difference[2] = wrap_angle(difference[2]);
+            smoothed[k] = step.filtered + gain * difference;
+        }
+        smoothed
+            .iter()
+            .zip(&self.smoother_steps)
+            .map(|(state, step)| CarState {
+                x: state[0],
+                y: state[1],
+                yaw: state[2],
+                velocity: state[3],
+                time_stamp: step.time_stamp,
+                ..self.state
+            })
+            .collect()
+    }
+
+    // correct the estimate with a GPS fix in local coordinates (x, y, and heading in z)
+    pub fn update_gps(&mut self, measurement: &XYZValues) {
+        let (expected, h) = gps_measurement_jacobian(&self.state.to_vector4());
+        let mut innovation = Vector3::new(measurement.x, measurement.y, measurement.z) - expected;
+        innovation[2] = wrap_angle(innovation[2]);
+        let s = h * self.covariance * h.transpose() + self.gps_noise;
+        let Some(s_inv) = s.try_inverse() else {
+            return;
+        };
+        self.nis.gps = Some((innovation.transpose() * s_inv * innovation)[0]);
+        let gain = self.covariance * h.transpose() * s_inv;
+        let correction = gain * innovation;
+        self.state.x += correction[0];
+        self.state.y += correction[1];
+        self.state.yaw += correction[2];
+        self.state.velocity += correction[3];
+        let i_kh = Matrix4::identity() - gain * h;
+        self.covariance = if self.joseph_form {
+            i_kh * self.covariance * i_kh.transpose() + gain * self.gps_noise * gain.transpose()
+        } else {
+            i_kh * self.covariance
+        };
+        self.record();
+    }
+
+    fn record(&mut self) {
+        if self.enforce_symmetry {
+            self.covariance = 0.5 * (self.covariance + self.covariance.transpose());
+        }
+        if let Some(step) = self.smoother_steps.last_mut() {
+            step.filtered = self.state.to_vector4();
+            step.filtered_covariance = self.covariance;
+        }
+        if !is_positive_definite(&self.covariance) {
+            self.covariance_faults.push(self.state.time_stamp);
+        }
+        self.rectangular = self.state.to_rectangular(Some(CarColor::Green));
+        self.history.push((
+            self.state.time_stamp,
+            self.state.x,
+            self.state.y,
+            self.state.yaw,
+            self.state.velocity,
+            self.covariance.trace(),
+        ));
+    }
+}
+
+impl Estimator for KalmanFilter {
+    fn name(&self) -> &'static str {
+        EstimatorKind::Ekf.name()
+    }
+    fn predict(&mut self, acceleration: f64, steering_angle: f64) {
+        KalmanFilter::predict(self, acceleration, steering_angle);
+    }
+    fn update_gps(&mut self, measurement: &XYZValues) {
+        KalmanFilter::update_gps(self, measurement);
+    }
+    fn state(&self) -> CarState {
+        self.state
+    }
+    fn covariance(&self) -> Matrix4<f64> {
+        self.covariance
+    }
+    fn smoothed_trajectory(&self) -> Option<Vec<CarState>> {
+        Some(self.smooth())
+    }
+    fn covariance_faults(&self) -> &[f64] {
+        &self.covariance_faults
+    }
+    fn nis(&self) -> Nis {
+        self.nis
+    }
+}
+
+// propagates the motion model only and ignores every measurement
+pub struct DeadReckoning {
+    pub filter: KalmanFilter,
+}
+
+impl Estimator for DeadReckoning {
+    fn name(&self) -> &'static str {
+        EstimatorKind::DeadReckoning.name()
+    }
+    fn predict(&mut self, acceleration: f64, steering_angle: f64) {
+        self.filter.predict(acceleration, steering_angle);
+    }
+    fn update_gps(&mut self, _measurement: &XYZValues) {}
+    fn state(&self) -> CarState {
+        self.filter.state
+    }
+    fn covariance(&self) -> Matrix4<f64> {
+        self.filter.covariance
+    }
+    fn covariance_faults(&self) -> &[f64] {
+        &self.filter.covariance_faults
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::Car;
+    use crate::numerical::{assert_jacobian, random_vector};
+    use crate::sensors::GPS::GpsXYZ;
+
+    #[test]
+    fn test_filter_tracks_gps() {
+        let mut car = Car::new(0.0, 0.0, 0.0, 20.0, 40.0, 5.0, 2.0, 0.5, 0.1, None);
+        let mut gps = GpsXYZ::new(None);
+        gps.seed(7);
+        let mut start = car.state;
+        start.x += 3.0;
+        start.y -= 2.0;
+        let mut filter = KalmanFilter::new(&start, 2.0, 0.5, 0.1);
+        for _ in 0..100 {
+            car.step(0.1, 0.01);
+            gps.from_carstate(&car.state);
+            filter.predict(0.1, 0.01);
+            filter.update_gps(&gps.get_local_xyz(None));
+        }
+        assert!((filter.state.x - car.state.x).abs() < 0.5);
+        assert!((filter.state.y - car.state.y).abs() < 0.5);
+        assert_eq!(filter.history.len(), 200);
+    }
+
+    #[test]
+    fn test_nis_flags_an_outlying_fix() {
+        let mut car = Car::new(0.0, 0.0, 0.0, 20.0, 40.0, 5.0, 2.0, 0.5, 0.1, None);
+        let mut gps = GpsXYZ::new(None);
+        gps.seed(2);
+        let mut filter = KalmanFilter::new(&car.state, 2.0, 0.5, 0.1);
+        assert_eq!(filter.nis().gps, None);
+        for _ in 0..50 {
+            car.step(0.0, 0.0);
+            gps.from_carstate(&car.state);
+            filter.predict(0.0, 0.0);
+            filter.update_gps(&gps.get_local_xyz(None));
+        }
+        let nis = filter.nis().gps.unwrap();
+        assert!(nis < 10.0, "{}", nis);
+
+        let mut outlier = gps.get_local_xyz(None);
+        outlier.x += 5.0;
+        filter.predict(0.0, 0.0);
+        filter.update_gps(&outlier);
+        let nis = filter.nis().gps.unwrap();
+        assert!(nis > 100.0, "{}", nis);
+        assert_eq!(filter.nis().odometry, None);
+    }
+
+    #[test]
+    fn test_smoother_is_closer_to_the_truth_than_the_filter() {
+        let mut car = Car::new(0.0, 0.0, 0.0, 20.0, 40.0, 5.0, 2.0, 0.5, 0.1, None);
+        let mut gps = GpsXYZ::new(None);
+        gps.seed(4);
+        gps.noise_ratio = 1.0;
+        let mut filter = KalmanFilter::new(&car.state, 2.0, 0.5, 0.1);
+        let (mut truth, mut filtered) = (Vec::new(), Vec::new());
+        for k in 0..200 {
+            let steering_angle = 0.05 * (0.05 * k as f64).sin();
+            car.step(0.0, steering_angle);
+            gps.from_carstate(&car.state);
+            filter.predict(0.0, steering_angle);
+            filter.update_gps(&gps.get_local_xyz(None));
+            truth.push(car.state);
+            filtered.push(filter.state);
+        }
+        let rmse = |estimates: &[CarState]| {
+            let sum: f64 = estimates
+                .iter()
+                .zip(&truth)
+                .map(|(e, t)| (e.x - t.x).powi(2) + (e.y - t.y).powi(2))
+                .sum();
+            (sum / truth.len() as f64).sqrt()
+        };
+        let smoothed = filter.smooth();
+        assert_eq!(smoothed.len(), truth.len());
+        // the last smoothed estimate has no later measurements to draw on
+        assert_eq!(smoothed.last().unwrap().x, filter.state.x);
+        assert!(
+            rmse(&smoothed) < 0.8 * rmse(&filtered),
+            "smoothed {} filtered {}",
+            rmse(&smoothed),
+            rmse(&filtered)
+        );
+    }
+
+    #[test]
+    fn test_gps_measurement_jacobian() {
+        assert_jacobian(
+            "gps measurement",
+            |state: &Vector4<f64>| Vector3::from(gps_measurement((*state).into())),
+            |state| gps_measurement_jacobian(state).1,
+            |rng| {
+                random_vector(
+                    rng,
+                    [(-100.0, 100.0), (-100.0, 100.0), (-3.0, 3.0), (0.0, 20.0)],
+                )
+            },
+            1e-9,
+        );
+    }
+}
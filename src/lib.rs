@@ -0,0 +1,29 @@
+extern crate image;
+extern crate imageproc;
+extern crate nalgebra;
+extern crate piston_window;
+
+pub mod actuator;
+pub mod autodiff;
+pub mod camera;
+pub mod car;
+pub mod collision;
+pub mod control_profile;
+pub mod controller;
+pub mod error_state_filter;
+pub mod hud;
+pub mod information_filter;
+pub mod interactive;
+pub mod kalman_filter;
+pub mod mpc;
+pub mod numerical;
+pub mod path;
+pub mod render;
+pub mod runner;
+pub mod scenario;
+pub mod sensor_measurement;
+pub mod sensors;
+pub mod square_root_filter;
+pub mod state;
+pub mod stationary;
+pub mod world;
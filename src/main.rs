@@ -1,26 +1,110 @@
 extern crate image;
-extern crate imageproc;
-extern crate nalgebra;
+extern crate kalman_filter;
 extern crate piston_window;
 
-mod car;
-mod kalman_filter;
-mod sensor_measurement;
-mod sensors;
-mod state;
-
-use car::Car;
-use sensor_measurement::SensorSet;
-use state::{CarColor, CarState, Rectangular};
+use kalman_filter::camera::Camera;
+use kalman_filter::hud::{hud_lines, FrameRate};
+use kalman_filter::interactive::{Drag, Playback, Teleop};
+use kalman_filter::render::{
+    draw_footprint, draw_gps_fix, draw_grid, draw_scale_bar, draw_trails, draw_uncertainty, Layers,
+};
+use kalman_filter::runner::{build_simulation, run_headless, RunOptions, USAGE};
 
 use image::{ImageBuffer, Rgba, RgbaImage};
 
 use piston_window::*;
 
+use std::time::Instant;
+
+// zoom factor per notch of the mouse wheel
+const ZOOM_STEP: f64 = 1.2;
+// font of the heads-up display, compiled into the binary so it runs from any directory
+const HUD_FONT: &[u8] = include_bytes!("../assets/fonts/DejaVuSansMono.ttf");
+const HUD_FONT_SIZE: u32 = 12;
+const HUD_LINE_HEIGHT: f64 = 15.0;
+const HUD_MARGIN: f64 = 8.0;
+// advance of a glyph of the monospaced font, per unit of font size
+const HUD_GLYPH_WIDTH: f64 = 0.6;
+
+// the next vehicle to follow: each one in turn, then none
+fn next_follow(follow: Option<usize>, vehicles: usize) -> Option<usize> {
+    match follow {
+        None if vehicles > 0 => Some(0),
+        Some(id) if id + 1 < vehicles => Some(id + 1),
+        _ => None,
+    }
+}
+
+// the layer toggled by a number key, 1 for the first one
+fn layer_index(key: Key) -> Option<usize> {
+    let keys = [
+        Key::D1,
+        Key::D2,
+        Key::D3,
+        Key::D4,
+        Key::D5,
+        Key::D6,
+        Key::D7,
+        Key::D8,
+        Key::D9,
+    ];
+    keys.iter().position(|candidate| *candidate == key)
+}
+
 fn main() {
-    let mut i = 0;
-    let mut car = Car::new(0.0, 240.0, 0.0, 40.0, 20.0, 0.0, 2.0, 0.5, 0.1, None);
-    let mut sensor_measurement = SensorSet::new(&car.state);
+    let options = match RunOptions::from_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    if options.headless {
+        match run_headless(&options) {
+            Ok(summary) => {
+                println!(
+                    "ran {} steps ({} s), {} collisions, position RMSE per vehicle: {:?}",
+                    summary.steps, summary.time, summary.collisions, summary.position_rmse
+                );
+                if summary.covariance_faults.iter().any(|faults| *faults > 0) {
+                    println!(
+                        "covariance not positive definite this many times per vehicle: {:?}",
+                        summary.covariance_faults
+                    );
+                }
+                if summary.imu_bias_errors.iter().any(Option::is_some) {
+                    println!(
+                        "IMU bias error (estimated - true) per vehicle: {:?}",
+                        summary.imu_bias_errors
+                    );
+                }
+                if summary.calibration_errors.iter().any(Option::is_some) {
+                    println!(
+                        "calibration error (estimated - true) per vehicle: {:?}",
+                        summary.calibration_errors
+                    );
+                }
+            }
+            Err(message) => {
+                eprintln!("{}", message);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let (mut world, steps) = match build_simulation(&options) {
+        Ok(simulation) => (simulation.world, simulation.steps),
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
 
     let screen_width = 640 * 2;
     let screen_height = 480;
@@ -30,20 +114,152 @@ fn main() {
         .build()
         .unwrap();
     let mut image_buffer: RgbaImage = ImageBuffer::new(screen_width, screen_height);
+    let mut glyphs = Glyphs::from_bytes(
+        HUD_FONT,
+        window.create_texture_context(),
+        TextureSettings::new(),
+    )
+    .expect("the bundled HUD font is a valid TrueType font");
 
+    let mut camera = Camera::new(screen_width, screen_height);
+    camera.follow = next_follow(None, world.vehicles.len());
+    let mut drag = Drag::default();
+    let mut playback = Playback::new();
+    let mut teleop = Teleop::default();
+    let mut layers = Layers::new(options.trails.clone());
+    let mut frame_rate = FrameRate::default();
+    let mut last_frame = Instant::now();
+
+    let mut i = 0;
     while let Some(event) = window.next() {
-        let gt_viz_rect = car.step(0.1, 0.001);
-        let sensor_viz_rect = sensor_measurement.get_observed_state(&car.state);
-        println!("CarActual {{ Position: {}/{}, yaw: {}, velocity: {} }}", car.state.x, car.state.y, car.state.yaw, car.state.velocity);
+        if let Some([u, v]) = event.mouse_cursor_args() {
+            if let Some((du, dv)) = drag.move_to((u, v)) {
+                camera.pan(du, dv);
+            }
+        }
+        if let Some([_, notches]) = event.mouse_scroll_args() {
+            camera.zoom_at(drag.cursor, ZOOM_STEP.powf(notches));
+        }
+        match event.press_args() {
+            Some(Button::Mouse(MouseButton::Left)) => drag.press(),
+            Some(Button::Keyboard(Key::F)) => {
+                camera.follow = next_follow(camera.follow, world.vehicles.len())
+            }
+            Some(Button::Keyboard(Key::H)) => {
+                camera.heading_up = !camera.heading_up;
+                camera.rotation = 0.0;
+            }
+            Some(Button::Keyboard(Key::Space)) => playback.toggle_pause(),
+            Some(Button::Keyboard(Key::S | Key::Period)) => playback.request_step(),
+            Some(Button::Keyboard(Key::Equals | Key::Plus | Key::NumPadPlus)) => playback.faster(),
+            Some(Button::Keyboard(Key::Minus | Key::NumPadMinus)) => playback.slower(),
+            Some(Button::Keyboard(Key::R)) => match build_simulation(&options) {
+                Ok(simulation) => {
+                    world = simulation.world;
+                    teleop = Teleop::default();
+                    playback = Playback::new();
+                    i = 0;
+                }
+                Err(message) => eprintln!("{}", message),
+            },
+            Some(Button::Keyboard(Key::T)) => {
+                teleop.vehicle = match teleop.vehicle {
+                    Some(_) => None,
+                    None => Some(camera.follow.unwrap_or(0)),
+                }
+            }
+            Some(Button::Keyboard(key)) => {
+                if let Some(index) = layer_index(key) {
+                    layers.toggle(index);
+                }
+            }
+            _ => {}
+        }
+        if let Some(Button::Keyboard(key)) = event.button_args().map(|args| args.button) {
+            let held = event.press_args().is_some();
+            match key {
+                Key::Up => teleop.throttle = held,
+                Key::Down => teleop.brake = held,
+                Key::Left => teleop.left = held,
+                Key::Right => teleop.right = held,
+                _ => {}
+            }
+        }
+        if let Some(Button::Mouse(MouseButton::Left)) = event.release_args() {
+            drag.release();
+        }
+        // the simulation advances once per frame, not with every input event
+        if event.render_args().is_none() {
+            continue;
+        }
+        let now = Instant::now();
+        frame_rate.tick(now.duration_since(last_frame).as_secs_f64());
+        last_frame = now;
+
+        for vehicle in world.vehicles.iter_mut() {
+            vehicle.manual_control = (teleop.vehicle == Some(vehicle.id))
+                .then(|| teleop.command(vehicle.car.model.max_steer));
+        }
+        for _ in 0..playback.frame_steps() {
+            if i >= steps {
+                playback.paused = true;
+                break;
+            }
+            // ongoing contacts are on the HUD; only the start of each one is logged
+            for collision in world.step().iter().filter(|collision| collision.started) {
+                println!(
+                    "Collision @{}: {:?} <-> {:?}, depth: {}",
+                    collision.time, collision.first, collision.second, collision.contact.depth
+                );
+            }
+            i += 1;
+        }
 
         // Clear the image buffer and draw on it
         for pixel in image_buffer.pixels_mut() {
             *pixel = Rgba([1, 1, 1, 0]); // Set the background to transparent
         }
 
-        gt_viz_rect.draw_rect(&mut image_buffer);
-        sensor_viz_rect.draw_rect(&mut image_buffer);
-        println!("{}",sensor_measurement);
+        if let Some(vehicle) = camera
+            .follow
+            .and_then(|id| world.vehicles.iter().find(|vehicle| vehicle.id == id))
+        {
+            let state = &vehicle.car.state;
+            camera.track((state.x, state.y), state.yaw);
+        }
+        if layers.grid {
+            draw_grid(&mut image_buffer, &camera);
+        }
+        for vehicle in world.vehicles.iter() {
+            let smoothed = if layers.trails.smoothed {
+                vehicle.estimator.smoothed_trajectory()
+            } else {
+                None
+            };
+            draw_trails(
+                &mut image_buffer,
+                &camera,
+                &vehicle.history,
+                smoothed.as_deref(),
+                &layers.trails,
+            );
+        }
+        for snapshot in world.snapshots() {
+            draw_footprint(&mut image_buffer, &camera, &snapshot.ground_truth_rect);
+            if layers.dead_reckoning {
+                draw_footprint(&mut image_buffer, &camera, &snapshot.measured_rect);
+            }
+            draw_footprint(&mut image_buffer, &camera, &snapshot.estimate_rect);
+            if layers.uncertainty {
+                draw_uncertainty(&mut image_buffer, &camera, &snapshot);
+            }
+            if layers.gps_fix {
+                if let Some(fix) = &snapshot.latest_gps_fix {
+                    draw_gps_fix(&mut image_buffer, &camera, fix, snapshot.gps_noise_std_dev);
+                }
+            }
+        }
+        draw_scale_bar(&mut image_buffer, &camera);
 
         // Create a texture from the ImageBuffer
         let texture = Texture::from_image(
@@ -53,15 +269,47 @@ fn main() {
         )
         .unwrap();
 
+        let lines = hud_lines(
+            &world,
+            i,
+            steps,
+            &playback,
+            &frame_rate,
+            camera.grid_spacing(),
+        );
+
         // Display the texture on the window
-        window.draw_2d(&event, |context, graphics, _| {
+        window.draw_2d(&event, |context, graphics, device| {
             clear([1.0; 4], graphics);
             image(&texture, context.transform, graphics);
-        });
 
-        i += 1;
-        if i > 2000 {
-            break;
-        }
+            let width = lines.iter().map(|line| line.len()).max().unwrap_or(0) as f64
+                * HUD_GLYPH_WIDTH
+                * HUD_FONT_SIZE as f64;
+            rectangle(
+                [1.0, 1.0, 1.0, 0.8],
+                [
+                    0.0,
+                    0.0,
+                    width + 2.0 * HUD_MARGIN,
+                    lines.len() as f64 * HUD_LINE_HEIGHT + 2.0 * HUD_MARGIN,
+                ],
+                context.transform,
+                graphics,
+            );
+            for (row, line) in lines.iter().enumerate() {
+                let baseline = HUD_MARGIN + (row + 1) as f64 * HUD_LINE_HEIGHT - 3.0;
+                text::Text::new_color([0.0, 0.0, 0.0, 1.0], HUD_FONT_SIZE)
+                    .draw(
+                        line,
+                        &mut glyphs,
+                        &context.draw_state,
+                        context.transform.trans(HUD_MARGIN, baseline),
+                        graphics,
+                    )
+                    .ok();
+            }
+            glyphs.factory.encoder.flush(device);
+        });
     }
 }
@@ -0,0 +1,394 @@
+use nalgebra::{DMatrix, DVector, Matrix4, Vector4};
+
+use crate::car::KinematicBicycleModel;
+use crate::controller::Controller;
+use crate::path::ReferencePath;
+use crate::state::{wrap_angle, CarState};
+
+// one sample of a time-indexed reference trajectory, with its feed-forward controls
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TrajectoryPoint {
+    pub x: f64,
+    pub y: f64,
+    pub yaw: f64,
+    pub velocity: f64,
+    pub acceleration: f64,
+    pub steering_angle: f64,
+}
+
+impl TrajectoryPoint {
+    // samples `path` every `dt` seconds at constant `speed`, with steering feed-forward
+    // from the heading change between consecutive samples; empty unless the car moves forward
+    // between samples
+    pub fn along_path(path: &ReferencePath, speed: f64, dt: f64, wheelbase: f64) -> Vec<Self> {
+        let spacing = speed * dt;
+        if !(spacing > 0.0 && spacing.is_finite()) {
+            return Vec::new();
+        }
+        let steps = (path.length() / spacing).ceil() as usize;
+        let mut trajectory: Vec<Self> = (0..=steps)
+            .map(|k| {
+                let s = k as f64 * spacing;
+                let (x, y) = path.point_at(s);
+                TrajectoryPoint {
+                    x,
+                    y,
+                    yaw: path.heading_at(s),
+                    velocity: speed,
+                    acceleration: 0.0,
+                    steering_angle: 0.0,
+                }
+            })
+            .collect();
+        for k in 0..trajectory.len().saturating_sub(1) {
+            let yaw_change = wrap_angle(trajectory[k + 1].yaw - trajectory[k].yaw);
+            trajectory[k].steering_angle = (wheelbase * yaw_change / spacing).atan();
+        }
+        // keep the headings continuous so linear interpolation does not jump at +-pi
+        for k in 1..trajectory.len() {
+            trajectory[k].yaw =
+                trajectory[k - 1].yaw + wrap_angle(trajectory[k].yaw - trajectory[k - 1].yaw);
+        }
+        trajectory
+    }
+
+    fn to_vector(self) -> Vector4<f64> {
+        Vector4::new(self.x, self.y, self.yaw, self.velocity)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct MpcConfig {
+    pub horizon: usize,
+    // weights on the (x, y, yaw, velocity) tracking error at every step of the horizon
+    pub state_weight: [f64; 4],
+    pub terminal_weight: [f64; 4],
+    // weights on the (acceleration, steering_angle) deviation from the feed-forward
+    pub control_weight: [f64; 2],
+    pub min_acceleration: f64,
+    pub max_acceleration: f64,
+    // sweeps of the box-constrained QP solver
+    pub iterations: usize,
+}
+
+impl Default for MpcConfig {
+    fn default() -> Self {
+        Self {
+            horizon: 20,
+            state_weight: [1.0, 1.0, 0.5, 0.1],
+            terminal_weight: [5.0, 5.0, 2.0, 0.5],
+            control_weight: [0.1, 1.0],
+            min_acceleration: -3.0,
+            max_acceleration: 2.0,
+            iterations: 200,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MpcSolution {
+    // (acceleration, steering_angle) for every step of the horizon
+    pub controls: Vec<(f64, f64)>,
+    // states predicted by the linearised model under `controls`
+    pub predicted: Vec<Vector4<f64>>,
+    pub cost: f64,
+}
+
+// minimises 0.5 u'Hu + g'u subject to lower <= u <= upper by projected Gauss-Seidel sweeps;
+// H must be symmetric positive definite, coordinates without a positive diagonal are left as they
+// start
+pub fn solve_box_qp(
+    h: &DMatrix<f64>,
+    g: &DVector<f64>,
+    lower: &DVector<f64>,
+    upper: &DVector<f64>,
+    iterations: usize,
+) -> DVector<f64> {
+    let n = g.len();
+    let mut u = DVector::from_fn(n, |i, _| 0.0_f64.clamp(lower[i], upper[i]));
+    for _ in 0..iterations {
+        let mut largest_step: f64 = 0.0;
+        for i in 0..n {
+            if h[(i, i)] <= 0.0 {
+                continue;
+            }
+            let gradient = (0..n).map(|j| h[(i, j)] * u[j]).sum::<f64>() + g[i];
+            let updated = (u[i] - gradient / h[(i, i)]).clamp(lower[i], upper[i]);
+            largest_step = largest_step.max((updated - u[i]).abs());
+            u[i] = updated;
+        }
+        if largest_step < 1e-8 {
+            break;
+        }
+    }
+    u
+}
+
+// linear time-varying MPC that linearises the bicycle model along the reference
+pub struct Mpc {
+    pub model: KinematicBicycleModel,
+    pub config: MpcConfig,
+}
+
+impl Mpc {
+    pub fn new(model: KinematicBicycleModel, config: MpcConfig) -> Self {
+        Self { model, config }
+    }
+
+    fn step(&self, x: &Vector4<f64>, acceleration: f64, steering_angle: f64) -> Vector4<f64> {
+        let next = self
+            .model
+            ._update(x[0], x[1], x[2], x[3], acceleration, steering_angle);
+        Vector4::new(next.x, next.y, next.yaw, next.velocity)
+    }
+
+    // plans over `reference`, which starts at the current time; the horizon is shortened
+    // when the reference has fewer than `horizon + 1` points
+    pub fn solve(&self, state: &CarState, reference: &[TrajectoryPoint]) -> Option<MpcSolution> {
+        let n = self.config.horizon.min(reference.len().saturating_sub(1));
+        if n == 0 {
+            return None;
+        }
+        let max_steer = self.model.max_steer;
+
+        // deviation dynamics dx[k+1] = A dx[k] + B du[k] + c[k] around the reference
+        let mut sx = DMatrix::<f64>::zeros(4 * n, 4);
+        let mut su = DMatrix::<f64>::zeros(4 * n, 2 * n);
+        let mut sc = DVector::<f64>::zeros(4 * n);
+        let mut transition = Matrix4::<f64>::identity();
+        let mut offset = Vector4::<f64>::zeros();
+        for k in 0..n {
+            let point = reference[k];
+            let (a, b) = self.model._autodiff_jacobians(
+                point.x,
+                point.y,
+                point.yaw,
+                point.velocity,
+                point.acceleration,
+                point.steering_angle,
+            );
+            let mut c = self.step(&point.to_vector(), point.acceleration, point.steering_angle)
+                - reference[k + 1].to_vector();
+            c[2] = wrap_angle(c[2]);
+
+            transition = a * transition;
+            offset = a * offset + c;
+            sx.view_mut((4 * k, 0), (4, 4)).copy_from(&transition);
+            sc.rows_mut(4 * k, 4).copy_from(&offset);
+            for j in 0..k {
+                let previous = su.view((4 * (k - 1), 2 * j), (4, 2)).into_owned();
+                su.view_mut((4 * k, 2 * j), (4, 2))
+                    .copy_from(&(a * previous));
+            }
+            su.view_mut((4 * k, 2 * k), (4, 2)).copy_from(&b);
+        }
+
+        let mut initial = state.to_vector4() - reference[0].to_vector();
+        initial[2] = wrap_angle(initial[2]);
+
+        let q = DVector::from_fn(4 * n, |i, _| {
+            if i >= 4 * (n - 1) {
+                self.config.terminal_weight[i % 4]
+            } else {
+                self.config.state_weight[i % 4]
+            }
+        });
+        let r = DVector::from_fn(2 * n, |i, _| self.config.control_weight[i % 2]);
+        let q_su = DMatrix::from_diagonal(&q) * &su;
+        let h = su.transpose() * &q_su + DMatrix::from_diagonal(&r);
+        let free_response = &sx * initial + &sc;
+        let g = q_su.transpose() * &free_response;
+
+        let lower = DVector::from_fn(2 * n, |i, _| {
+            let point = reference[i / 2];
+            if i % 2 == 0 {
+                self.config.min_acceleration - point.acceleration
+            } else {
+                -max_steer - point.steering_angle
+            }
+        });
+        let upper = DVector::from_fn(2 * n, |i, _| {
+            let point = reference[i / 2];
+            if i % 2 == 0 {
+                self.config.max_acceleration - point.acceleration
+            } else {
+                max_steer - point.steering_angle
+            }
+        });
+        let du = solve_box_qp(&h, &g, &lower, &upper, self.config.iterations);
+
+        let deviations = free_response + &su * &du;
+        let cost = 0.5 * du.dot(&(&h * &du)) + g.dot(&du);
+        Some(MpcSolution {
+            controls: (0..n)
+                .map(|k| {
+                    (
+                        reference[k].acceleration + du[2 * k],
+                        reference[k].steering_angle + du[2 * k + 1],
+                    )
+                })
+                .collect(),
+            predicted: (0..n)
+                .map(|k| reference[k + 1].to_vector() + deviations.fixed_rows::<4>(4 * k))
+                .collect(),
+            cost,
+        })
+    }
+}
+
+// runs the MPC against a trajectory whose first point corresponds to simulation time zero
+pub struct MpcController {
+    pub mpc: Mpc,
+    pub trajectory: Vec<TrajectoryPoint>,
+    pub last_solution: Option<MpcSolution>,
+}
+
+impl MpcController {
+    pub fn new(mpc: Mpc, trajectory: Vec<TrajectoryPoint>) -> Self {
+        Self {
+            mpc,
+            trajectory,
+            last_solution: None,
+        }
+    }
+}
+
+impl Controller for MpcController {
+    fn control(&mut self, time: f64, state: &CarState) -> (f64, f64) {
+        // the time is the end of the step being commanded (as World::step passes it) while the
+        // state is still the one at its start, so the reference begins one point earlier
+        let start = ((time / self.mpc.model.dt).round() as usize)
+            .saturating_sub(1)
+            .min(self.trajectory.len().saturating_sub(1));
+        let mut reference = self.trajectory[start..].to_vec();
+        // hold the final point so the car comes to rest there
+        if let Some(last) = reference.last().copied() {
+            let hold = TrajectoryPoint {
+                velocity: 0.0,
+                acceleration: 0.0,
+                steering_angle: 0.0,
+                ..last
+            };
+            while reference.len() <= self.mpc.config.horizon {
+                reference.push(hold);
+            }
+        }
+        self.last_solution = self.mpc.solve(state, &reference);
+        self.last_solution
+            .as_ref()
+            .and_then(|solution| solution.controls.first().copied())
+            .unwrap_or((0.0, 0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::Car;
+    use crate::controller::ControlSource;
+    use crate::world::World;
+
+    #[test]
+    fn test_box_qp_respects_bounds() {
+        let h = DMatrix::from_row_slice(2, 2, &[2.0, 0.5, 0.5, 1.0]);
+        let g = DVector::from_vec(vec![-4.0, 1.0]);
+        let unconstrained = solve_box_qp(
+            &h,
+            &g,
+            &DVector::from_element(2, -10.0),
+            &DVector::from_element(2, 10.0),
+            500,
+        );
+        let exact = h.clone().try_inverse().unwrap() * -&g;
+        assert!((unconstrained - exact).norm() < 1e-6);
+
+        let clamped = solve_box_qp(
+            &h,
+            &g,
+            &DVector::from_element(2, -0.5),
+            &DVector::from_element(2, 1.0),
+            500,
+        );
+        assert_eq!(clamped[0], 1.0);
+        assert!(clamped[1] >= -0.5 && clamped[1] <= 1.0);
+
+        // a zero diagonal leaves that coordinate where it starts instead of dividing by zero
+        let singular = DMatrix::from_row_slice(2, 2, &[2.0, 0.0, 0.0, 0.0]);
+        let partial = solve_box_qp(
+            &singular,
+            &g,
+            &DVector::from_element(2, -10.0),
+            &DVector::from_element(2, 10.0),
+            500,
+        );
+        assert!((partial[0] - 2.0).abs() < 1e-9);
+        assert_eq!(partial[1], 0.0);
+    }
+
+    #[test]
+    fn test_trajectory_needs_forward_motion() {
+        let path = ReferencePath::new(vec![(0.0, 0.0), (10.0, 0.0)]);
+        assert!(TrajectoryPoint::along_path(&path, 0.0, 0.1, 2.5).is_empty());
+        assert!(TrajectoryPoint::along_path(&path, -5.0, 0.1, 2.5).is_empty());
+        assert!(TrajectoryPoint::along_path(&path, 5.0, 0.0, 2.5).is_empty());
+        let trajectory = TrajectoryPoint::along_path(&path, 5.0, 0.1, 2.5);
+        assert_eq!(trajectory.len(), 21);
+        assert!(trajectory
+            .iter()
+            .all(|point| point.steering_angle.is_finite()));
+    }
+
+    #[test]
+    fn test_mpc_tracks_path_within_limits() {
+        let dt = 0.1;
+        let path = ReferencePath::new(vec![(0.0, 0.0), (40.0, 0.0), (60.0, 20.0), (100.0, 20.0)]);
+        let trajectory = TrajectoryPoint::along_path(&path, 5.0, dt, 2.5);
+        let mut car = Car::new(0.0, 2.0, 0.0, 1.8, 4.5, 5.0, 2.5, 0.5, dt, None);
+        let config = MpcConfig::default();
+        let mut controller = MpcController::new(Mpc::new(car.model.clone(), config), trajectory);
+        let mut worst_error: f64 = 0.0;
+        for step in 0..260 {
+            let time = (step + 1) as f64 * dt;
+            let (acceleration, steering_angle) = controller.control(time, &car.state);
+            assert!(steering_angle.abs() <= 0.5 + 1e-9);
+            assert!((config.min_acceleration..=config.max_acceleration).contains(&acceleration));
+            car.step(acceleration, steering_angle);
+            if step > 30 {
+                let error = path
+                    .nearest(car.state.x, car.state.y)
+                    .unwrap()
+                    .lateral_error;
+                worst_error = worst_error.max(error.abs());
+            }
+        }
+        assert!(worst_error < 1.5, "lateral error {}", worst_error);
+        assert!((car.state.x - 100.0).abs() < 2.0 && (car.state.y - 20.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_mpc_follows_its_reference_in_the_world() {
+        let dt = 0.1;
+        let path = ReferencePath::new(vec![(0.0, 0.0), (100.0, 0.0)]);
+        let trajectory = TrajectoryPoint::along_path(&path, 5.0, dt, 2.5);
+        let car = Car::new(0.0, 0.0, 0.0, 1.8, 4.5, 5.0, 2.5, 0.5, dt, None);
+        let controller = MpcController::new(
+            Mpc::new(car.model.clone(), MpcConfig::default()),
+            trajectory.clone(),
+        );
+        let mut world = World::new(dt);
+        world.add_vehicle(car, Box::new(controller));
+        world.vehicles[0].control_source = ControlSource::GroundTruth;
+        for reference in trajectory.iter().skip(1).take(60) {
+            world.step();
+            // the car starting on the reference stays on it instead of chasing a point ahead
+            let state = world.vehicles[0].car.state;
+            assert!(
+                (state.x - reference.x).abs() < 0.05,
+                "t {:.1}: x {} reference {}",
+                world.time,
+                state.x,
+                reference.x
+            );
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use nalgebra::{SMatrix, SVector};
+
+// default relative step of the central differences; the truncation error shrinks with the
+// square of the step while rounding error grows as it shrinks, and 1e-6 balances both for f64
+pub const DEFAULT_STEP: f64 = 1e-6;
+
+// Jacobian of `function` at `x` by central differences; each input is perturbed by
+// `step * max(1, |x_j|)` so large and small coordinates get a comparable relative step
+pub fn numerical_jacobian<const R: usize, const C: usize>(
+    function: impl Fn(&SVector<f64, C>) -> SVector<f64, R>,
+    x: &SVector<f64, C>,
+    step: f64,
+) -> SMatrix<f64, R, C> {
+    let mut jacobian = SMatrix::<f64, R, C>::zeros();
+    for j in 0..C {
+        let h = step * x[j].abs().max(1.0);
+        let (mut plus, mut minus) = (*x, *x);
+        plus[j] += h;
+        minus[j] -= h;
+        let column = (function(&plus) - function(&minus)) / (2.0 * h);
+        jacobian.set_column(j, &column);
+    }
+    jacobian
+}
+
+// checks `analytic` against central differences of `function` at `samples` random inputs drawn
+// by `sample`, panicking with the worst entry when they differ by more than `tolerance`
+#[cfg(test)]
+pub(crate) fn assert_jacobian<const R: usize, const C: usize>(
+    name: &str,
+    function: impl Fn(&SVector<f64, C>) -> SVector<f64, R>,
+    analytic: impl Fn(&SVector<f64, C>) -> SMatrix<f64, R, C>,
+    mut sample: impl FnMut(&mut rand::rngs::StdRng) -> SVector<f64, C>,
+    tolerance: f64,
+) {
+    use rand::SeedableRng;
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    for _ in 0..100 {
+        let x = sample(&mut rng);
+        let expected = numerical_jacobian(&function, &x, DEFAULT_STEP);
+        let actual = analytic(&x);
+        let error = actual - expected;
+        let index = error.iamax_full();
+        assert!(
+            error[index].abs() <= tolerance,
+            "{} Jacobian entry {:?} is {} but central differences give {} at {:?}",
+            name,
+            index,
+            actual[index],
+            expected[index],
+            x.as_slice(),
+        );
+    }
+}
+
+// a uniform random vector with each entry drawn from its own range
+#[cfg(test)]
+pub(crate) fn random_vector<const C: usize>(
+    rng: &mut rand::rngs::StdRng,
+    ranges: [(f64, f64); C],
+) -> SVector<f64, C> {
+    use rand::Rng;
+
+    SVector::from_fn(|i, _| rng.gen_range(ranges[i].0..ranges[i].1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Matrix2, Vector2};
+
+    #[test]
+    fn test_jacobian_of_polar_conversion() {
+        let polar = |v: &Vector2<f64>| Vector2::new(v[0] * v[1].cos(), v[0] * v[1].sin());
+        let analytic = |v: &Vector2<f64>| {
+            Matrix2::new(
+                v[1].cos(),
+                -v[0] * v[1].sin(),
+                v[1].sin(),
+                v[0] * v[1].cos(),
+            )
+        };
+        assert_jacobian(
+            "polar",
+            polar,
+            analytic,
+            |rng| random_vector(rng, [(0.1, 100.0), (-3.0, 3.0)]),
+            1e-6,
+        );
+        let coarse = numerical_jacobian(polar, &Vector2::new(2.0, 0.5), 1e-2);
+        assert!((coarse - analytic(&Vector2::new(2.0, 0.5))).norm() > 1e-6);
+    }
+
+    #[test]
+    #[should_panic(expected = "wrong Jacobian entry")]
+    fn test_detects_wrong_jacobian() {
+        assert_jacobian(
+            "wrong",
+            |v: &Vector2<f64>| Vector2::new(v[0] * v[1], v[1]),
+            |v: &Vector2<f64>| Matrix2::new(v[1], 0.0, 0.0, 1.0),
+            |rng| random_vector(rng, [(-1.0, 1.0), (-1.0, 1.0)]),
+            1e-6,
+        );
+    }
+}
@@ -0,0 +1,500 @@
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use super::{valid_spacing, Pose, ReferencePath};
+use crate::state::wrap_angle;
+
+// tolerance for the sign of a segment length in unit-radius formulas; shorter segments are dropped
+const ZERO: f64 = 1e-10;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Steer {
+    Left,
+    Straight,
+    Right,
+}
+
+impl Steer {
+    fn mirrored(self) -> Self {
+        match self {
+            Steer::Left => Steer::Right,
+            Steer::Straight => Steer::Straight,
+            Steer::Right => Steer::Left,
+        }
+    }
+}
+
+// a straight line or a full-lock arc; negative lengths are driven in reverse
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ArcSegment {
+    pub steer: Steer,
+    pub length: f64,
+}
+
+// a path made of straight lines and arcs of one turning radius, as produced by
+// `dubins` and `reeds_shepp`
+#[derive(Debug, Clone)]
+pub struct ArcPath {
+    pub start: Pose,
+    pub radius: f64,
+    pub segments: Vec<ArcSegment>,
+}
+
+// the pose after driving `length` metres (negative in reverse) along one segment
+fn advance(pose: Pose, steer: Steer, length: f64, radius: f64) -> Pose {
+    let Pose { x, y, heading } = pose;
+    match steer {
+        Steer::Straight => Pose::new(
+            x + length * heading.cos(),
+            y + length * heading.sin(),
+            heading,
+        ),
+        Steer::Left => {
+            let end = heading + length / radius;
+            Pose::new(
+                x + radius * (end.sin() - heading.sin()),
+                y + radius * (heading.cos() - end.cos()),
+                wrap_angle(end),
+            )
+        }
+        Steer::Right => {
+            let end = heading - length / radius;
+            Pose::new(
+                x + radius * (heading.sin() - end.sin()),
+                y + radius * (end.cos() - heading.cos()),
+                wrap_angle(end),
+            )
+        }
+    }
+}
+
+impl ArcPath {
+    // total distance driven, forwards and in reverse
+    pub fn length(&self) -> f64 {
+        self.segments
+            .iter()
+            .map(|segment| segment.length.abs())
+            .sum()
+    }
+
+    // the segment at distance `s` and how far into it `s` lies, with the pose where it starts
+    fn locate(&self, s: f64) -> Option<(Pose, ArcSegment, f64)> {
+        let mut pose = self.start;
+        let mut remaining = s.clamp(0.0, self.length());
+        for (i, segment) in self.segments.iter().enumerate() {
+            let length = segment.length.abs();
+            if remaining <= length || i + 1 == self.segments.len() {
+                return Some((pose, *segment, remaining.min(length)));
+            }
+            pose = advance(pose, segment.steer, segment.length, self.radius);
+            remaining -= length;
+        }
+        None
+    }
+
+    pub fn pose_at(&self, s: f64) -> Pose {
+        match self.locate(s) {
+            Some((pose, segment, into)) => advance(
+                pose,
+                segment.steer,
+                into.copysign(segment.length),
+                self.radius,
+            ),
+            None => self.start,
+        }
+    }
+
+    pub fn end(&self) -> Pose {
+        self.pose_at(self.length())
+    }
+
+    // curvature of the steering at distance `s`, positive to the left
+    pub fn curvature_at(&self, s: f64) -> f64 {
+        match self.locate(s).map(|(_, segment, _)| segment.steer) {
+            Some(Steer::Left) => 1.0 / self.radius,
+            Some(Steer::Right) => -1.0 / self.radius,
+            _ => 0.0,
+        }
+    }
+
+    pub fn is_reverse_at(&self, s: f64) -> bool {
+        self.locate(s)
+            .is_some_and(|(_, segment, _)| segment.length < 0.0)
+    }
+
+    // the positions along the path every `spacing` metres or closer; where the car reverses
+    // the polyline doubles back, so its headings are the direction of travel. Empty unless the
+    // spacing is positive
+    pub fn to_reference_path(&self, spacing: f64) -> ReferencePath {
+        if !valid_spacing(spacing) {
+            return ReferencePath::new(Vec::new());
+        }
+        let mut pose = self.start;
+        let mut points = vec![(pose.x, pose.y)];
+        for segment in self.segments.iter() {
+            let pieces = (segment.length.abs() / spacing).ceil() as usize;
+            for k in 1..=pieces {
+                let length = segment.length * k as f64 / pieces as f64;
+                let sample = advance(pose, segment.steer, length, self.radius);
+                points.push((sample.x, sample.y));
+            }
+            pose = advance(pose, segment.steer, segment.length, self.radius);
+        }
+        ReferencePath::new(points)
+    }
+}
+
+// `goal` in the frame of `start`, scaled so the turning radius is one
+fn normalised_goal(start: Pose, goal: Pose, radius: f64) -> (f64, f64, f64) {
+    let (dx, dy) = (goal.x - start.x, goal.y - start.y);
+    let (sin, cos) = start.heading.sin_cos();
+    (
+        (dx * cos + dy * sin) / radius,
+        (-dx * sin + dy * cos) / radius,
+        wrap_angle(goal.heading - start.heading),
+    )
+}
+
+fn shortest(start: Pose, radius: f64, candidates: Vec<(Vec<Steer>, Vec<f64>)>) -> Option<ArcPath> {
+    let total = |lengths: &[f64]| lengths.iter().map(|length| length.abs()).sum::<f64>();
+    candidates
+        .into_iter()
+        .min_by(|a, b| total(&a.1).total_cmp(&total(&b.1)))
+        .map(|(word, lengths)| ArcPath {
+            start,
+            radius,
+            segments: word
+                .into_iter()
+                .zip(lengths)
+                .filter(|(_, length)| length.abs() > ZERO)
+                .map(|(steer, length)| ArcSegment {
+                    steer,
+                    length: length * radius,
+                })
+                .collect(),
+        })
+}
+
+fn mod_two_pi(angle: f64) -> f64 {
+    angle.rem_euclid(2.0 * PI)
+}
+
+// all feasible Dubins words with their segment lengths for a unit turning radius
+fn dubins_candidates(start: Pose, goal: Pose, radius: f64) -> Vec<(Vec<Steer>, Vec<f64>)> {
+    use Steer::{Left as L, Right as R, Straight as S};
+    let (dx, dy) = ((goal.x - start.x) / radius, (goal.y - start.y) / radius);
+    let d = dx.hypot(dy);
+    let theta = if d > 0.0 { dy.atan2(dx) } else { 0.0 };
+    let alpha = mod_two_pi(start.heading - theta);
+    let beta = mod_two_pi(goal.heading - theta);
+    let (sa, ca) = alpha.sin_cos();
+    let (sb, cb) = beta.sin_cos();
+    let c_ab = (alpha - beta).cos();
+
+    let mut candidates = Vec::new();
+    let p_sq = 2.0 + d * d - 2.0 * c_ab + 2.0 * d * (sa - sb);
+    if p_sq >= 0.0 {
+        let tmp = (cb - ca).atan2(d + sa - sb);
+        candidates.push((
+            vec![L, S, L],
+            vec![mod_two_pi(tmp - alpha), p_sq.sqrt(), mod_two_pi(beta - tmp)],
+        ));
+    }
+    let p_sq = 2.0 + d * d - 2.0 * c_ab + 2.0 * d * (sb - sa);
+    if p_sq >= 0.0 {
+        let tmp = (ca - cb).atan2(d - sa + sb);
+        candidates.push((
+            vec![R, S, R],
+            vec![mod_two_pi(alpha - tmp), p_sq.sqrt(), mod_two_pi(tmp - beta)],
+        ));
+    }
+    let p_sq = -2.0 + d * d + 2.0 * c_ab + 2.0 * d * (sa + sb);
+    if p_sq >= 0.0 {
+        let p = p_sq.sqrt();
+        let tmp = (-ca - cb).atan2(d + sa + sb) - (-2.0_f64).atan2(p);
+        candidates.push((
+            vec![L, S, R],
+            vec![mod_two_pi(tmp - alpha), p, mod_two_pi(tmp - beta)],
+        ));
+    }
+    let p_sq = -2.0 + d * d + 2.0 * c_ab - 2.0 * d * (sa + sb);
+    if p_sq >= 0.0 {
+        let p = p_sq.sqrt();
+        let tmp = (ca + cb).atan2(d - sa - sb) - 2.0_f64.atan2(p);
+        candidates.push((
+            vec![R, S, L],
+            vec![mod_two_pi(alpha - tmp), p, mod_two_pi(beta - tmp)],
+        ));
+    }
+    let tmp = (6.0 - d * d + 2.0 * c_ab + 2.0 * d * (sa - sb)) / 8.0;
+    if tmp.abs() <= 1.0 {
+        let p = mod_two_pi(2.0 * PI - tmp.acos());
+        let t = mod_two_pi(alpha - (ca - cb).atan2(d - sa + sb) + 0.5 * p);
+        candidates.push((vec![R, L, R], vec![t, p, mod_two_pi(alpha - beta - t + p)]));
+    }
+    let tmp = (6.0 - d * d + 2.0 * c_ab + 2.0 * d * (sb - sa)) / 8.0;
+    if tmp.abs() <= 1.0 {
+        let p = mod_two_pi(2.0 * PI - tmp.acos());
+        let t = mod_two_pi(-alpha - (ca - cb).atan2(d + sa - sb) + 0.5 * p);
+        candidates.push((vec![L, R, L], vec![t, p, mod_two_pi(beta - alpha - t + p)]));
+    }
+    candidates
+}
+
+// shortest forward-only path from `start` to `goal` with turns no tighter than `radius`
+pub fn dubins(start: Pose, goal: Pose, radius: f64) -> Option<ArcPath> {
+    if radius.is_nan() || radius <= 0.0 {
+        return None;
+    }
+    shortest(start, radius, dubins_candidates(start, goal, radius))
+}
+
+fn polar(x: f64, y: f64) -> (f64, f64) {
+    (x.hypot(y), y.atan2(x))
+}
+
+fn tau_omega(u: f64, v: f64, xi: f64, eta: f64, phi: f64) -> (f64, f64) {
+    let delta = wrap_angle(u - v);
+    let a = u.sin() - delta.sin();
+    let b = u.cos() - delta.cos() - 1.0;
+    let t1 = (eta * a - xi * b).atan2(xi * a + eta * b);
+    let t2 = 2.0 * (delta.cos() - v.cos() - u.cos()) + 3.0;
+    let tau = if t2 < 0.0 {
+        wrap_angle(t1 + PI)
+    } else {
+        wrap_angle(t1)
+    };
+    (tau, wrap_angle(tau - u + v - phi))
+}
+
+// the base Reeds-Shepp formulas, numbered after Reeds and Shepp (1990); each solves one word
+// for a goal (x, y, phi) relative to a unit-radius car at the origin
+fn lp_sp_lp(x: f64, y: f64, phi: f64) -> Option<Vec<f64>> {
+    let (u, t) = polar(x - phi.sin(), y - 1.0 + phi.cos());
+    let v = wrap_angle(phi - t);
+    (t >= -ZERO && v >= -ZERO).then(|| vec![t, u, v])
+}
+
+fn lp_sp_rp(x: f64, y: f64, phi: f64) -> Option<Vec<f64>> {
+    let (u1, t1) = polar(x + phi.sin(), y - 1.0 - phi.cos());
+    let u1 = u1 * u1;
+    if u1 < 4.0 {
+        return None;
+    }
+    let u = (u1 - 4.0).sqrt();
+    let t = wrap_angle(t1 + 2.0_f64.atan2(u));
+    let v = wrap_angle(t - phi);
+    (t >= -ZERO && v >= -ZERO).then(|| vec![t, u, v])
+}
+
+fn lp_rm_l(x: f64, y: f64, phi: f64) -> Option<Vec<f64>> {
+    let (u1, theta) = polar(x - phi.sin(), y - 1.0 + phi.cos());
+    if u1 > 4.0 {
+        return None;
+    }
+    let u = -2.0 * (0.25 * u1).asin();
+    let t = wrap_angle(theta + 0.5 * u + PI);
+    let v = wrap_angle(phi - t + u);
+    (t >= -ZERO && u <= ZERO).then(|| vec![t, u, v])
+}
+
+fn lp_rup_lum_rm(x: f64, y: f64, phi: f64) -> Option<Vec<f64>> {
+    let (xi, eta) = (x + phi.sin(), y - 1.0 - phi.cos());
+    let rho = 0.25 * (2.0 + xi.hypot(eta));
+    if rho > 1.0 {
+        return None;
+    }
+    let u = rho.acos();
+    let (t, v) = tau_omega(u, -u, xi, eta, phi);
+    (t >= -ZERO && v <= ZERO).then(|| vec![t, u, -u, v])
+}
+
+fn lp_rum_lum_rp(x: f64, y: f64, phi: f64) -> Option<Vec<f64>> {
+    let (xi, eta) = (x + phi.sin(), y - 1.0 - phi.cos());
+    let rho = (20.0 - xi * xi - eta * eta) / 16.0;
+    if !(0.0..=1.0).contains(&rho) {
+        return None;
+    }
+    let u = -rho.acos();
+    if u < -FRAC_PI_2 {
+        return None;
+    }
+    let (t, v) = tau_omega(u, u, xi, eta, phi);
+    (t >= -ZERO && v >= -ZERO).then(|| vec![t, u, u, v])
+}
+
+fn lp_rm_sm_lm(x: f64, y: f64, phi: f64) -> Option<Vec<f64>> {
+    let (rho, theta) = polar(x - phi.sin(), y - 1.0 + phi.cos());
+    if rho < 2.0 {
+        return None;
+    }
+    let r = (rho * rho - 4.0).sqrt();
+    let u = 2.0 - r;
+    let t = wrap_angle(theta + r.atan2(-2.0));
+    let v = wrap_angle(phi - FRAC_PI_2 - t);
+    (t >= -ZERO && u <= ZERO && v <= ZERO).then(|| vec![t, -FRAC_PI_2, u, v])
+}
+
+fn lp_rm_sm_rm(x: f64, y: f64, phi: f64) -> Option<Vec<f64>> {
+    let (xi, eta) = (x + phi.sin(), y - 1.0 - phi.cos());
+    let (rho, theta) = polar(-eta, xi);
+    if rho < 2.0 {
+        return None;
+    }
+    let t = theta;
+    let u = 2.0 - rho;
+    let v = wrap_angle(t + FRAC_PI_2 - phi);
+    (t >= -ZERO && u <= ZERO && v <= ZERO).then(|| vec![t, -FRAC_PI_2, u, v])
+}
+
+fn lp_rm_s_lm_rp(x: f64, y: f64, phi: f64) -> Option<Vec<f64>> {
+    let (xi, eta) = (x + phi.sin(), y - 1.0 - phi.cos());
+    let (rho, _) = polar(xi, eta);
+    if rho < 2.0 {
+        return None;
+    }
+    let u = 4.0 - (rho * rho - 4.0).sqrt();
+    if u > ZERO {
+        return None;
+    }
+    let t = wrap_angle(((4.0 - u) * xi - 2.0 * eta).atan2(-2.0 * xi + (u - 4.0) * eta));
+    let v = wrap_angle(t - phi);
+    (t >= -ZERO && v >= -ZERO).then(|| vec![t, -FRAC_PI_2, u, -FRAC_PI_2, v])
+}
+
+type Formula = fn(f64, f64, f64) -> Option<Vec<f64>>;
+
+// every feasible Reeds-Shepp word with its segment lengths for a unit turning radius, using the
+// time-flip (drive it backwards) and reflection (swap left and right) symmetries of each formula,
+// and for asymmetric words also the word driven from the goal back to the start
+fn reeds_shepp_candidates(x: f64, y: f64, phi: f64) -> Vec<(Vec<Steer>, Vec<f64>)> {
+    use Steer::{Left as L, Right as R, Straight as S};
+    let formulas: [(Formula, Vec<Steer>, bool); 8] = [
+        (lp_sp_lp, vec![L, S, L], false),
+        (lp_sp_rp, vec![L, S, R], false),
+        (lp_rm_l, vec![L, R, L], true),
+        (lp_rup_lum_rm, vec![L, R, L, R], false),
+        (lp_rum_lum_rp, vec![L, R, L, R], false),
+        (lp_rm_sm_lm, vec![L, R, S, L], true),
+        (lp_rm_sm_rm, vec![L, R, S, R], true),
+        (lp_rm_s_lm_rp, vec![L, R, S, L, R], false),
+    ];
+    let (sin, cos) = phi.sin_cos();
+    let (x_back, y_back) = (x * cos + y * sin, x * sin - y * cos);
+
+    let mut candidates = Vec::new();
+    for (formula, word, backwards) in formulas.iter() {
+        let mut frames = vec![(x, y, false)];
+        if *backwards {
+            frames.push((x_back, y_back, true));
+        }
+        for (x, y, reversed) in frames {
+            for (flip, reflect) in [(false, false), (true, false), (false, true), (true, true)] {
+                let (sx, sy) = (if flip { -x } else { x }, if reflect { -y } else { y });
+                let sphi = if flip != reflect { -phi } else { phi };
+                let Some(mut lengths) = formula(sx, sy, sphi) else {
+                    continue;
+                };
+                let mut steers: Vec<Steer> = word
+                    .iter()
+                    .map(|steer| if reflect { steer.mirrored() } else { *steer })
+                    .collect();
+                if flip {
+                    lengths.iter_mut().for_each(|length| *length = -*length);
+                }
+                if reversed {
+                    steers.reverse();
+                    lengths.reverse();
+                }
+                candidates.push((steers, lengths));
+            }
+        }
+    }
+    candidates
+}
+
+// shortest path from `start` to `goal` with turns no tighter than `radius` when the car may
+// also reverse
+pub fn reeds_shepp(start: Pose, goal: Pose, radius: f64) -> Option<ArcPath> {
+    if radius.is_nan() || radius <= 0.0 {
+        return None;
+    }
+    let (x, y, phi) = normalised_goal(start, goal, radius);
+    shortest(start, radius, reeds_shepp_candidates(x, y, phi))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_pose(rng: &mut StdRng) -> Pose {
+        Pose::new(
+            rng.gen_range(-20.0..20.0),
+            rng.gen_range(-20.0..20.0),
+            rng.gen_range(-PI..PI),
+        )
+    }
+
+    fn assert_reaches(path: &ArcPath, goal: Pose) {
+        let end = path.end();
+        assert!(
+            (end.x - goal.x).abs() < 1e-6
+                && (end.y - goal.y).abs() < 1e-6
+                && wrap_angle(end.heading - goal.heading).abs() < 1e-6,
+            "{:?} ends at {:?} instead of {:?}",
+            path.segments,
+            end,
+            goal
+        );
+    }
+
+    fn arc_path(start: Pose, radius: f64, candidate: (Vec<Steer>, Vec<f64>)) -> ArcPath {
+        shortest(start, radius, vec![candidate]).unwrap()
+    }
+
+    #[test]
+    fn test_every_candidate_reaches_the_goal() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let radius = 4.0;
+        for _ in 0..200 {
+            let (start, goal) = (random_pose(&mut rng), random_pose(&mut rng));
+            for candidate in dubins_candidates(start, goal, radius) {
+                let path = arc_path(start, radius, candidate);
+                assert!(path.segments.iter().all(|segment| segment.length >= 0.0));
+                assert_reaches(&path, goal);
+            }
+            let (x, y, phi) = normalised_goal(start, goal, radius);
+            for candidate in reeds_shepp_candidates(x, y, phi) {
+                assert_reaches(&arc_path(start, radius, candidate), goal);
+            }
+            let dubins = dubins(start, goal, radius).unwrap();
+            let reeds_shepp = reeds_shepp(start, goal, radius).unwrap();
+            assert!(reeds_shepp.length() <= dubins.length() + 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_path_queries() {
+        let start = Pose::new(0.0, 0.0, 0.0);
+        // a quarter circle to the left followed by a straight line
+        let path = dubins(start, Pose::new(5.0, 15.0, FRAC_PI_2), 5.0).unwrap();
+        assert_eq!(path.segments.len(), 2);
+        assert!((path.length() - (2.5 * PI + 10.0)).abs() < 1e-9);
+        assert_eq!(path.curvature_at(1.0), 0.2);
+        assert_eq!(path.curvature_at(path.length() - 1.0), 0.0);
+        let halfway = path.pose_at(1.25 * PI);
+        assert!((halfway.heading - 0.25 * PI).abs() < 1e-9);
+
+        // straight behind the car: reversing is the shortest way there
+        let back = reeds_shepp(start, Pose::new(-10.0, 0.0, 0.0), 5.0).unwrap();
+        assert!((back.length() - 10.0).abs() < 1e-9);
+        assert!(back.is_reverse_at(5.0));
+        let reference = back.to_reference_path(1.0);
+        assert!((reference.length() - 10.0).abs() < 1e-9);
+        assert!((reference.heading_at(5.0).abs() - PI).abs() < 1e-9);
+        for spacing in [0.0, -1.0] {
+            assert!(back.to_reference_path(spacing).points.is_empty());
+        }
+    }
+}
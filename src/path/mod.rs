@@ -0,0 +1,227 @@
+// reference paths for the controllers: polylines through waypoints, smooth curves sampled
+// into polylines, and shortest paths for a car with a minimum turning radius
+mod maneuver;
+mod spline;
+
+pub use maneuver::{dubins, reeds_shepp, ArcPath, ArcSegment, Steer};
+
+use crate::state::wrap_angle;
+
+// a position together with the direction the car faces
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Pose {
+    pub x: f64,
+    pub y: f64,
+    pub heading: f64,
+}
+
+impl Pose {
+    pub fn new(x: f64, y: f64, heading: f64) -> Self {
+        Self { x, y, heading }
+    }
+}
+
+// radius of the tightest circle a bicycle model with this geometry can drive
+pub fn min_turning_radius(wheelbase: f64, max_steer: f64) -> f64 {
+    wheelbase / max_steer.abs().tan()
+}
+
+// a zero spacing would sample a path into unboundedly many points and a negative one into none
+fn valid_spacing(spacing: f64) -> bool {
+    spacing.is_finite() && spacing > 0.0
+}
+
+// a reference path given as a polyline through waypoints, parameterised by arc length
+#[derive(Debug, Clone)]
+pub struct ReferencePath {
+    pub points: Vec<(f64, f64)>,
+    // arc length from the first point to each point
+    cumulative: Vec<f64>,
+    // curvature estimated at each point from the turn between its two segments
+    curvature: Vec<f64>,
+}
+
+// the point of a path closest to a query position
+#[derive(Debug, Copy, Clone)]
+pub struct PathProjection {
+    pub s: f64,
+    pub x: f64,
+    pub y: f64,
+    pub heading: f64,
+    // signed distance from the path to the query, positive to the left of the path
+    pub lateral_error: f64,
+}
+
+impl ReferencePath {
+    pub fn new(points: Vec<(f64, f64)>) -> Self {
+        let mut cumulative = Vec::with_capacity(points.len());
+        let mut s = 0.0;
+        for (i, point) in points.iter().enumerate() {
+            if i > 0 {
+                let previous = points[i - 1];
+                s += (point.0 - previous.0).hypot(point.1 - previous.1);
+            }
+            cumulative.push(s);
+        }
+        let mut path = Self {
+            points,
+            cumulative,
+            curvature: Vec::new(),
+        };
+        path.curvature = path.vertex_curvature();
+        path
+    }
+
+    // heading change at each inner point divided by the length it is spread over;
+    // the end points take the curvature of their neighbour
+    fn vertex_curvature(&self) -> Vec<f64> {
+        let n = self.points.len();
+        let mut curvature = vec![0.0; n];
+        if n < 3 {
+            return curvature;
+        }
+        let mut heading = self.heading_at(0.0);
+        for i in 1..n - 1 {
+            let before = self.cumulative[i] - self.cumulative[i - 1];
+            let after = self.cumulative[i + 1] - self.cumulative[i];
+            if after == 0.0 {
+                curvature[i] = curvature[i - 1];
+                continue;
+            }
+            let (x0, y0) = self.points[i];
+            let (x1, y1) = self.points[i + 1];
+            let next_heading = (y1 - y0).atan2(x1 - x0);
+            curvature[i] = 2.0 * wrap_angle(next_heading - heading) / (before + after);
+            heading = next_heading;
+        }
+        curvature[0] = curvature[1];
+        curvature[n - 1] = curvature[n - 2];
+        curvature
+    }
+
+    pub fn length(&self) -> f64 {
+        self.cumulative.last().copied().unwrap_or(0.0)
+    }
+
+    // index of the segment containing arc length `s`
+    fn segment_at(&self, s: f64) -> usize {
+        let next = self.cumulative.partition_point(|length| *length <= s);
+        next.clamp(1, self.points.len().max(2) - 1) - 1
+    }
+
+    // position of arc length `s` within segment `i`, from 0 to 1
+    fn fraction(&self, i: usize, s: f64) -> f64 {
+        let segment = self.cumulative[i + 1] - self.cumulative[i];
+        if segment > 0.0 {
+            ((s - self.cumulative[i]) / segment).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+
+    pub fn point_at(&self, s: f64) -> (f64, f64) {
+        if self.points.len() < 2 {
+            return self.points.first().copied().unwrap_or((0.0, 0.0));
+        }
+        let s = s.clamp(0.0, self.length());
+        let i = self.segment_at(s);
+        let (x0, y0) = self.points[i];
+        let (x1, y1) = self.points[i + 1];
+        let t = self.fraction(i, s);
+        (x0 + (x1 - x0) * t, y0 + (y1 - y0) * t)
+    }
+
+    pub fn heading_at(&self, s: f64) -> f64 {
+        if self.points.len() < 2 {
+            return 0.0;
+        }
+        let i = self.segment_at(s.clamp(0.0, self.length()));
+        let (x0, y0) = self.points[i];
+        let (x1, y1) = self.points[i + 1];
+        (y1 - y0).atan2(x1 - x0)
+    }
+
+    // signed curvature at arc length `s`, positive when the path turns left
+    pub fn curvature_at(&self, s: f64) -> f64 {
+        if self.points.len() < 2 {
+            return 0.0;
+        }
+        let s = s.clamp(0.0, self.length());
+        let i = self.segment_at(s);
+        let t = self.fraction(i, s);
+        self.curvature[i] + (self.curvature[i + 1] - self.curvature[i]) * t
+    }
+
+    pub fn pose_at(&self, s: f64) -> Pose {
+        let (x, y) = self.point_at(s);
+        Pose::new(x, y, self.heading_at(s))
+    }
+
+    pub fn nearest(&self, x: f64, y: f64) -> Option<PathProjection> {
+        let mut best: Option<(f64, PathProjection)> = None;
+        for i in 0..self.points.len().saturating_sub(1) {
+            let (x0, y0) = self.points[i];
+            let (x1, y1) = self.points[i + 1];
+            let (dx, dy) = (x1 - x0, y1 - y0);
+            let length_sq = dx * dx + dy * dy;
+            if length_sq == 0.0 {
+                continue;
+            }
+            let t = (((x - x0) * dx + (y - y0) * dy) / length_sq).clamp(0.0, 1.0);
+            let (px, py) = (x0 + t * dx, y0 + t * dy);
+            let distance_sq = (x - px).powi(2) + (y - py).powi(2);
+            if best.is_none_or(|(best_sq, _)| distance_sq < best_sq) {
+                let heading = dy.atan2(dx);
+                // cross product of the segment direction and the offset gives the side
+                let lateral_error = (dx * (y - py) - dy * (x - px)) / length_sq.sqrt();
+                best = Some((
+                    distance_sq,
+                    PathProjection {
+                        s: self.cumulative[i] + t * length_sq.sqrt(),
+                        x: px,
+                        y: py,
+                        heading,
+                        lateral_error,
+                    },
+                ));
+            }
+        }
+        best.map(|(_, projection)| projection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_polyline_queries() {
+        let path = ReferencePath::new(vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]);
+        assert_eq!(path.length(), 20.0);
+        assert_eq!(path.point_at(15.0), (10.0, 5.0));
+        assert!((path.heading_at(15.0) - std::f64::consts::FRAC_PI_2).abs() < 1e-12);
+
+        let projection = path.nearest(5.0, 2.0).unwrap();
+        assert_eq!((projection.x, projection.y, projection.s), (5.0, 0.0, 5.0));
+        assert_eq!(projection.lateral_error, 2.0);
+        let projection = path.nearest(12.0, 6.0).unwrap();
+        assert_eq!(projection.lateral_error, -2.0);
+        assert_eq!(projection.s, 16.0);
+    }
+
+    #[test]
+    fn test_curvature_of_sampled_circle() {
+        let radius = 5.0;
+        let points = (0..=100)
+            .map(|i| {
+                let angle = i as f64 * 0.01 * std::f64::consts::PI;
+                (radius * angle.sin(), radius * (1.0 - angle.cos()))
+            })
+            .collect();
+        let path = ReferencePath::new(points);
+        for s in [0.0, 3.0, 7.5, path.length()] {
+            assert!((path.curvature_at(s) - 1.0 / radius).abs() < 1e-3);
+        }
+        assert!((min_turning_radius(2.0, 0.5) - 2.0 / 0.5_f64.tan()).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,218 @@
+use super::{valid_spacing, Pose, ReferencePath};
+
+// drops consecutive duplicates, which would give zero-length spline intervals
+fn distinct(waypoints: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let mut points: Vec<(f64, f64)> = Vec::with_capacity(waypoints.len());
+    for point in waypoints.iter() {
+        if points.last() != Some(point) {
+            points.push(*point);
+        }
+    }
+    points
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    (b.0 - a.0).hypot(b.1 - a.1)
+}
+
+// second derivatives of the natural cubic spline through (knots[i], values[i]),
+// from the tridiagonal system solved by the Thomas algorithm
+fn natural_second_derivatives(knots: &[f64], values: &[f64]) -> Vec<f64> {
+    let n = knots.len();
+    let mut second = vec![0.0; n];
+    if n < 3 {
+        return second;
+    }
+    let mut diagonal = vec![0.0; n];
+    let mut rhs = vec![0.0; n];
+    let mut upper = vec![0.0; n];
+    for i in 1..n - 1 {
+        let h0 = knots[i] - knots[i - 1];
+        let h1 = knots[i + 1] - knots[i];
+        let lower = h0 / 6.0;
+        diagonal[i] = (h0 + h1) / 3.0;
+        upper[i] = h1 / 6.0;
+        rhs[i] = (values[i + 1] - values[i]) / h1 - (values[i] - values[i - 1]) / h0;
+        if i > 1 {
+            let factor = lower / diagonal[i - 1];
+            diagonal[i] -= factor * upper[i - 1];
+            rhs[i] -= factor * rhs[i - 1];
+        }
+    }
+    for i in (1..n - 1).rev() {
+        second[i] = (rhs[i] - upper[i] * second[i + 1]) / diagonal[i];
+    }
+    second
+}
+
+fn cubic_value(knots: &[f64], values: &[f64], second: &[f64], i: usize, t: f64) -> f64 {
+    let h = knots[i + 1] - knots[i];
+    let a = (knots[i + 1] - t) / h;
+    let b = (t - knots[i]) / h;
+    a * values[i]
+        + b * values[i + 1]
+        + ((a * a * a - a) * second[i] + (b * b * b - b) * second[i + 1]) * h * h / 6.0
+}
+
+impl ReferencePath {
+    // natural cubic spline through `waypoints`, parameterised by chord length and
+    // sampled about every `spacing` metres; empty unless the spacing is positive
+    pub fn cubic_spline(waypoints: &[(f64, f64)], spacing: f64) -> Self {
+        if !valid_spacing(spacing) {
+            return Self::new(Vec::new());
+        }
+        let points = distinct(waypoints);
+        if points.len() < 2 {
+            return Self::new(points);
+        }
+        let mut knots = vec![0.0];
+        for i in 1..points.len() {
+            knots.push(knots[i - 1] + distance(points[i - 1], points[i]));
+        }
+        let xs: Vec<f64> = points.iter().map(|p| p.0).collect();
+        let ys: Vec<f64> = points.iter().map(|p| p.1).collect();
+        let second_x = natural_second_derivatives(&knots, &xs);
+        let second_y = natural_second_derivatives(&knots, &ys);
+
+        let mut samples = vec![points[0]];
+        for i in 0..points.len() - 1 {
+            let pieces = ((knots[i + 1] - knots[i]) / spacing).ceil().max(1.0) as usize;
+            for k in 1..=pieces {
+                let t = knots[i] + (knots[i + 1] - knots[i]) * k as f64 / pieces as f64;
+                samples.push((
+                    cubic_value(&knots, &xs, &second_x, i, t),
+                    cubic_value(&knots, &ys, &second_y, i, t),
+                ));
+            }
+        }
+        Self::new(samples)
+    }
+
+    // uniform Catmull-Rom spline through `waypoints`; unlike the cubic spline each piece only
+    // depends on its four nearest waypoints. The end tangents point at the neighbouring waypoint.
+    pub fn catmull_rom(waypoints: &[(f64, f64)], spacing: f64) -> Self {
+        if !valid_spacing(spacing) {
+            return Self::new(Vec::new());
+        }
+        let points = distinct(waypoints);
+        let n = points.len();
+        if n < 2 {
+            return Self::new(points);
+        }
+        let tangent = |i: usize| {
+            let (before, after) = (points[i.saturating_sub(1)], points[(i + 1).min(n - 1)]);
+            let scale = if i == 0 || i == n - 1 { 1.0 } else { 0.5 };
+            ((after.0 - before.0) * scale, (after.1 - before.1) * scale)
+        };
+        let mut samples = vec![points[0]];
+        for i in 0..n - 1 {
+            let (p0, p1) = (points[i], points[i + 1]);
+            let (m0, m1) = (tangent(i), tangent(i + 1));
+            let pieces = (distance(p0, p1) / spacing).ceil().max(1.0) as usize;
+            for k in 1..=pieces {
+                // cubic Hermite basis
+                let t = k as f64 / pieces as f64;
+                let (t2, t3) = (t * t, t * t * t);
+                let h00 = 2.0 * t3 - 3.0 * t2 + 1.0;
+                let h10 = t3 - 2.0 * t2 + t;
+                let h01 = -2.0 * t3 + 3.0 * t2;
+                let h11 = t3 - t2;
+                samples.push((
+                    h00 * p0.0 + h10 * m0.0 + h01 * p1.0 + h11 * m1.0,
+                    h00 * p0.1 + h10 * m0.1 + h01 * p1.1 + h11 * m1.1,
+                ));
+            }
+        }
+        Self::new(samples)
+    }
+
+    // clothoid (Euler spiral) of `length` metres from `start`, whose curvature changes linearly
+    // from `start_curvature` to `end_curvature`
+    pub fn clothoid(
+        start: Pose,
+        start_curvature: f64,
+        end_curvature: f64,
+        length: f64,
+        spacing: f64,
+    ) -> Self {
+        if !valid_spacing(spacing) {
+            return Self::new(Vec::new());
+        }
+        let sharpness = if length > 0.0 {
+            (end_curvature - start_curvature) / length
+        } else {
+            0.0
+        };
+        let heading = |s: f64| start.heading + start_curvature * s + 0.5 * sharpness * s * s;
+        let pieces = (length / spacing).ceil().max(1.0) as usize;
+        let step = length / pieces as f64;
+        let mut samples = vec![(start.x, start.y)];
+        let (mut x, mut y) = (start.x, start.y);
+        for k in 0..pieces {
+            // Simpson's rule for the position integral over one piece
+            let (s0, s1) = (k as f64 * step, (k + 1) as f64 * step);
+            let (a, m, b) = (heading(s0), heading(0.5 * (s0 + s1)), heading(s1));
+            x += step / 6.0 * (a.cos() + 4.0 * m.cos() + b.cos());
+            y += step / 6.0 * (a.sin() + 4.0 * m.sin() + b.sin());
+            samples.push((x, y));
+        }
+        Self::new(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_splines_pass_through_waypoints() {
+        let waypoints = [(0.0, 0.0), (10.0, 5.0), (20.0, 0.0), (30.0, 5.0)];
+        for path in [
+            ReferencePath::cubic_spline(&waypoints, 0.5),
+            ReferencePath::catmull_rom(&waypoints, 0.5),
+        ] {
+            for waypoint in waypoints.iter() {
+                let projection = path.nearest(waypoint.0, waypoint.1).unwrap();
+                assert!(projection.lateral_error.abs() < 1e-9);
+            }
+            // the middle waypoint is a crest, so the path turns right there
+            let crest = path.nearest(10.0, 5.0).unwrap().s;
+            assert!(path.curvature_at(crest) < 0.0);
+        }
+    }
+
+    #[test]
+    fn test_clothoid_curvature_and_heading() {
+        let start = Pose::new(1.0, 2.0, 0.5);
+        let length = 20.0;
+        let path = ReferencePath::clothoid(start, 0.0, 0.2, length, 0.05);
+        assert!((path.length() - length).abs() < 1e-3);
+        assert!((path.curvature_at(10.0) - 0.1).abs() < 1e-3);
+        // the heading gained is the integral of the curvature
+        let end_heading = path.heading_at(length);
+        assert!((end_heading - (0.5 + 0.5 * 0.2 * length)).abs() < 0.01);
+
+        // a circle is a clothoid with constant curvature
+        let circle = ReferencePath::clothoid(Pose::new(0.0, 0.0, 0.0), 0.25, 0.25, 8.0 * PI, 0.01);
+        let (x, y) = circle.point_at(circle.length());
+        assert!(x.hypot(y) < 1e-6);
+    }
+
+    #[test]
+    fn test_non_positive_spacing_gives_empty_path() {
+        let waypoints = [(0.0, 0.0), (10.0, 5.0), (20.0, 0.0)];
+        let start = Pose::new(0.0, 0.0, 0.0);
+        for spacing in [0.0, -1.0, f64::NAN] {
+            assert!(ReferencePath::cubic_spline(&waypoints, spacing)
+                .points
+                .is_empty());
+            assert!(ReferencePath::catmull_rom(&waypoints, spacing)
+                .points
+                .is_empty());
+            assert!(ReferencePath::clothoid(start, 0.0, 0.2, 20.0, spacing)
+                .points
+                .is_empty());
+        }
+    }
+}
@@ -0,0 +1,492 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use image::{Rgba, RgbaImage};
+use imageproc::drawing::draw_line_segment_mut;
+use nalgebra::Matrix2;
+
+use crate::camera::Camera;
+use crate::sensors::GPS::XYZValues;
+use crate::state::{CarState, Rectangular};
+use crate::world::{VehicleHistory, VehicleSnapshot};
+
+// the estimate's uncertainty fades out with the number of standard deviations
+const SIGMA_COLORS: [(f64, Rgba<u8>); 3] = [
+    (1.0, Rgba([0, 160, 0, 255])),
+    (2.0, Rgba([0, 160, 0, 160])),
+    (3.0, Rgba([0, 160, 0, 80])),
+];
+const HEADING_COLOR: Rgba<u8> = Rgba([0, 120, 0, 200]);
+const GPS_COLOR: Rgba<u8> = Rgba([255, 140, 0, 255]);
+// length of the heading wedge and half the size of the GPS fix marker, in pixels regardless of
+// the zoom
+const HEADING_WEDGE_RADIUS: f64 = 30.0;
+const GPS_MARKER_SIZE: f64 = 3.0;
+// standard deviations of yaw covered by the heading wedge on either side of the estimate
+const HEADING_WEDGE_SIGMA: f64 = 2.0;
+const OUTLINE_SEGMENTS: usize = 48;
+const GROUND_TRUTH_TRAIL_COLOR: Rgba<u8> = Rgba([0, 0, 255, 255]);
+const DEAD_RECKONING_TRAIL_COLOR: Rgba<u8> = Rgba([255, 0, 0, 255]);
+const ESTIMATE_TRAIL_COLOR: Rgba<u8> = Rgba([0, 160, 0, 255]);
+const SMOOTHED_TRAIL_COLOR: Rgba<u8> = Rgba([160, 0, 200, 255]);
+const GPS_CLOUD_SIZE: f64 = 1.0;
+const GRID_COLOR: Rgba<u8> = Rgba([225, 225, 225, 255]);
+const AXIS_COLOR: Rgba<u8> = Rgba([170, 170, 170, 255]);
+const SCALE_BAR_COLOR: Rgba<u8> = Rgba([0, 0, 0, 255]);
+// distance of the scale bar from the bottom left corner of the screen, and its tick height, in
+// pixels
+const SCALE_BAR_MARGIN: f64 = 20.0;
+const SCALE_BAR_TICK: f64 = 5.0;
+
+// which trails to draw behind every vehicle, and over how many steps
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrailOptions {
+    pub ground_truth: bool,
+    pub dead_reckoning: bool,
+    pub estimate: bool,
+    pub smoothed: bool,
+    // the GPS fixes delivered so far as a point cloud
+    pub gps: bool,
+    // steps of history drawn, fading out towards the oldest; the whole run when None
+    pub length: Option<usize>,
+}
+
+impl TrailOptions {
+    pub const NAMES: [&'static str; 5] = ["truth", "dead-reckoning", "estimate", "smoothed", "gps"];
+
+    pub fn none() -> Self {
+        Self {
+            ground_truth: false,
+            dead_reckoning: false,
+            estimate: false,
+            smoothed: false,
+            gps: false,
+            length: None,
+        }
+    }
+}
+
+impl Default for TrailOptions {
+    fn default() -> Self {
+        Self {
+            ground_truth: true,
+            estimate: true,
+            gps: true,
+            ..Self::none()
+        }
+    }
+}
+
+// a comma separated list of trail names, or "none"
+impl FromStr for TrailOptions {
+    type Err = String;
+
+    fn from_str(list: &str) -> Result<Self, Self::Err> {
+        let mut options = Self::none();
+        for name in list.split(',').map(str::trim) {
+            match name {
+                "none" => {}
+                "truth" => options.ground_truth = true,
+                "dead-reckoning" => options.dead_reckoning = true,
+                "estimate" => options.estimate = true,
+                "smoothed" => options.smoothed = true,
+                "gps" => options.gps = true,
+                _ => {
+                    return Err(format!(
+                        "unknown trail '{}', expected none or some of {}",
+                        name,
+                        Self::NAMES.join(", ")
+                    ))
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+// what the window draws besides the vehicles and their estimates
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layers {
+    pub grid: bool,
+    pub uncertainty: bool,
+    pub gps_fix: bool,
+    // the pose dead-reckoned from the IMU alone
+    pub dead_reckoning: bool,
+    pub trails: TrailOptions,
+}
+
+impl Layers {
+    // in the order of `toggle`
+    pub const NAMES: [&'static str; 9] = [
+        "grid",
+        "uncertainty",
+        "gps fix",
+        "dead reckoning",
+        "truth trail",
+        "dead reckoning trail",
+        "estimate trail",
+        "smoothed trail",
+        "gps trail",
+    ];
+
+    pub fn new(trails: TrailOptions) -> Self {
+        Self {
+            grid: true,
+            uncertainty: true,
+            gps_fix: true,
+            dead_reckoning: true,
+            trails,
+        }
+    }
+
+    // switches the layer at `index` of `NAMES` on or off; returns false for an unknown index
+    pub fn toggle(&mut self, index: usize) -> bool {
+        let layer = match index {
+            0 => &mut self.grid,
+            1 => &mut self.uncertainty,
+            2 => &mut self.gps_fix,
+            3 => &mut self.dead_reckoning,
+            4 => &mut self.trails.ground_truth,
+            5 => &mut self.trails.dead_reckoning,
+            6 => &mut self.trails.estimate,
+            7 => &mut self.trails.smoothed,
+            8 => &mut self.trails.gps,
+            _ => return false,
+        };
+        *layer = !*layer;
+        true
+    }
+}
+
+// the color with its opacity scaled down for older parts of a trail; `age` runs from 0 for the
+// newest to 1 for the oldest
+fn faded(color: Rgba<u8>, age: f64) -> Rgba<u8> {
+    let Rgba([r, g, b, a]) = color;
+    Rgba([
+        r,
+        g,
+        b,
+        (a as f64 * (1.0 - 0.8 * age.clamp(0.0, 1.0))) as u8,
+    ])
+}
+
+fn draw_trail(image_buffer: &mut RgbaImage, camera: &Camera, states: &[CarState], color: Rgba<u8>) {
+    let count = states.len();
+    for (i, pair) in states.windows(2).enumerate() {
+        let age = 1.0 - (i + 1) as f64 / (count - 1) as f64;
+        draw_polyline(
+            image_buffer,
+            camera,
+            &[(pair[0].x, pair[0].y), (pair[1].x, pair[1].y)],
+            faded(color, age),
+        );
+    }
+}
+
+// the recent part of each enabled trail of one vehicle; `smoothed` is the estimator's smoothed
+// trajectory, when it has one
+pub fn draw_trails(
+    image_buffer: &mut RgbaImage,
+    camera: &Camera,
+    history: &VehicleHistory,
+    smoothed: Option<&[CarState]>,
+    options: &TrailOptions,
+) {
+    let recent = |states: &'_ [CarState]| -> Vec<CarState> {
+        let start = options
+            .length
+            .map_or(0, |length| states.len().saturating_sub(length));
+        states[start..].to_vec()
+    };
+    if options.ground_truth {
+        let states = recent(&history.ground_truth);
+        draw_trail(image_buffer, camera, &states, GROUND_TRUTH_TRAIL_COLOR);
+    }
+    if options.dead_reckoning {
+        let states = recent(&history.dead_reckoning);
+        draw_trail(image_buffer, camera, &states, DEAD_RECKONING_TRAIL_COLOR);
+    }
+    if options.estimate {
+        let states = recent(&history.estimate);
+        draw_trail(image_buffer, camera, &states, ESTIMATE_TRAIL_COLOR);
+    }
+    if let (true, Some(smoothed)) = (options.smoothed, smoothed) {
+        draw_trail(
+            image_buffer,
+            camera,
+            &recent(smoothed),
+            SMOOTHED_TRAIL_COLOR,
+        );
+    }
+    if options.gps {
+        // fixes are kept over the same span of time as the trails
+        let truth = recent(&history.ground_truth);
+        let (Some(first), Some(last)) = (truth.first(), truth.last()) else {
+            return;
+        };
+        let span = (last.time_stamp - first.time_stamp).max(f64::EPSILON);
+        for fix in &history.gps_fixes {
+            if fix.time_stamp < first.time_stamp {
+                continue;
+            }
+            let color = faded(GPS_COLOR, (last.time_stamp - fix.time_stamp) / span);
+            draw_cross(image_buffer, camera, (fix.x, fix.y), GPS_CLOUD_SIZE, color);
+        }
+    }
+}
+
+// the points a 2D gaussian puts `sigma` standard deviations from its mean, i.e. at a
+// Mahalanobis distance of `sigma`
+#[derive(Debug, Copy, Clone)]
+pub struct Ellipse {
+    pub center: (f64, f64),
+    // half the length of the major and of the minor axis
+    pub semi_axes: (f64, f64),
+    // angle of the major axis from the x axis
+    pub angle: f64,
+}
+
+impl Ellipse {
+    pub fn from_covariance(center: (f64, f64), covariance: &Matrix2<f64>, sigma: f64) -> Self {
+        // eigenvalues of a symmetric 2x2 matrix in closed form; a covariance that has lost
+        // positive definiteness is drawn as if its negative variances were zero
+        let (a, b, c) = (covariance[(0, 0)], covariance[(1, 1)], covariance[(0, 1)]);
+        let mean = (a + b) / 2.0;
+        let spread = (((a - b) / 2.0).powi(2) + c * c).sqrt();
+        let (major, minor) = ((mean + spread).max(0.0), (mean - spread).max(0.0));
+        Self {
+            center,
+            semi_axes: (sigma * major.sqrt(), sigma * minor.sqrt()),
+            angle: 0.5 * (2.0 * c).atan2(a - b),
+        }
+    }
+
+    pub fn circle(center: (f64, f64), radius: f64) -> Self {
+        Self {
+            center,
+            semi_axes: (radius, radius),
+            angle: 0.0,
+        }
+    }
+
+    // closed outline, the first point repeated at the end
+    pub fn outline(&self, segments: usize) -> Vec<(f64, f64)> {
+        let (sin, cos) = self.angle.sin_cos();
+        (0..=segments)
+            .map(|i| {
+                let t = 2.0 * PI * i as f64 / segments as f64;
+                let (u, v) = (self.semi_axes.0 * t.cos(), self.semi_axes.1 * t.sin());
+                (
+                    self.center.0 + u * cos - v * sin,
+                    self.center.1 + u * sin + v * cos,
+                )
+            })
+            .collect()
+    }
+}
+
+// closed outline of a circular sector at `center` spanning `sigma` standard deviations of yaw on
+// either side of `yaw`; it widens to a full circle when the heading is unknown
+pub fn heading_wedge(
+    center: (f64, f64),
+    yaw: f64,
+    yaw_variance: f64,
+    sigma: f64,
+    radius: f64,
+    segments: usize,
+) -> Vec<(f64, f64)> {
+    let half_angle = (sigma * yaw_variance.max(0.0).sqrt()).min(PI);
+    let mut points = vec![center];
+    points.extend((0..=segments).map(|i| {
+        let angle = yaw - half_angle + 2.0 * half_angle * i as f64 / segments as f64;
+        (
+            center.0 + radius * angle.cos(),
+            center.1 + radius * angle.sin(),
+        )
+    }));
+    points.push(center);
+    points
+}
+
+// a polyline through world points
+fn draw_polyline(
+    image_buffer: &mut RgbaImage,
+    camera: &Camera,
+    points: &[(f64, f64)],
+    color: Rgba<u8>,
+) {
+    let points: Vec<(f64, f64)> = points
+        .iter()
+        .map(|point| camera.to_screen(*point))
+        .collect();
+    draw_screen_polyline(image_buffer, &points, color);
+}
+
+fn draw_screen_polyline(image_buffer: &mut RgbaImage, points: &[(f64, f64)], color: Rgba<u8>) {
+    for pair in points.windows(2) {
+        draw_line_segment_mut(
+            image_buffer,
+            (pair[0].0 as f32, pair[0].1 as f32),
+            (pair[1].0 as f32, pair[1].1 as f32),
+            color,
+        );
+    }
+}
+
+// a cross of `size` pixels either way at a world point
+fn draw_cross(
+    image_buffer: &mut RgbaImage,
+    camera: &Camera,
+    point: (f64, f64),
+    size: f64,
+    color: Rgba<u8>,
+) {
+    let (u, v) = camera.to_screen(point);
+    draw_screen_polyline(image_buffer, &[(u - size, v), (u + size, v)], color);
+    draw_screen_polyline(image_buffer, &[(u, v - size), (u, v + size)], color);
+}
+
+// the outline of a vehicle or obstacle
+pub fn draw_footprint(image_buffer: &mut RgbaImage, camera: &Camera, rect: &Rectangular) {
+    let mut corners = rect.corners().to_vec();
+    corners.push(corners[0]);
+    draw_polyline(image_buffer, camera, &corners, rect.color.to_rgba());
+}
+
+// lines at every multiple of the grid spacing that is on screen, the world axes darker
+pub fn draw_grid(image_buffer: &mut RgbaImage, camera: &Camera) {
+    let spacing = camera.grid_spacing();
+    let (min_x, min_y, max_x, max_y) = camera.visible_bounds();
+    let color = |line: f64| if line == 0.0 { AXIS_COLOR } else { GRID_COLOR };
+    for i in (min_x / spacing).floor() as i64..=(max_x / spacing).ceil() as i64 {
+        let x = i as f64 * spacing;
+        draw_polyline(image_buffer, camera, &[(x, min_y), (x, max_y)], color(x));
+    }
+    for i in (min_y / spacing).floor() as i64..=(max_y / spacing).ceil() as i64 {
+        let y = i as f64 * spacing;
+        draw_polyline(image_buffer, camera, &[(min_x, y), (max_x, y)], color(y));
+    }
+}
+
+// a bar one grid spacing long in the bottom left corner
+pub fn draw_scale_bar(image_buffer: &mut RgbaImage, camera: &Camera) {
+    let length = camera.grid_spacing() * camera.pixels_per_meter;
+    let (left, bottom) = (SCALE_BAR_MARGIN, camera.screen_height - SCALE_BAR_MARGIN);
+    let right = left + length;
+    draw_screen_polyline(
+        image_buffer,
+        &[
+            (left, bottom - SCALE_BAR_TICK),
+            (left, bottom),
+            (right, bottom),
+            (right, bottom - SCALE_BAR_TICK),
+        ],
+        SCALE_BAR_COLOR,
+    );
+}
+
+// the 1, 2 and 3 sigma position ellipses of the estimate and its heading wedge
+pub fn draw_uncertainty(image_buffer: &mut RgbaImage, camera: &Camera, snapshot: &VehicleSnapshot) {
+    let estimate = &snapshot.estimate;
+    let center = (estimate.x, estimate.y);
+    let position = snapshot.covariance.fixed_view::<2, 2>(0, 0).into_owned();
+    for (sigma, color) in SIGMA_COLORS {
+        let ellipse = Ellipse::from_covariance(center, &position, sigma);
+        draw_polyline(
+            image_buffer,
+            camera,
+            &ellipse.outline(OUTLINE_SEGMENTS),
+            color,
+        );
+    }
+    let wedge = heading_wedge(
+        center,
+        estimate.yaw,
+        snapshot.covariance[(2, 2)],
+        HEADING_WEDGE_SIGMA,
+        HEADING_WEDGE_RADIUS / camera.pixels_per_meter,
+        OUTLINE_SEGMENTS / 4,
+    );
+    draw_polyline(image_buffer, camera, &wedge, HEADING_COLOR);
+}
+
+// a cross at the fix with a circle of one standard deviation of its noise
+pub fn draw_gps_fix(image_buffer: &mut RgbaImage, camera: &Camera, fix: &XYZValues, std_dev: f64) {
+    draw_cross(
+        image_buffer,
+        camera,
+        (fix.x, fix.y),
+        GPS_MARKER_SIZE,
+        GPS_COLOR,
+    );
+    let circle = Ellipse::circle((fix.x, fix.y), std_dev);
+    draw_polyline(
+        image_buffer,
+        camera,
+        &circle.outline(OUTLINE_SEGMENTS),
+        GPS_COLOR,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ellipse_outline_lies_at_sigma_mahalanobis_distance() {
+        let covariance = Matrix2::new(4.0, 1.5, 1.5, 1.0);
+        let inverse = covariance.try_inverse().unwrap();
+        for sigma in [1.0, 2.0, 3.0] {
+            let ellipse = Ellipse::from_covariance((10.0, -5.0), &covariance, sigma);
+            assert!(ellipse.semi_axes.0 >= ellipse.semi_axes.1);
+            for (x, y) in ellipse.outline(16) {
+                let offset = nalgebra::Vector2::new(x - 10.0, y + 5.0);
+                let distance = (offset.transpose() * inverse * offset)[0].sqrt();
+                assert!((distance - sigma).abs() < 1e-9, "{} at {}", distance, sigma);
+            }
+        }
+        // uncorrelated with the larger variance along y
+        let ellipse = Ellipse::from_covariance((0.0, 0.0), &Matrix2::new(1.0, 0.0, 0.0, 9.0), 1.0);
+        assert!((ellipse.semi_axes.0 - 3.0).abs() < 1e-12);
+        assert!((ellipse.semi_axes.1 - 1.0).abs() < 1e-12);
+        assert!((ellipse.angle.abs() - PI / 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_parses_trail_lists_and_fades_older_segments() {
+        let options: TrailOptions = "truth, smoothed,gps".parse().unwrap();
+        assert!(options.ground_truth && options.smoothed && options.gps);
+        assert!(!options.dead_reckoning && !options.estimate);
+        assert_eq!("none".parse::<TrailOptions>(), Ok(TrailOptions::none()));
+        assert!("truth,radar".parse::<TrailOptions>().is_err());
+
+        assert_eq!(faded(GPS_COLOR, 0.0), GPS_COLOR);
+        assert!(faded(GPS_COLOR, 1.0).0[3] < faded(GPS_COLOR, 0.5).0[3]);
+        assert!(faded(GPS_COLOR, 1.0).0[3] > 0);
+    }
+
+    #[test]
+    fn test_toggles_layers_by_index() {
+        let mut layers = Layers::new(TrailOptions::none());
+        assert!(layers.toggle(0) && !layers.grid);
+        assert!(layers.toggle(7) && layers.trails.smoothed);
+        assert!(layers.toggle(7) && !layers.trails.smoothed);
+        assert!(!layers.toggle(Layers::NAMES.len()));
+    }
+
+    #[test]
+    fn test_heading_wedge_spans_sigma_of_yaw() {
+        let wedge = heading_wedge((1.0, 2.0), 0.5, 0.01, 2.0, 10.0, 8);
+        assert_eq!(wedge.first(), Some(&(1.0, 2.0)));
+        assert_eq!(wedge.last(), Some(&(1.0, 2.0)));
+        let angles: Vec<f64> = wedge[1..wedge.len() - 1]
+            .iter()
+            .map(|(x, y)| (y - 2.0).atan2(x - 1.0))
+            .collect();
+        assert!((angles[0] - 0.3).abs() < 1e-12);
+        assert!((angles[angles.len() - 1] - 0.7).abs() < 1e-12);
+        // an unknown heading is a full circle rather than wrapping around
+        let wedge = heading_wedge((0.0, 0.0), 0.0, 100.0, 2.0, 10.0, 8);
+        assert!((wedge[1].0 + 10.0).abs() < 1e-9 && (wedge[9].0 + 10.0).abs() < 1e-9);
+    }
+}
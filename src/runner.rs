@@ -0,0 +1,415 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::car::Car;
+use crate::controller::ConstantControl;
+use crate::kalman_filter::EstimatorKind;
+use crate::render::TrailOptions;
+use crate::scenario::Scenario;
+use crate::sensor_measurement::Calibration;
+use crate::sensors::IMU::ImuBiases;
+use crate::world::{VehicleSnapshot, World};
+
+pub const USAGE: &str = "\
+usage: kalman_filter [options]
+
+options:
+    --headless             run without opening a window
+    --duration <seconds>   simulated time to run (default 200)
+    --dt <seconds>         simulation step (default 0.1)
+    --seed <u64>           seed for all sensor and actuator noise
+    --scenario <file>      load vehicles and settings from a .toml/.yaml scenario file;
+                           the options above and below override its values
+    --estimator <name>     ekf | sqrt-ekf | information | es-ekf |
+                           dead-reckoning (default ekf)
+    --output <dir>         write per-vehicle CSV logs into this directory
+    --trails <list>        trails drawn behind each vehicle, comma separated: none or
+                           truth, dead-reckoning, estimate, smoothed, gps
+                           (default truth,estimate,gps)
+    --trail-length <steps> steps of history the trails show (default the whole run)
+    --help                 print this message
+
+window controls:
+    mouse wheel            zoom about the cursor
+    left drag              pan, which stops following
+    f                      follow each vehicle in turn, then none
+    h                      toggle heading-up while following
+    space                  pause or resume
+    s, .                   pause and advance a single step
+    +, -                   double or halve the simulation speed
+    r                      restart the simulation
+    1-9                    toggle grid, uncertainty, gps fix, dead reckoning, truth trail,
+                           dead reckoning trail, estimate trail, smoothed trail, gps trail
+    t                      drive the followed vehicle with the arrow keys, or stop driving";
+
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    pub headless: bool,
+    pub help: bool,
+    // unset values come from the scenario, or the built-in demo defaults
+    pub duration: Option<f64>,
+    pub dt: Option<f64>,
+    pub seed: Option<u64>,
+    pub scenario: Option<PathBuf>,
+    pub estimator: Option<EstimatorKind>,
+    pub output_dir: Option<PathBuf>,
+    pub trails: TrailOptions,
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for {}", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, flag))
+}
+
+impl RunOptions {
+    // parses the command line, without the program name
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            match flag.as_str() {
+                "--headless" => options.headless = true,
+                "--help" | "-h" => options.help = true,
+                "--duration" => options.duration = Some(parse_value(&flag, args.next())?),
+                "--dt" => options.dt = Some(parse_value(&flag, args.next())?),
+                "--seed" => options.seed = Some(parse_value(&flag, args.next())?),
+                "--scenario" => options.scenario = Some(parse_value(&flag, args.next())?),
+                "--estimator" => options.estimator = Some(parse_value(&flag, args.next())?),
+                "--output" => options.output_dir = Some(parse_value(&flag, args.next())?),
+                "--trails" => {
+                    options.trails = TrailOptions {
+                        length: options.trails.length,
+                        ..parse_value(&flag, args.next())?
+                    }
+                }
+                "--trail-length" => {
+                    options.trails.length = Some(parse_value(&flag, args.next())?)
+                }
+                _ => return Err(format!("unknown option '{}'\n\n{}", flag, USAGE)),
+            }
+        }
+        if let Some(dt) = options.dt {
+            if dt.is_nan() || dt <= 0.0 {
+                return Err(format!("--dt must be positive, got {}", dt));
+            }
+        }
+        if let Some(duration) = options.duration {
+            if duration.is_nan() || duration < 0.0 {
+                return Err(format!("--duration must not be negative, got {}", duration));
+            }
+        }
+        Ok(options)
+    }
+}
+
+// a world ready to run together with how many steps to run it for
+pub struct Simulation {
+    pub world: World,
+    pub steps: usize,
+}
+
+fn demo_world(dt: f64, seed: Option<u64>, estimator: Option<EstimatorKind>) -> World {
+    let mut world = World::new(dt);
+    world.seed = seed;
+    if let Some(kind) = estimator {
+        world.estimator.kind = kind;
+    }
+    world.add_vehicle(
+        Car::new(0.0, 240.0, 0.0, 20.0, 40.0, 0.0, 2.0, 0.5, dt, None),
+        Box::new(ConstantControl::new(0.1, 0.001)),
+    );
+    world.add_vehicle(
+        Car::new(0.0, 120.0, 0.0, 20.0, 40.0, 2.0, 2.0, 0.5, dt, None),
+        Box::new(ConstantControl::new(0.0, -0.002)),
+    );
+    world
+}
+
+// the simulation described by the options: a scenario file when given, the demo setup otherwise
+pub fn build_simulation(options: &RunOptions) -> Result<Simulation, String> {
+    let (world, duration) = match &options.scenario {
+        Some(path) => {
+            let mut scenario = Scenario::load(path)?;
+            if let Some(dt) = options.dt {
+                scenario.dt = dt;
+            }
+            if let Some(seed) = options.seed {
+                scenario.seed = Some(seed);
+            }
+            if let Some(kind) = options.estimator {
+                scenario.estimator.kind = kind.name().to_string();
+            }
+            (scenario.build_world()?, scenario.duration)
+        }
+        None => (
+            demo_world(options.dt.unwrap_or(0.1), options.seed, options.estimator),
+            200.0,
+        ),
+    };
+    let duration = options.duration.unwrap_or(duration);
+    Ok(Simulation {
+        steps: (duration / world.dt).round() as usize,
+        world,
+    })
+}
+
+// writes one CSV row per vehicle and step
+pub struct TrajectoryLogger {
+    writers: Vec<BufWriter<File>>,
+}
+
+impl TrajectoryLogger {
+    pub fn create(dir: &PathBuf, vehicles: usize) -> Result<Self, String> {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("cannot create output directory '{}': {}", dir.display(), e))?;
+        let mut writers = Vec::new();
+        for id in 0..vehicles {
+            let path = dir.join(format!("vehicle_{}.csv", id));
+            let file = File::create(&path)
+                .map_err(|e| format!("cannot create '{}': {}", path.display(), e))?;
+            let mut writer = BufWriter::new(file);
+            writeln!(
+                writer,
+                "time,true_x,true_y,true_yaw,true_velocity,\
+                 measured_x,measured_y,measured_yaw,measured_velocity,\
+                 estimate_x,estimate_y,estimate_yaw,estimate_velocity,\
+                 var_x,var_y,var_yaw,var_velocity"
+            )
+            .map_err(|e| e.to_string())?;
+            writers.push(writer);
+        }
+        Ok(Self { writers })
+    }
+
+    pub fn log(&mut self, snapshots: &[VehicleSnapshot]) -> Result<(), String> {
+        for (snapshot, writer) in snapshots.iter().zip(self.writers.iter_mut()) {
+            let (t, m, e, p) = (
+                &snapshot.ground_truth,
+                &snapshot.measured,
+                &snapshot.estimate,
+                &snapshot.covariance,
+            );
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                snapshot.time,
+                t.x,
+                t.y,
+                t.yaw,
+                t.velocity,
+                m.x,
+                m.y,
+                m.yaw,
+                m.velocity,
+                e.x,
+                e.y,
+                e.yaw,
+                e.velocity,
+                p[(0, 0)],
+                p[(1, 1)],
+                p[(2, 2)],
+                p[(3, 3)]
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        for writer in self.writers.iter_mut() {
+            writer.flush().map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RunSummary {
+    pub steps: usize,
+    pub time: f64,
+    pub collisions: usize,
+    // root mean square position error of the estimate, per vehicle
+    pub position_rmse: Vec<f64>,
+    // how often each vehicle's estimator found its covariance not positive definite
+    pub covariance_faults: Vec<usize>,
+    // estimated minus true IMU biases at the end, per vehicle whose estimator tracks them
+    pub imu_bias_errors: Vec<Option<ImuBiases>>,
+    // estimated minus true sensor calibration at the end, per vehicle whose estimator tracks it
+    pub calibration_errors: Vec<Option<Calibration>>,
+}
+
+// runs the simulation loop without a window
+pub fn run_headless(options: &RunOptions) -> Result<RunSummary, String> {
+    let mut simulation = build_simulation(options)?;
+    run_world(
+        &mut simulation.world,
+        simulation.steps,
+        options.output_dir.as_ref(),
+    )
+}
+
+pub fn run_world(
+    world: &mut World,
+    steps: usize,
+    output_dir: Option<&PathBuf>,
+) -> Result<RunSummary, String> {
+    let mut logger = match output_dir {
+        Some(dir) => Some(TrajectoryLogger::create(dir, world.vehicles.len())?),
+        None => None,
+    };
+    let mut squared_error = vec![0.0; world.vehicles.len()];
+    let mut collisions = 0;
+    for _ in 0..steps {
+        // a contact lasting several steps is one collision, as in the interactive log
+        collisions += world
+            .step()
+            .iter()
+            .filter(|collision| collision.started)
+            .count();
+        let snapshots = world.snapshots();
+        for snapshot in snapshots.iter() {
+            squared_error[snapshot.id] += (snapshot.estimate.x - snapshot.ground_truth.x).powi(2)
+                + (snapshot.estimate.y - snapshot.ground_truth.y).powi(2);
+        }
+        if let Some(logger) = logger.as_mut() {
+            logger.log(&snapshots)?;
+        }
+    }
+    if let Some(logger) = logger.as_mut() {
+        logger.flush()?;
+    }
+    Ok(RunSummary {
+        steps,
+        time: world.time,
+        collisions,
+        position_rmse: squared_error
+            .iter()
+            .map(|sum| (sum / steps.max(1) as f64).sqrt())
+            .collect(),
+        covariance_faults: world
+            .vehicles
+            .iter()
+            .map(|vehicle| vehicle.estimator.covariance_faults().len())
+            .collect(),
+        imu_bias_errors: world
+            .vehicles
+            .iter()
+            .map(|vehicle| {
+                let estimated = vehicle.estimator.imu_biases()?;
+                Some(estimated.difference(&vehicle.sensors.imu.bias))
+            })
+            .collect(),
+        calibration_errors: world
+            .vehicles
+            .iter()
+            .map(|vehicle| {
+                let estimated = vehicle.estimator.calibration()?;
+                Some(estimated.difference(&vehicle.sensors.calibration()))
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{Footprint, ReferencePoint};
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options = RunOptions::from_args(args(
+            "--headless --duration 5 --dt 0.05 --seed 7 --estimator dead-reckoning --output out",
+        ))
+        .unwrap();
+        assert!(options.headless);
+        assert_eq!(options.duration, Some(5.0));
+        assert_eq!(options.seed, Some(7));
+        assert_eq!(options.estimator, Some(EstimatorKind::DeadReckoning));
+        assert_eq!(options.output_dir, Some(PathBuf::from("out")));
+
+        assert!(RunOptions::from_args(args("--dt")).is_err());
+        assert!(RunOptions::from_args(args("--dt -1")).is_err());
+        assert!(RunOptions::from_args(args("--estimator ukf")).is_err());
+        assert!(RunOptions::from_args(args("--bogus")).is_err());
+
+        let options =
+            RunOptions::from_args(args("--trail-length 50 --trails smoothed,gps")).unwrap();
+        assert!(options.trails.smoothed && options.trails.gps && !options.trails.ground_truth);
+        assert_eq!(options.trails.length, Some(50));
+        assert!(RunOptions::from_args(args("--trails lidar")).is_err());
+    }
+
+    #[test]
+    fn test_headless_run_writes_logs() {
+        let dir = std::env::temp_dir().join(format!("kalman_filter_run_{}", std::process::id()));
+        let options = RunOptions::from_args(vec![
+            "--headless".to_string(),
+            "--duration".to_string(),
+            "2".to_string(),
+            "--seed".to_string(),
+            "1".to_string(),
+            "--output".to_string(),
+            dir.display().to_string(),
+        ])
+        .unwrap();
+        let summary = run_headless(&options).unwrap();
+        assert_eq!(summary.steps, 20);
+        assert_eq!(summary.position_rmse.len(), 2);
+        assert_eq!(summary.covariance_faults, vec![0, 0]);
+        let log = fs::read_to_string(dir.join("vehicle_0.csv")).unwrap();
+        assert_eq!(log.lines().count(), 21);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_scenario_with_overrides() {
+        let path = std::env::temp_dir().join(format!("kalman_filter_{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "duration = 3.0\ndt = 0.05\n[[vehicles]]\nvelocity = 1.0\n",
+        )
+        .unwrap();
+        let mut options = RunOptions {
+            scenario: Some(path.clone()),
+            duration: Some(1.0),
+            estimator: Some(EstimatorKind::DeadReckoning),
+            ..Default::default()
+        };
+        let simulation = build_simulation(&options).unwrap();
+        assert_eq!(simulation.steps, 20);
+        assert_eq!(simulation.world.vehicles.len(), 1);
+        assert_eq!(
+            simulation.world.vehicles[0].estimator.name(),
+            "dead-reckoning"
+        );
+        fs::remove_file(&path).unwrap();
+
+        options.scenario = Some(PathBuf::from("does/not/exist.toml"));
+        assert!(build_simulation(&options).is_err());
+    }
+
+    #[test]
+    fn test_collisions_are_counted_once_per_contact() {
+        let mut world = World::new(0.1);
+        let car = Car::new(0.0, 0.0, 0.0, 20.0, 40.0, 10.0, 2.0, 0.5, 0.1, None);
+        world.add_vehicle(car, Box::new(ConstantControl::new(0.0, 0.0)));
+        world.add_obstacle(Footprint::new(
+            60.0,
+            0.0,
+            0.0,
+            10.0,
+            10.0,
+            ReferencePoint::Center,
+        ));
+        // the car drives through the obstacle, overlapping it for several steps
+        let summary = run_world(&mut world, 100, None).unwrap();
+        assert_eq!(summary.collisions, 1);
+    }
+}
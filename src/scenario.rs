@@ -0,0 +1,1087 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::actuator::Actuator;
+use crate::car::{Car, KinematicBicycleModel};
+use crate::control_profile::{ControlProfile, Interpolation, Schedule};
+use crate::controller::{
+    ConstantControl, ControlSource, Controller, LateralController, PathFollower, Pid, PurePursuit,
+    Stanley,
+};
+use crate::kalman_filter::{EstimatorConfig, EstimatorKind};
+use crate::mpc::{Mpc, MpcConfig, MpcController, TrajectoryPoint};
+use crate::path::ReferencePath;
+use crate::sensor_measurement::{SensorClock, SensorSet, SensorTiming};
+use crate::sensors::IMU::ImuBiases;
+use crate::state::{CarColor, Footprint, ReferencePoint};
+use crate::stationary::StationaryDetector;
+use crate::world::World;
+
+// a complete simulation setup, loaded from a TOML or YAML file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default = "default_duration")]
+    pub duration: f64,
+    #[serde(default = "default_dt")]
+    pub dt: f64,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub estimator: EstimatorSpec,
+    pub vehicles: Vec<VehicleSpec>,
+    #[serde(default)]
+    pub obstacles: Vec<ObstacleSpec>,
+    // directory relative file references are resolved against
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+}
+
+fn default_duration() -> f64 {
+    200.0
+}
+
+fn default_dt() -> f64 {
+    0.1
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EstimatorSpec {
+    pub kind: String,
+    pub process_noise: [f64; 4],
+    pub control_noise: [f64; 2],
+    pub gps_noise: [f64; 3],
+    pub joseph_form: bool,
+    pub enforce_symmetry: bool,
+    pub imu_noise: [f64; 2],
+    pub imu_bias_walk: [f64; 2],
+    pub odometry_noise: f64,
+    pub estimate_imu_biases: bool,
+    pub estimate_calibration: bool,
+    pub estimate_time_offset: bool,
+    pub static_alignment: bool,
+}
+
+impl Default for EstimatorSpec {
+    fn default() -> Self {
+        let config = EstimatorConfig::default();
+        Self {
+            kind: "ekf".to_string(),
+            process_noise: config.process_noise,
+            control_noise: config.control_noise,
+            gps_noise: config.gps_noise,
+            joseph_form: config.joseph_form,
+            enforce_symmetry: config.enforce_symmetry,
+            imu_noise: config.imu_noise,
+            imu_bias_walk: config.imu_bias_walk,
+            odometry_noise: config.odometry_noise,
+            estimate_imu_biases: config.estimate_imu_biases,
+            estimate_calibration: config.estimate_calibration,
+            estimate_time_offset: config.estimate_time_offset,
+            static_alignment: config.static_alignment,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VehicleSpec {
+    pub x: f64,
+    pub y: f64,
+    pub yaw: f64,
+    pub velocity: f64,
+    pub length: f64,
+    pub width: f64,
+    pub wheelbase: f64,
+    pub max_steer: f64,
+    pub color: Option<String>,
+    pub control: ControlSpec,
+    // feed the controller the estimate instead of the ground truth
+    pub control_from_estimate: bool,
+    pub sensors: SensorsSpec,
+    pub actuators: ActuatorsSpec,
+}
+
+impl Default for VehicleSpec {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            yaw: 0.0,
+            velocity: 0.0,
+            length: 40.0,
+            width: 20.0,
+            wheelbase: 2.0,
+            max_steer: 0.5,
+            color: None,
+            control: ControlSpec::default(),
+            control_from_estimate: false,
+            sensors: SensorsSpec::default(),
+            actuators: ActuatorsSpec::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ControlSpec {
+    Constant {
+        #[serde(default)]
+        acceleration: f64,
+        #[serde(default)]
+        steering_angle: f64,
+    },
+    Schedule {
+        #[serde(default)]
+        acceleration: Vec<(f64, f64)>,
+        #[serde(default)]
+        steering: Vec<(f64, f64)>,
+        #[serde(default)]
+        interpolation: InterpolationSpec,
+    },
+    SineSweep {
+        #[serde(default)]
+        start_time: f64,
+        duration: f64,
+        amplitude: f64,
+        start_frequency: f64,
+        end_frequency: f64,
+        #[serde(default)]
+        acceleration: f64,
+    },
+    LaneChange {
+        #[serde(default)]
+        start_time: f64,
+        duration: f64,
+        offset: f64,
+        // defaults to the vehicle's initial velocity
+        speed: Option<f64>,
+    },
+    UTurn {
+        #[serde(default)]
+        start_time: f64,
+        steering_angle: f64,
+        // defaults to the vehicle's initial velocity
+        speed: Option<f64>,
+    },
+    // replays a `time,acceleration,steering_angle` CSV, relative to the scenario file
+    Replay {
+        file: PathBuf,
+    },
+    PathFollower {
+        waypoints: Vec<(f64, f64)>,
+        target_speed: f64,
+        #[serde(default)]
+        lateral: LateralSpec,
+        #[serde(default)]
+        speed_pid: PidSpec,
+    },
+    // model predictive control along the waypoints, driven at constant `target_speed`
+    Mpc {
+        waypoints: Vec<(f64, f64)>,
+        target_speed: f64,
+        #[serde(default = "default_mpc_horizon")]
+        horizon: usize,
+    },
+}
+
+fn default_mpc_horizon() -> usize {
+    MpcConfig::default().horizon
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LateralSpec {
+    PurePursuit {
+        #[serde(default = "default_lookahead_distance")]
+        lookahead_distance: f64,
+        #[serde(default = "default_lookahead_gain")]
+        lookahead_gain: f64,
+    },
+    Stanley {
+        #[serde(default = "default_stanley_gain")]
+        gain: f64,
+        #[serde(default = "default_stanley_softening")]
+        softening: f64,
+    },
+}
+
+fn default_lookahead_distance() -> f64 {
+    4.0
+}
+
+fn default_lookahead_gain() -> f64 {
+    0.5
+}
+
+fn default_stanley_gain() -> f64 {
+    1.0
+}
+
+fn default_stanley_softening() -> f64 {
+    1.0
+}
+
+impl Default for LateralSpec {
+    fn default() -> Self {
+        LateralSpec::PurePursuit {
+            lookahead_distance: default_lookahead_distance(),
+            lookahead_gain: default_lookahead_gain(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PidSpec {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+    pub max_acceleration: f64,
+    pub max_deceleration: f64,
+}
+
+impl Default for PidSpec {
+    fn default() -> Self {
+        Self {
+            kp: 1.0,
+            ki: 0.1,
+            kd: 0.0,
+            max_acceleration: 2.0,
+            max_deceleration: 3.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterpolationSpec {
+    Constant,
+    #[default]
+    Linear,
+}
+
+impl Default for ControlSpec {
+    fn default() -> Self {
+        ControlSpec::Constant {
+            acceleration: 0.0,
+            steering_angle: 0.0,
+        }
+    }
+}
+
+impl ControlSpec {
+    // builds the controller for `vehicle`, whose motion follows `model`;
+    // relative replay files are looked up in `base_dir`
+    pub fn build(
+        &self,
+        vehicle: &VehicleSpec,
+        model: &KinematicBicycleModel,
+        base_dir: &Path,
+    ) -> Result<Box<dyn Controller>, String> {
+        let speed = |speed: &Option<f64>| speed.unwrap_or(vehicle.velocity);
+        let controller: Box<dyn Controller> = match self {
+            ControlSpec::Constant {
+                acceleration,
+                steering_angle,
+            } => Box::new(ConstantControl::new(*acceleration, *steering_angle)),
+            ControlSpec::Schedule {
+                acceleration,
+                steering,
+                interpolation,
+            } => {
+                let interpolation = match interpolation {
+                    InterpolationSpec::Constant => Interpolation::Constant,
+                    InterpolationSpec::Linear => Interpolation::Linear,
+                };
+                Box::new(ControlProfile::Schedule {
+                    acceleration: Schedule::new(acceleration.clone(), interpolation),
+                    steering: Schedule::new(steering.clone(), interpolation),
+                })
+            }
+            ControlSpec::SineSweep {
+                start_time,
+                duration,
+                amplitude,
+                start_frequency,
+                end_frequency,
+                acceleration,
+            } => Box::new(ControlProfile::SineSweep {
+                start_time: *start_time,
+                duration: *duration,
+                amplitude: *amplitude,
+                start_frequency: *start_frequency,
+                end_frequency: *end_frequency,
+                acceleration: *acceleration,
+            }),
+            ControlSpec::LaneChange {
+                start_time,
+                duration,
+                offset,
+                speed: lane_speed,
+            } => Box::new(ControlProfile::lane_change(
+                *start_time,
+                *duration,
+                *offset,
+                speed(lane_speed),
+                vehicle.wheelbase,
+            )),
+            ControlSpec::UTurn {
+                start_time,
+                steering_angle,
+                speed: turn_speed,
+            } => Box::new(ControlProfile::u_turn(
+                *start_time,
+                speed(turn_speed),
+                vehicle.wheelbase,
+                *steering_angle,
+            )),
+            ControlSpec::Replay { file } => {
+                Box::new(ControlProfile::load_recording(&base_dir.join(file))?)
+            }
+            ControlSpec::PathFollower {
+                waypoints,
+                target_speed,
+                lateral,
+                speed_pid,
+            } => {
+                let lateral = match *lateral {
+                    LateralSpec::PurePursuit {
+                        lookahead_distance,
+                        lookahead_gain,
+                    } => LateralController::PurePursuit(PurePursuit {
+                        lookahead_distance,
+                        lookahead_gain,
+                        wheelbase: vehicle.wheelbase,
+                        max_steer: vehicle.max_steer,
+                    }),
+                    LateralSpec::Stanley { gain, softening } => {
+                        LateralController::Stanley(Stanley {
+                            gain,
+                            softening,
+                            wheelbase: vehicle.wheelbase,
+                            max_steer: vehicle.max_steer,
+                        })
+                    }
+                };
+                Box::new(PathFollower::new(
+                    ReferencePath::new(waypoints.clone()),
+                    lateral,
+                    Pid::new(
+                        speed_pid.kp,
+                        speed_pid.ki,
+                        speed_pid.kd,
+                        -speed_pid.max_deceleration,
+                        speed_pid.max_acceleration,
+                    ),
+                    *target_speed,
+                ))
+            }
+            ControlSpec::Mpc {
+                waypoints,
+                target_speed,
+                horizon,
+            } => {
+                let trajectory = TrajectoryPoint::along_path(
+                    &ReferencePath::new(waypoints.clone()),
+                    *target_speed,
+                    model.dt,
+                    model.wheelbase,
+                );
+                let config = MpcConfig {
+                    horizon: *horizon,
+                    ..MpcConfig::default()
+                };
+                Box::new(MpcController::new(
+                    Mpc::new(model.clone(), config),
+                    trajectory,
+                ))
+            }
+        };
+        Ok(controller)
+    }
+
+    fn validate(&self, name: &str, vehicle: &VehicleSpec) -> Result<(), String> {
+        match self {
+            ControlSpec::Constant { .. }
+            | ControlSpec::Schedule { .. }
+            | ControlSpec::Replay { .. } => Ok(()),
+            ControlSpec::SineSweep {
+                duration,
+                start_frequency,
+                end_frequency,
+                ..
+            } => {
+                check_positive(&format!("{}.duration", name), *duration)?;
+                check_non_negative(&format!("{}.start_frequency", name), *start_frequency)?;
+                check_non_negative(&format!("{}.end_frequency", name), *end_frequency)
+            }
+            ControlSpec::LaneChange {
+                duration, speed, ..
+            } => {
+                check_positive(&format!("{}.duration", name), *duration)?;
+                check_positive(
+                    &format!("{}.speed", name),
+                    speed.unwrap_or(vehicle.velocity),
+                )
+            }
+            ControlSpec::UTurn {
+                steering_angle,
+                speed,
+                ..
+            } => {
+                check(
+                    *steering_angle != 0.0 && steering_angle.abs() <= vehicle.max_steer,
+                    || {
+                        format!(
+                            "{}.steering_angle must be non-zero and within max_steer, got {}",
+                            name, steering_angle
+                        )
+                    },
+                )?;
+                check_positive(
+                    &format!("{}.speed", name),
+                    speed.unwrap_or(vehicle.velocity),
+                )
+            }
+            ControlSpec::PathFollower {
+                waypoints,
+                target_speed,
+                speed_pid,
+                ..
+            } => {
+                check(waypoints.len() >= 2, || {
+                    format!("{}.waypoints needs at least two points", name)
+                })?;
+                check_non_negative(&format!("{}.target_speed", name), *target_speed)?;
+                check_non_negative(
+                    &format!("{}.speed_pid.max_acceleration", name),
+                    speed_pid.max_acceleration,
+                )?;
+                check_non_negative(
+                    &format!("{}.speed_pid.max_deceleration", name),
+                    speed_pid.max_deceleration,
+                )
+            }
+            ControlSpec::Mpc {
+                waypoints,
+                target_speed,
+                horizon,
+            } => {
+                check(waypoints.len() >= 2, || {
+                    format!("{}.waypoints needs at least two points", name)
+                })?;
+                check_positive(&format!("{}.target_speed", name), *target_speed)?;
+                check(*horizon > 0, || {
+                    format!("{}.horizon must be positive", name)
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SensorsSpec {
+    pub gps: GpsSpec,
+    pub imu: ImuSpec,
+    pub encoder: EncoderSpec,
+    pub stationary: StationarySpec,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GpsSpec {
+    pub noise_ratio: f64,
+    pub rate: Option<f64>,
+    pub latency: f64,
+    pub outages: Vec<(f64, f64)>,
+    // antenna position relative to the vehicle reference point, (forward, left)
+    pub lever_arm: [f64; 2],
+    // seconds the GPS clock is ahead of simulation time, and seconds it gains per second
+    pub clock_offset: f64,
+    pub clock_drift: f64,
+}
+
+impl Default for GpsSpec {
+    fn default() -> Self {
+        Self {
+            noise_ratio: 0.1,
+            rate: None,
+            latency: 0.0,
+            outages: Vec::new(),
+            lever_arm: [0.0, 0.0],
+            clock_offset: 0.0,
+            clock_drift: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImuSpec {
+    pub gyro_noise_ratio: f64,
+    pub acce_noise_ratio: f64,
+    // initial biases: accelerometer (forward, lateral) and gyro
+    pub acce_bias: [f64; 2],
+    pub gyro_bias: f64,
+    // standard deviations of the bias drift over one second
+    pub acce_bias_walk: f64,
+    pub gyro_bias_walk: f64,
+    // yaw of the sensor axes relative to the vehicle axes
+    pub mounting_yaw: f64,
+    // seconds the IMU clock is ahead of simulation time, and seconds it gains per second
+    pub clock_offset: f64,
+    pub clock_drift: f64,
+    pub mag_noise_ratio: f64,
+}
+
+impl Default for ImuSpec {
+    fn default() -> Self {
+        Self {
+            gyro_noise_ratio: 0.01,
+            acce_noise_ratio: 0.01,
+            acce_bias: [0.0, 0.0],
+            gyro_bias: 0.0,
+            acce_bias_walk: 0.0,
+            gyro_bias_walk: 0.0,
+            mounting_yaw: 0.0,
+            clock_offset: 0.0,
+            clock_drift: 0.0,
+            mag_noise_ratio: 0.01,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncoderSpec {
+    pub noise_std_dev: f64,
+    // reported per true speed, e.g. from a wheel radius that differs from the nominal one
+    pub scale_factor: f64,
+}
+
+impl Default for EncoderSpec {
+    fn default() -> Self {
+        Self {
+            noise_std_dev: 0.0,
+            scale_factor: 1.0,
+        }
+    }
+}
+
+// thresholds of the standstill detector, see `StationaryDetector`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StationarySpec {
+    pub window: usize,
+    pub accel_variance: f64,
+    pub gyro_variance: f64,
+    pub speed_threshold: f64,
+}
+
+impl Default for StationarySpec {
+    fn default() -> Self {
+        let detector = StationaryDetector::new();
+        Self {
+            window: detector.window,
+            accel_variance: detector.accel_variance,
+            gyro_variance: detector.gyro_variance,
+            speed_threshold: detector.speed_threshold,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ActuatorsSpec {
+    pub steering: ActuatorSpec,
+    // acts on the acceleration, so its rate limit is a jerk limit
+    pub throttle: ActuatorSpec,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ActuatorSpec {
+    pub time_constant: f64,
+    pub rate_limit: Option<f64>,
+    // limits of the applied value; steering is also always limited by max_steer
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub deadband: f64,
+    pub noise_std_dev: f64,
+}
+
+impl ActuatorSpec {
+    pub fn apply(&self, actuator: &mut Actuator) {
+        actuator.time_constant = self.time_constant;
+        actuator.rate_limit = self.rate_limit;
+        actuator.min = self.min.map_or(actuator.min, |min| min.max(actuator.min));
+        actuator.max = self.max.map_or(actuator.max, |max| max.min(actuator.max));
+        actuator.deadband = self.deadband;
+        actuator.noise_std_dev = self.noise_std_dev;
+    }
+
+    // `limits` are the (min, max) of the actuator the spec is applied to, which the bounds
+    // given here only narrow
+    fn validate(&self, name: &str, limits: (f64, f64)) -> Result<(), String> {
+        check_non_negative(&format!("{}.time_constant", name), self.time_constant)?;
+        if let Some(rate) = self.rate_limit {
+            check_positive(&format!("{}.rate_limit", name), rate)?;
+        }
+        let min = self.min.map_or(limits.0, |min| min.max(limits.0));
+        let max = self.max.map_or(limits.1, |max| max.min(limits.1));
+        check(min < max, || {
+            format!(
+                "{}.min must be below max within [{}, {}], got [{}, {}]",
+                name, limits.0, limits.1, min, max
+            )
+        })?;
+        check_non_negative(&format!("{}.deadband", name), self.deadband)?;
+        check_non_negative(&format!("{}.noise_std_dev", name), self.noise_std_dev)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObstacleSpec {
+    pub x: f64,
+    pub y: f64,
+    #[serde(default)]
+    pub heading: f64,
+    pub length: f64,
+    pub width: f64,
+}
+
+fn check(condition: bool, message: impl FnOnce() -> String) -> Result<(), String> {
+    if condition {
+        Ok(())
+    } else {
+        Err(message())
+    }
+}
+
+fn check_positive(name: &str, value: f64) -> Result<(), String> {
+    check(value.is_finite() && value > 0.0, || {
+        format!("{} must be positive, got {}", name, value)
+    })
+}
+
+fn check_non_negative(name: &str, value: f64) -> Result<(), String> {
+    check(value.is_finite() && value >= 0.0, || {
+        format!("{} must not be negative, got {}", name, value)
+    })
+}
+
+fn parse_color(name: &str) -> Option<CarColor> {
+    match name {
+        "red" => Some(CarColor::Red),
+        "green" => Some(CarColor::Green),
+        "blue" => Some(CarColor::Blue),
+        _ => None,
+    }
+}
+
+impl Scenario {
+    // loads a scenario, choosing the format from the file extension
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read scenario '{}': {}", path.display(), e))?;
+        let scenario = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml_str(&text),
+            Some("yaml") | Some("yml") => Self::from_yaml_str(&text),
+            _ => Err("unknown scenario format, expected a .toml, .yaml or .yml file".to_string()),
+        };
+        let mut scenario =
+            scenario.map_err(|e| format!("invalid scenario '{}': {}", path.display(), e))?;
+        scenario.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(scenario)
+    }
+
+    pub fn from_toml_str(text: &str) -> Result<Self, String> {
+        let scenario: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn from_yaml_str(text: &str) -> Result<Self, String> {
+        let scenario: Self = serde_yaml::from_str(text).map_err(|e| e.to_string())?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        check_positive("dt", self.dt)?;
+        check_non_negative("duration", self.duration)?;
+        self.estimator.kind.parse::<EstimatorKind>()?;
+        for (i, value) in self.estimator.process_noise.iter().enumerate() {
+            check_non_negative(&format!("estimator.process_noise[{}]", i), *value)?;
+        }
+        for (i, value) in self.estimator.control_noise.iter().enumerate() {
+            check_non_negative(&format!("estimator.control_noise[{}]", i), *value)?;
+        }
+        for (i, value) in self.estimator.gps_noise.iter().enumerate() {
+            check_positive(&format!("estimator.gps_noise[{}]", i), *value)?;
+        }
+        for (i, value) in self.estimator.imu_noise.iter().enumerate() {
+            check_non_negative(&format!("estimator.imu_noise[{}]", i), *value)?;
+        }
+        for (i, value) in self.estimator.imu_bias_walk.iter().enumerate() {
+            check_non_negative(&format!("estimator.imu_bias_walk[{}]", i), *value)?;
+        }
+        check_positive("estimator.odometry_noise", self.estimator.odometry_noise)?;
+        check(!self.vehicles.is_empty(), || {
+            "a scenario needs at least one vehicle".to_string()
+        })?;
+        for (i, vehicle) in self.vehicles.iter().enumerate() {
+            let name = |field: &str| format!("vehicles[{}].{}", i, field);
+            check_positive(&name("length"), vehicle.length)?;
+            check_positive(&name("width"), vehicle.width)?;
+            check_positive(&name("wheelbase"), vehicle.wheelbase)?;
+            check(
+                vehicle.max_steer > 0.0 && vehicle.max_steer < std::f64::consts::FRAC_PI_2,
+                || {
+                    format!(
+                        "{} must be in (0, pi/2), got {}",
+                        name("max_steer"),
+                        vehicle.max_steer
+                    )
+                },
+            )?;
+            vehicle.control.validate(&name("control"), vehicle)?;
+            vehicle.actuators.steering.validate(
+                &name("actuators.steering"),
+                (-vehicle.max_steer, vehicle.max_steer),
+            )?;
+            vehicle.actuators.throttle.validate(
+                &name("actuators.throttle"),
+                (f64::NEG_INFINITY, f64::INFINITY),
+            )?;
+            if let Some(color) = &vehicle.color {
+                check(parse_color(color).is_some(), || {
+                    format!(
+                        "{} must be red, green or blue, got '{}'",
+                        name("color"),
+                        color
+                    )
+                })?;
+            }
+            let gps = &vehicle.sensors.gps;
+            check_non_negative(&name("sensors.gps.noise_ratio"), gps.noise_ratio)?;
+            if let Some(rate) = gps.rate {
+                check_positive(&name("sensors.gps.rate"), rate)?;
+            }
+            check_non_negative(&name("sensors.gps.latency"), gps.latency)?;
+            for (j, (start, end)) in gps.outages.iter().enumerate() {
+                check(start < end, || {
+                    format!(
+                        "{}[{}] must end after it starts, got [{}, {}]",
+                        name("sensors.gps.outages"),
+                        j,
+                        start,
+                        end
+                    )
+                })?;
+            }
+            let imu = &vehicle.sensors.imu;
+            check_non_negative(&name("sensors.imu.gyro_noise_ratio"), imu.gyro_noise_ratio)?;
+            check_non_negative(&name("sensors.imu.acce_noise_ratio"), imu.acce_noise_ratio)?;
+            check_non_negative(&name("sensors.imu.acce_bias_walk"), imu.acce_bias_walk)?;
+            check_non_negative(&name("sensors.imu.gyro_bias_walk"), imu.gyro_bias_walk)?;
+            check_non_negative(
+                &name("sensors.encoder.noise_std_dev"),
+                vehicle.sensors.encoder.noise_std_dev,
+            )?;
+            check_positive(
+                &name("sensors.encoder.scale_factor"),
+                vehicle.sensors.encoder.scale_factor,
+            )?;
+            check_non_negative(&name("sensors.imu.mag_noise_ratio"), imu.mag_noise_ratio)?;
+            let stationary = &vehicle.sensors.stationary;
+            check(stationary.window > 0, || {
+                format!("{} must be positive", name("sensors.stationary.window"))
+            })?;
+            check_non_negative(
+                &name("sensors.stationary.accel_variance"),
+                stationary.accel_variance,
+            )?;
+            check_non_negative(
+                &name("sensors.stationary.gyro_variance"),
+                stationary.gyro_variance,
+            )?;
+            check_non_negative(
+                &name("sensors.stationary.speed_threshold"),
+                stationary.speed_threshold,
+            )?;
+        }
+        for (i, obstacle) in self.obstacles.iter().enumerate() {
+            check_positive(&format!("obstacles[{}].length", i), obstacle.length)?;
+            check_positive(&format!("obstacles[{}].width", i), obstacle.width)?;
+        }
+        Ok(())
+    }
+
+    pub fn estimator_config(&self) -> EstimatorConfig {
+        EstimatorConfig {
+            kind: self.estimator.kind.parse().unwrap_or(EstimatorKind::Ekf),
+            process_noise: self.estimator.process_noise,
+            control_noise: self.estimator.control_noise,
+            gps_noise: self.estimator.gps_noise,
+            joseph_form: self.estimator.joseph_form,
+            enforce_symmetry: self.estimator.enforce_symmetry,
+            imu_noise: self.estimator.imu_noise,
+            imu_bias_walk: self.estimator.imu_bias_walk,
+            odometry_noise: self.estimator.odometry_noise,
+            estimate_imu_biases: self.estimator.estimate_imu_biases,
+            estimate_calibration: self.estimator.estimate_calibration,
+            estimate_time_offset: self.estimator.estimate_time_offset,
+            static_alignment: self.estimator.static_alignment,
+        }
+    }
+
+    pub fn build_world(&self) -> Result<World, String> {
+        let mut world = World::new(self.dt);
+        world.estimator = self.estimator_config();
+        world.seed = self.seed;
+        for vehicle in self.vehicles.iter() {
+            let mut car = Car::new(
+                vehicle.x,
+                vehicle.y,
+                vehicle.yaw,
+                vehicle.width,
+                vehicle.length,
+                vehicle.velocity,
+                vehicle.wheelbase,
+                vehicle.max_steer,
+                self.dt,
+                vehicle.color.as_deref().and_then(parse_color),
+            );
+            vehicle.actuators.steering.apply(&mut car.steering);
+            vehicle.actuators.throttle.apply(&mut car.throttle);
+            let controller = vehicle.control.build(vehicle, &car.model, &self.base_dir)?;
+            let id = world.add_vehicle(car, controller);
+            if vehicle.control_from_estimate {
+                world.vehicles[id].control_source = ControlSource::Estimate;
+            }
+            vehicle.sensors.apply(&mut world.vehicles[id].sensors);
+        }
+        for obstacle in self.obstacles.iter() {
+            world.add_obstacle(Footprint::new(
+                obstacle.x,
+                obstacle.y,
+                obstacle.heading,
+                obstacle.length,
+                obstacle.width,
+                ReferencePoint::Center,
+            ));
+        }
+        Ok(world)
+    }
+}
+
+impl SensorsSpec {
+    pub fn apply(&self, sensors: &mut SensorSet) {
+        sensors.gps.noise_ratio = self.gps.noise_ratio;
+        sensors.gps_timing =
+            SensorTiming::new(self.gps.rate, self.gps.latency, self.gps.outages.clone());
+        sensors.imu.gyro_noise_ratio = self.imu.gyro_noise_ratio;
+        sensors.imu.acce_noise_ratio = self.imu.acce_noise_ratio;
+        sensors.imu.bias = ImuBiases {
+            accel_forward: self.imu.acce_bias[0],
+            accel_lateral: self.imu.acce_bias[1],
+            gyro_z: self.imu.gyro_bias,
+        };
+        sensors.imu.acce_bias_walk = self.imu.acce_bias_walk;
+        sensors.imu.gyro_bias_walk = self.imu.gyro_bias_walk;
+        sensors.imu.mounting_yaw = self.imu.mounting_yaw;
+        sensors.imu.mag_noise_ratio = self.imu.mag_noise_ratio;
+        sensors.stationary.window = self.stationary.window;
+        sensors.stationary.accel_variance = self.stationary.accel_variance;
+        sensors.stationary.gyro_variance = self.stationary.gyro_variance;
+        sensors.stationary.speed_threshold = self.stationary.speed_threshold;
+        sensors.encoder.noise_std_dev = self.encoder.noise_std_dev;
+        sensors.encoder.scale_factor = self.encoder.scale_factor;
+        sensors.gps.lever_arm = self.gps.lever_arm;
+        sensors.gps_clock = SensorClock {
+            offset: self.gps.clock_offset,
+            drift: self.gps.clock_drift,
+        };
+        sensors.imu_clock = SensorClock {
+            offset: self.imu.clock_offset,
+            drift: self.imu.clock_drift,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML_SCENARIO: &str = r#"
+duration = 5.0
+dt = 0.05
+seed = 11
+
+[estimator]
+kind = "dead-reckoning"
+
+[[vehicles]]
+y = 240.0
+color = "red"
+control = { type = "constant", acceleration = 0.1, steering_angle = 0.001 }
+
+[vehicles.sensors.gps]
+noise_ratio = 0.2
+rate = 5.0
+latency = 0.1
+outages = [[1.0, 2.0]]
+
+[vehicles.actuators.steering]
+time_constant = 0.2
+rate_limit = 0.5
+max = 2.0
+
+[[vehicles]]
+y = 120.0
+velocity = 2.0
+
+[[obstacles]]
+x = 300.0
+y = 240.0
+length = 10.0
+width = 10.0
+"#;
+
+    #[test]
+    fn test_load_toml_scenario() {
+        let scenario = Scenario::from_toml_str(TOML_SCENARIO).unwrap();
+        assert_eq!(scenario.vehicles.len(), 2);
+        let world = scenario.build_world().unwrap();
+        assert_eq!(world.dt, 0.05);
+        assert_eq!(world.estimator.kind, EstimatorKind::DeadReckoning);
+        assert_eq!(world.obstacles.len(), 1);
+        let sensors = &world.vehicles[0].sensors;
+        assert_eq!(sensors.gps.noise_ratio, 0.2);
+        assert_eq!(sensors.gps_timing.rate, Some(5.0));
+        assert_eq!(sensors.gps_timing.outages, vec![(1.0, 2.0)]);
+        let steering = &world.vehicles[0].car.steering;
+        assert_eq!(steering.rate_limit, Some(0.5));
+        assert_eq!((steering.min, steering.max), (-0.5, 0.5));
+        assert_eq!(world.vehicles[1].car.state.velocity, 2.0);
+    }
+
+    #[test]
+    fn test_load_yaml_scenario() {
+        let yaml = "
+duration: 1.0
+vehicles:
+  - x: 10.0
+    control: { type: constant, steering_angle: 0.1 }
+    sensors:
+      gps: { latency: 0.2 }
+";
+        let scenario = Scenario::from_yaml_str(yaml).unwrap();
+        assert_eq!(scenario.vehicles[0].x, 10.0);
+        assert_eq!(scenario.vehicles[0].sensors.gps.latency, 0.2);
+    }
+
+    #[test]
+    fn test_control_profiles_in_scenario() {
+        let toml = r#"
+[[vehicles]]
+velocity = 10.0
+control = { type = "lane_change", start_time = 1.0, duration = 4.0, offset = 3.5 }
+
+[[vehicles]]
+velocity = 5.0
+control = { type = "schedule", acceleration = [[0.0, 1.0], [2.0, 0.0]], interpolation = "constant" }
+
+[[vehicles]]
+velocity = 5.0
+control = { type = "u_turn", steering_angle = 0.3 }
+
+[[vehicles]]
+control_from_estimate = true
+control = { type = "path_follower", waypoints = [[0.0, 0.0], [100.0, 0.0]], target_speed = 5.0, lateral = { type = "stanley" } }
+
+[[vehicles]]
+control = { type = "mpc", waypoints = [[0.0, 0.0], [100.0, 0.0]], target_speed = 5.0, horizon = 10 }
+"#;
+        let scenario = Scenario::from_toml_str(toml).unwrap();
+        let mut world = scenario.build_world().unwrap();
+        let state = world.vehicles[1].car.state;
+        assert_eq!(
+            world.vehicles[1].controller.control(1.0, &state),
+            (1.0, 0.0)
+        );
+        assert_eq!(
+            world.vehicles[1].controller.control(2.5, &state),
+            (0.0, 0.0)
+        );
+        assert_eq!(world.vehicles[3].control_source, ControlSource::Estimate);
+        let state = world.vehicles[4].car.state;
+        let (acceleration, _) = world.vehicles[4].controller.control(0.0, &state);
+        assert!(acceleration > 0.0);
+
+        let error = Scenario::from_toml_str(
+            "[[vehicles]]\ncontrol = { type = \"lane_change\", duration = 4.0, offset = 3.5 }\n",
+        )
+        .unwrap_err();
+        assert!(error.contains("vehicles[0].control.speed"), "{}", error);
+
+        let error = Scenario::from_toml_str(
+            "[[vehicles]]\nvelocity = 5.0\ncontrol = { type = \"u_turn\", steering_angle = 0.9 }\n",
+        )
+        .unwrap_err();
+        assert!(error.contains("steering_angle"), "{}", error);
+
+        let missing = Scenario::from_toml_str(
+            "[[vehicles]]\ncontrol = { type = \"replay\", file = \"missing.csv\" }\n",
+        )
+        .unwrap();
+        assert!(missing.build_world().is_err());
+    }
+
+    #[test]
+    fn test_invalid_scenarios_are_reported() {
+        let error = Scenario::from_toml_str("[[vehicles]]\nwheelbase = -1.0\n").unwrap_err();
+        assert!(error.contains("vehicles[0].wheelbase"), "{}", error);
+
+        let error = Scenario::from_toml_str("[[vehicles]]\nwheel_base = 2.0\n").unwrap_err();
+        assert!(error.contains("wheel_base"), "{}", error);
+
+        let error = Scenario::from_toml_str("vehicles = []\n").unwrap_err();
+        assert!(error.contains("at least one vehicle"), "{}", error);
+
+        let error =
+            Scenario::from_toml_str("[estimator]\nkind = \"ukf\"\n[[vehicles]]\n").unwrap_err();
+        assert!(error.contains("ukf"), "{}", error);
+
+        let error = Scenario::from_toml_str(
+            "[[vehicles]]\n[vehicles.sensors.gps]\noutages = [[5.0, 1.0]]\n",
+        )
+        .unwrap_err();
+        assert!(error.contains("outages[0]"), "{}", error);
+
+        // bounds that only conflict once narrowed to the steering limit of max_steer = 0.5
+        for bound in ["min = 1.0", "max = -1.0"] {
+            let error = Scenario::from_toml_str(&format!(
+                "[[vehicles]]\n[vehicles.actuators.steering]\n{}\n",
+                bound
+            ))
+            .unwrap_err();
+            assert!(error.contains("actuators.steering.min"), "{}", error);
+        }
+
+        let error =
+            Scenario::from_toml_str("[[vehicles]]\n[vehicles.sensors.stationary]\nwindow = 0\n")
+                .unwrap_err();
+        assert_eq!(
+            error,
+            "vehicles[0].sensors.stationary.window must be positive"
+        );
+    }
+}
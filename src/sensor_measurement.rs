@@ -1,19 +1,138 @@
 use rand::rngs::ThreadRng;
 use rand::thread_rng;
 use rand_distr::{Distribution, Normal};
+use std::collections::VecDeque;
 use std::fmt;
 
 use crate::sensors::{Encoder, GPS, IMU};
+use crate::stationary::StationaryDetector;
 use crate::state::{CarColor, CarState};
 
 use crate::state::Rectangular;
 
+// when a sensor delivers its samples to the estimator
+#[derive(Debug, Clone, Default)]
+pub struct SensorTiming {
+    // samples per second, every simulation step when None
+    pub rate: Option<f64>,
+    // seconds between taking a sample and it becoming available
+    pub latency: f64,
+    // (start, end) intervals of simulation time without any samples
+    pub outages: Vec<(f64, f64)>,
+    last_sample: Option<f64>,
+}
+
+impl SensorTiming {
+    pub fn new(rate: Option<f64>, latency: f64, outages: Vec<(f64, f64)>) -> Self {
+        Self {
+            rate,
+            latency,
+            outages,
+            last_sample: None,
+        }
+    }
+
+    pub fn in_outage(&self, time: f64) -> bool {
+        self.outages
+            .iter()
+            .any(|(start, end)| time >= *start && time < *end)
+    }
+
+    // simulation time of the latest sample taken, if any
+    pub fn last_sample(&self) -> Option<f64> {
+        self.last_sample
+    }
+
+    // whether a sample should be taken at `time`; records it when it is
+    pub fn sample_due(&mut self, time: f64) -> bool {
+        if self.in_outage(time) {
+            return false;
+        }
+        let due = match (self.rate, self.last_sample) {
+            (Some(rate), Some(last)) => time - last >= 1.0 / rate - 1e-9,
+            _ => true,
+        };
+        if due {
+            self.last_sample = Some(time);
+        }
+        due
+    }
+}
+
+// the clock a sensor stamps its samples with: it is off from simulation time by an offset
+// that grows with the drift rate
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct SensorClock {
+    // seconds the sensor clock is ahead of simulation time at time zero
+    pub offset: f64,
+    // seconds gained per second
+    pub drift: f64,
+}
+
+impl SensorClock {
+    pub fn offset_at(&self, time: f64) -> f64 {
+        self.offset + self.drift * time
+    }
+
+    pub fn stamp(&self, time: f64) -> f64 {
+        time + self.offset_at(time)
+    }
+}
+
+// calibration of the sensor set that estimators can refine online; the default is the nominal
+// calibration the sensors are specified with
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Calibration {
+    // speed reported by the wheel encoder per true speed
+    pub encoder_scale: f64,
+    // GPS antenna position relative to the vehicle reference point, (forward, left)
+    pub gps_lever_arm: [f64; 2],
+    // yaw of the IMU axes relative to the vehicle axes
+    pub imu_mounting_yaw: f64,
+    // seconds the GPS clock is ahead of the IMU clock
+    pub gps_time_offset: f64,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            encoder_scale: 1.0,
+            gps_lever_arm: [0.0, 0.0],
+            imu_mounting_yaw: 0.0,
+            gps_time_offset: 0.0,
+        }
+    }
+}
+
+impl Calibration {
+    pub fn difference(&self, other: &Calibration) -> Calibration {
+        Calibration {
+            encoder_scale: self.encoder_scale - other.encoder_scale,
+            gps_lever_arm: [
+                self.gps_lever_arm[0] - other.gps_lever_arm[0],
+                self.gps_lever_arm[1] - other.gps_lever_arm[1],
+            ],
+            imu_mounting_yaw: self.imu_mounting_yaw - other.imu_mounting_yaw,
+            gps_time_offset: self.gps_time_offset - other.gps_time_offset,
+        }
+    }
+}
+
 pub struct SensorSet {
     pub gps: GPS::GpsXYZ,
     pub imu: IMU::IMUDevice,
     pub encoder: Encoder::WheelEncoder,
     pub wheel_encoder: Vec<()>,
     pub measured_state: CarState,
+    pub gps_timing: SensorTiming,
+    pub gps_clock: SensorClock,
+    pub imu_clock: SensorClock,
+    pub stationary: StationaryDetector,
+    // GPS fixes taken but not yet delivered because of latency, with the true time they were
+    // taken at
+    pending_gps: VecDeque<(f64, GPS::XYZValues)>,
+    // simulation time of the latest sample
+    time: f64,
 }
 
 impl SensorSet {
@@ -24,14 +143,69 @@ impl SensorSet {
             encoder: Encoder::WheelEncoder::new(),
             wheel_encoder: Vec::new(),
             measured_state: actual_car.clone(),
+            gps_timing: SensorTiming::default(),
+            gps_clock: SensorClock::default(),
+            imu_clock: SensorClock::default(),
+            stationary: StationaryDetector::new(),
+            pending_gps: VecDeque::new(),
+            time: actual_car.time_stamp,
         }
     }
 
+    // give every device its own reproducible noise stream derived from `seed`
+    pub fn seed(&mut self, seed: u64) {
+        self.gps.seed(seed);
+        self.imu.seed(seed.wrapping_add(1));
+        self.encoder.seed(seed.wrapping_add(2));
+    }
+
     pub fn from_carstate(&mut self, car: &CarState) {
         self.gps.from_carstate(car);
         self.imu.from_carstate(car);
         self.encoder.from_carstate(car);
         // self.wheel_encoder.push(self.encoder.from_carstate(car).clone());
+        let imu = self.imu.get_imu_data(None);
+        let (count, speed) = (self.encoder.get_count(), self.encoder.get_velocity());
+        if self.stationary.update(&imu, count, speed) {
+            self.imu.zero_velocity_update();
+        }
+        self.time = car.time_stamp;
+        if let Some(sample) = self.imu.imu_recorder.last_mut() {
+            sample.time_stamp = self.imu_clock.stamp(car.time_stamp);
+        }
+        if self.gps_timing.sample_due(car.time_stamp) {
+            let mut fix = self.gps.get_local_xyz(None);
+            fix.time_stamp = self.gps_clock.stamp(car.time_stamp);
+            self.pending_gps.push_back((car.time_stamp, fix));
+        }
+    }
+
+    // the true calibration of the simulated devices
+    pub fn calibration(&self) -> Calibration {
+        Calibration {
+            encoder_scale: self.encoder.scale_factor,
+            gps_lever_arm: self.gps.lever_arm,
+            imu_mounting_yaw: self.imu.mounting_yaw,
+            gps_time_offset: self.gps_clock.offset_at(self.time)
+                - self.imu_clock.offset_at(self.time),
+        }
+    }
+
+    // whether the GPS was in an outage at the latest sample
+    pub fn gps_in_outage(&self) -> bool {
+        self.gps_timing.in_outage(self.time)
+    }
+
+    // GPS fixes that have become available by `time`, oldest first
+    pub fn take_gps_fixes(&mut self, time: f64) -> Vec<GPS::XYZValues> {
+        let mut fixes = Vec::new();
+        while let Some((taken, _)) = self.pending_gps.front() {
+            if taken + self.gps_timing.latency > time + 1e-9 {
+                break;
+            }
+            fixes.extend(self.pending_gps.pop_front().map(|(_, fix)| fix));
+        }
+        fixes
     }
 
     pub fn get_observed_state(&mut self, car: &CarState) -> Rectangular{
@@ -56,3 +230,55 @@ impl fmt::Display for SensorSet {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gps_rate_latency_and_outage() {
+        let mut car = CarState::new();
+        let mut sensors = SensorSet::new(&car);
+        sensors.gps_timing = SensorTiming::new(Some(2.0), 0.3, vec![(2.0, 3.0)]);
+        let mut delivered = Vec::new();
+        for step in 1..=40 {
+            car.time_stamp = step as f64 * 0.1;
+            sensors.from_carstate(&car);
+            for fix in sensors.take_gps_fixes(car.time_stamp) {
+                delivered.push((fix.time_stamp, car.time_stamp));
+            }
+        }
+        let taken: Vec<f64> = delivered.iter().map(|(taken, _)| *taken).collect();
+        let expected = [0.1, 0.6, 1.1, 1.6, 3.0, 3.5];
+        assert_eq!(taken.len(), expected.len());
+        for ((taken, delivered), expected) in delivered.iter().zip(expected.iter()) {
+            assert!((taken - expected).abs() < 1e-9);
+            assert!((delivered - taken - 0.3).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_clock_offset_and_drift_stamp_samples() {
+        let mut car = CarState::new();
+        let mut sensors = SensorSet::new(&car);
+        sensors.gps_clock = SensorClock {
+            offset: 0.5,
+            drift: 0.01,
+        };
+        sensors.imu_clock = SensorClock {
+            offset: -0.1,
+            drift: 0.0,
+        };
+        for step in 1..=20 {
+            car.time_stamp = step as f64 * 0.1;
+            sensors.from_carstate(&car);
+            // delivery follows the true time, only the stamps are off
+            let fixes = sensors.take_gps_fixes(car.time_stamp);
+            assert_eq!(fixes.len(), 1);
+            assert!((fixes[0].time_stamp - (car.time_stamp * 1.01 + 0.5)).abs() < 1e-9);
+            let imu = sensors.imu.get_imu_data(None);
+            assert!((imu.time_stamp - (car.time_stamp - 0.1)).abs() < 1e-9);
+        }
+        assert!((sensors.calibration().gps_time_offset - 0.62).abs() < 1e-9);
+    }
+}
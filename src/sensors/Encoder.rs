@@ -1,6 +1,6 @@
 use imageproc::noise;
-use rand::rngs::ThreadRng;
-use rand::thread_rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rand::Rng;
 use rand_distr::{Distribution, Normal};
 use std::fmt;
@@ -38,33 +38,44 @@ impl Encoder {
             count: 0,
             last_count: 0,
             velocity: 0.0,
-            noise_std_dev: Some(0.0),
+            noise_std_dev,
         }
     }
 }
 
 pub struct WheelEncoder {
     pub encoder_recorder: Vec<Encoder>,
-    rng: ThreadRng,
+    rng: StdRng,
     normal: Normal<f64>,
+    pub noise_std_dev: f64,
+    // ratio of the nominal to the true wheel radius: the speed derived from the wheel rotation
+    // with the nominal radius is the true speed times this factor
+    pub scale_factor: f64,
 }
 
 impl WheelEncoder {
     pub fn new() -> Self {
         Self {
             encoder_recorder: Vec::new(),
-            rng: thread_rng(),
+            rng: StdRng::from_entropy(),
             normal: Normal::new(0.0, 0.1).unwrap(),
+            noise_std_dev: 0.0,
+            scale_factor: 1.0,
         }
     }
 
+    // reseed the noise generator so runs can be reproduced
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
     pub fn from_carstate(&mut self, car: &CarState) {
         let mut encoder = Encoder::new(
             "Left Wheel Encoder".to_string(),
             "Quadrature".to_string(),
             10000,
             true,
-            Some(0.0),
+            Some(self.noise_std_dev),
         );
         encoder.time_stamp = car.time_stamp;
         // Calculate the ground truth change in count
@@ -72,8 +83,7 @@ impl WheelEncoder {
         let delta_count_gt = car.velocity * car.dt / encoder.resolution.unwrap() as f64;
 
         // Introduce noise (Gaussian noise with standard deviation noise_std_dev)
-        let mut rng = rand::thread_rng();
-        let noise = rng.gen::<f64>() * encoder.noise_std_dev.unwrap();
+        let noise = self.rng.gen::<f64>() * encoder.noise_std_dev.unwrap();
 
         // Apply noise to the change in count
         let delta_count_with_noise = delta_count_gt + noise;
@@ -84,9 +94,18 @@ impl WheelEncoder {
         } else {
             encoder.count += delta_count_with_noise as i32;
         }
+        // the wheel speed the encoder electronics report alongside the count
+        encoder.velocity = car.velocity * self.scale_factor;
+        if self.noise_std_dev > 0.0 {
+            encoder.velocity += Normal::new(0.0, self.noise_std_dev).unwrap().sample(&mut self.rng);
+        }
         self.encoder_recorder.push(encoder.clone());
     }
 
+    pub fn get_velocity(&self) -> f64 {
+        self.encoder_recorder.last().map_or(0.0, |encoder| encoder.velocity)
+    }
+
     pub fn get_count(&mut self) -> i32 {
         self.encoder_recorder.last_mut().unwrap().count
     }
@@ -1,5 +1,5 @@
-use rand::rngs::ThreadRng;
-use rand::thread_rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rand::Rng;
 use rand_distr::{Distribution, Normal};
 use std::fmt;
@@ -47,6 +47,17 @@ impl XYZValues {
     }
 }
 
+// a fix taken at time zero, for the filter tests
+#[cfg(test)]
+pub fn fix(x: f64, y: f64, z: f64) -> XYZValues {
+    XYZValues {
+        time_stamp: 0.0,
+        x,
+        y,
+        z,
+    }
+}
+
 impl std::ops::Sub for XYZValues {
     type Output = Self;
     fn sub(self, other: Self) -> Self {
@@ -68,7 +79,11 @@ pub struct GpsXYZ {
     pub xyz_values: Vec<XYZValues>,
     pub covariances: Vec<f64>,
     earth_radius: f64,
-    rng: ThreadRng,
+    // scales the unit-ish normal noise applied to every fix
+    pub noise_ratio: f64,
+    // antenna position relative to the vehicle reference point, (forward, left) in meters
+    pub lever_arm: [f64; 2],
+    rng: StdRng,
     normal: Normal<f64>,
 }
 
@@ -80,13 +95,20 @@ impl GpsXYZ {
             xyz_values: Vec::new(),
             covariances: Vec::new(),
             earth_radius: earth_radius.unwrap_or(6371000.0),
-            rng: thread_rng(),
+            noise_ratio: 0.1,
+            lever_arm: [0.0, 0.0],
+            rng: StdRng::from_entropy(),
             normal: Normal::new(0.0, 0.1).unwrap(),
         }
     }
 
+    // reseed the noise generator so runs can be reproduced
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
     pub fn from_carstate(&mut self, car: &CarState) {
-        let noise_ratio = 0.1;
+        let noise_ratio = self.noise_ratio;
         let gps_noise = self.normal.sample(&mut self.rng) * noise_ratio;
         let gps_speed_noise = self.normal.sample(&mut self.rng) * noise_ratio * 100.0;
 
@@ -101,8 +123,10 @@ impl GpsXYZ {
             altitude,
             speed: car.velocity + gps_speed_noise,
         });
-        let x = car.x + gps_noise;
-        let y = car.y + gps_noise;
+        let (sin, cos) = car.yaw.sin_cos();
+        let [forward, left] = self.lever_arm;
+        let x = car.x + forward * cos - left * sin + gps_noise;
+        let y = car.y + forward * sin + left * cos + gps_noise;
         let z = car.yaw + gps_noise;
         self.xyz_values.push(XYZValues {
             x,
@@ -112,6 +136,11 @@ impl GpsXYZ {
         });
     }
 
+    // standard deviation of the noise on each coordinate of a fix
+    pub fn noise_std_dev(&self) -> f64 {
+        self.normal.std_dev() * self.noise_ratio
+    }
+
     pub fn get_local_xyz(&mut self, idx: Option<usize>) -> XYZValues {
         let index = idx.unwrap_or(self.gps_values.len() - 1);
         self.xyz_values[index]
@@ -1,5 +1,5 @@
-use rand::rngs::ThreadRng;
-use rand::thread_rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rand::Rng;
 use rand_distr::{Distribution, Normal};
 use std::fmt;
@@ -16,20 +16,55 @@ pub struct IMU9Axis {
     gyro_x: f64,
     gyro_y: f64,
     pub gyro_z: f64,
-    mag_x: f64,
-    mag_y: f64,
+    pub mag_x: f64,
+    pub mag_y: f64,
     mag_z: f64,
 }
 
+// constant offsets of the readings: accelerometer along the vehicle's forward and left axes,
+// and gyro about z
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct ImuBiases {
+    pub accel_forward: f64,
+    pub accel_lateral: f64,
+    pub gyro_z: f64,
+}
+
+impl ImuBiases {
+    pub fn difference(&self, other: &ImuBiases) -> ImuBiases {
+        ImuBiases {
+            accel_forward: self.accel_forward - other.accel_forward,
+            accel_lateral: self.accel_lateral - other.accel_lateral,
+            gyro_z: self.gyro_z - other.gyro_z,
+        }
+    }
+}
+
 pub struct IMUDevice {
     initial:bool,
     pub imu_recorder: Vec<IMU9Axis>,
-    rng: ThreadRng,
+    rng: StdRng,
     normal: Normal<f64>,
+    pub gyro_noise_ratio: f64,
+    pub acce_noise_ratio: f64,
     pub previous_yaw: f64,
     pub previous_velocity: f64,
     pub previous_x: f64,
     pub previous_y: f64,
+    // the true yaw and velocity at the previous sample, which the readings are derived from
+    last_true_yaw: f64,
+    last_true_velocity: f64,
+    // the true biases of the readings, which drift as random walks
+    pub bias: ImuBiases,
+    // standard deviations of the bias drift over one second
+    pub acce_bias_walk: f64,
+    pub gyro_bias_walk: f64,
+    // yaw of the sensor axes relative to the vehicle axes, in radians
+    pub mounting_yaw: f64,
+    pub mag_noise_ratio: f64,
+    // pose before the integration of the latest sample, restored when the vehicle turns out to
+    // have been standing still
+    last_pose: (f64, f64, f64),
 }
 
 //implement a method where it takes ground velocity and yaw, and reverse calculate the IMU data in high frequency.
@@ -48,6 +83,11 @@ impl IMU9Axis {
             mag_z: 0.0,
         }
     }
+
+    // heading of the sensor axes from the magnetometer, whose field points along the world x axis
+    pub fn heading(&self) -> f64 {
+        (-self.mag_y).atan2(self.mag_x)
+    }
 }
 
 impl IMUDevice {
@@ -55,45 +95,87 @@ impl IMUDevice {
         Self {
             initial: true,
             imu_recorder: vec![IMU9Axis::new()],
-            rng: thread_rng(),
+            rng: StdRng::from_entropy(),
             normal: Normal::new(0.0, 0.15).unwrap(),
+            gyro_noise_ratio: 0.01,
+            acce_noise_ratio: 0.01,
             previous_yaw: 0.0,
             previous_velocity: 0.0,
             previous_x: 0.0,
             previous_y: 0.0,
+            last_true_yaw: 0.0,
+            last_true_velocity: 0.0,
+            bias: ImuBiases::default(),
+            acce_bias_walk: 0.0,
+            gyro_bias_walk: 0.0,
+            mounting_yaw: 0.0,
+            mag_noise_ratio: 0.01,
+            last_pose: (0.0, 0.0, 0.0),
         }
     }
 
+    // reseed the noise generator so runs can be reproduced
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
     pub fn from_carstate(&mut self, car: &CarState) {
-        let gyro_noise_ratio = 0.01;
-        let acce_noise_ratio = 0.01;
-        let gyro_noise = self.normal.sample(&mut self.rng) * gyro_noise_ratio;
-        let acce_noise = self.normal.sample(&mut self.rng) * acce_noise_ratio;
+        let gyro_noise = self.normal.sample(&mut self.rng) * self.gyro_noise_ratio;
+        let acce_noise = self.normal.sample(&mut self.rng) * self.acce_noise_ratio;
         if self.initial {
             self.previous_yaw = car.yaw;
             self.previous_velocity = car.velocity;
             self.previous_x = car.x;
             self.previous_y = car.y;
+            self.last_true_yaw = car.yaw;
+            self.last_true_velocity = car.velocity;
             self.initial = false;
         }
         let mut imu_data = IMU9Axis::new();
         imu_data.time_stamp = get_time_stamp();
         // let dt = imu_data.time_stamp - self.imu_recorder.last().unwrap().time_stamp;
         let dt = car.dt;
-        imu_data.acce_x = ((car.velocity - self.previous_velocity )* car.yaw.cos() + acce_noise)/dt;
-        imu_data.acce_y = ((car.velocity - self.previous_velocity )* car.yaw.sin() + acce_noise)/dt;
-        imu_data.gyro_z = ((car.yaw + gyro_noise)- self.previous_yaw)/dt;
-        println!("car_yaw.cos: {}, car_yaw.sin: {}", car.yaw.cos(), car.yaw.sin());
+        // differencing the integrated estimate instead of the truth fed its drift back into the
+        // readings, which grew without bound
+        self.drift_biases(dt);
+        // forward and centripetal acceleration in the vehicle frame, seen along the sensor axes
+        // (which are biased and may be rotated by the mounting), then rotated by the vehicle yaw
+        // into the world frame as if the sensor were aligned with the vehicle
+        let forward = (car.velocity - self.last_true_velocity) / dt;
+        let lateral = car.velocity * (car.yaw - self.last_true_yaw) / dt;
+        let (mount_sin, mount_cos) = self.mounting_yaw.sin_cos();
+        let sensor_x = forward * mount_cos + lateral * mount_sin + self.bias.accel_forward;
+        let sensor_y = -forward * mount_sin + lateral * mount_cos + self.bias.accel_lateral;
+        imu_data.acce_x = sensor_x * car.yaw.cos() - sensor_y * car.yaw.sin() + acce_noise / dt;
+        imu_data.acce_y = sensor_x * car.yaw.sin() + sensor_y * car.yaw.cos() + acce_noise / dt;
+        imu_data.gyro_z = ((car.yaw + gyro_noise)- self.last_true_yaw)/dt + self.bias.gyro_z;
+        // the field along the world x axis, seen from the sensor axes
+        let sensor_yaw = car.yaw + self.mounting_yaw;
+        imu_data.mag_x = sensor_yaw.cos() + self.normal.sample(&mut self.rng) * self.mag_noise_ratio;
+        imu_data.mag_y = -sensor_yaw.sin() + self.normal.sample(&mut self.rng) * self.mag_noise_ratio;
+        self.last_true_yaw = car.yaw;
+        self.last_true_velocity = car.velocity;
         self.imu_recorder.push(imu_data.clone());
         self.get_imu_velocity_yaw(None, Some(dt));
 
     }
 
+    fn drift_biases(&mut self, dt: f64) {
+        if self.acce_bias_walk == 0.0 && self.gyro_bias_walk == 0.0 {
+            return;
+        }
+        let unit = Normal::new(0.0, dt.sqrt()).unwrap();
+        self.bias.accel_forward += unit.sample(&mut self.rng) * self.acce_bias_walk;
+        self.bias.accel_lateral += unit.sample(&mut self.rng) * self.acce_bias_walk;
+        self.bias.gyro_z += unit.sample(&mut self.rng) * self.gyro_bias_walk;
+    }
+
     pub fn get_imu_data(&mut self, idx: Option<usize>) -> IMU9Axis {
         self.imu_recorder[idx.unwrap_or(self.imu_recorder.len() - 1)]
     }
 
     pub fn get_imu_velocity_yaw(&mut self, idx: Option<usize>, dt:Option<f64>) {
+        self.last_pose = (self.previous_x, self.previous_y, self.previous_yaw);
         let _acce_x = self.imu_recorder[idx.unwrap_or(self.imu_recorder.len() - 1)].acce_x;
         let _acce_y = self.imu_recorder[idx.unwrap_or(self.imu_recorder.len() - 1)].acce_y;
     
@@ -104,9 +186,16 @@ impl IMUDevice {
         self.previous_velocity = self.previous_velocity + (_acce_x.powi(2) + _acce_y.powi(2)).sqrt() * dt.unwrap_or(_dt);
         self.previous_x = self.previous_x + self.previous_velocity * dt.unwrap_or(_dt) * self.previous_yaw.cos();
         self.previous_y = self.previous_y + self.previous_velocity * dt.unwrap_or(_dt) * self.previous_yaw.sin();
-        println!("previous_yaw.cos: {}, previous_yaw.sin: {}", self.previous_yaw.cos(), self.previous_yaw.sin());
         self.previous_yaw = self.previous_yaw + self.imu_recorder[idx.unwrap_or(self.imu_recorder.len() - 1)].gyro_z * dt.unwrap_or(_dt);
     }
+
+
+    // the vehicle stood still during the latest sample: undo its integration and hold the velocity
+    // at zero, so that the drift of the readings does not build up while standing
+    pub fn zero_velocity_update(&mut self) {
+        (self.previous_x, self.previous_y, self.previous_yaw) = self.last_pose;
+        self.previous_velocity = 0.0;
+    }
 }
 
 impl fmt::Display for IMUDevice {
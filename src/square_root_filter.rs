@@ -0,0 +1,227 @@
+use nalgebra::{DMatrix, Matrix2, Matrix3, Matrix4, SMatrix, SymmetricEigen, Vector3};
+
+use crate::car::KinematicBicycleModel;
+use crate::kalman_filter::{gps_measurement_jacobian, Estimator, EstimatorKind, KalmanFilter, Nis};
+use crate::sensors::GPS::XYZValues;
+use crate::state::{wrap_angle, CarState};
+
+// a factor L with L L^T = matrix for a symmetric positive semi-definite matrix; unlike a
+// Cholesky factor it also exists when the matrix is singular, e.g. for zero process noise
+pub fn matrix_square_root<const N: usize>(matrix: &SMatrix<f64, N, N>) -> SMatrix<f64, N, N> {
+    let symmetric = 0.5 * (matrix + matrix.transpose());
+    let eigen = SymmetricEigen::new(DMatrix::from_column_slice(N, N, symmetric.as_slice()));
+    let mut root = eigen.eigenvectors;
+    for (j, value) in eigen.eigenvalues.iter().enumerate() {
+        root.column_mut(j).scale_mut(value.max(0.0).sqrt());
+    }
+    SMatrix::from_column_slice(root.as_slice())
+}
+
+// EKF that propagates a square root S of the covariance (P = S S^T) with QR factorisations, so
+// the covariance it implies is symmetric and positive semi-definite by construction and the
+// numbers it works with span the square root of the dynamic range of P
+pub struct SquareRootFilter {
+    pub state: CarState,
+    // lower triangular
+    pub sqrt_covariance: Matrix4<f64>,
+    model: KinematicBicycleModel,
+    pub process_noise: Matrix4<f64>,
+    pub control_noise: Matrix2<f64>,
+    pub gps_noise: Matrix3<f64>,
+    pub covariance_faults: Vec<f64>,
+    pub nis: Nis,
+}
+
+impl SquareRootFilter {
+    // takes over the state, covariance and noise settings of an EKF
+    pub fn from_filter(filter: &KalmanFilter) -> Self {
+        Self {
+            state: filter.state,
+            sqrt_covariance: matrix_square_root(&filter.covariance),
+            model: filter.model.clone(),
+            process_noise: filter.process_noise,
+            control_noise: filter.control_noise,
+            gps_noise: filter.gps_noise,
+            covariance_faults: Vec::new(),
+            nis: Nis::default(),
+        }
+    }
+
+    pub fn predict(&mut self, acceleration: f64, steering_angle: f64) {
+        let s = &self.state;
+        let (f, g) = self.model._autodiff_jacobians(
+            s.x,
+            s.y,
+            s.yaw,
+            s.velocity,
+            acceleration,
+            steering_angle,
+        );
+        let predicted =
+            self.model
+                ._update(s.x, s.y, s.yaw, s.velocity, acceleration, steering_angle);
+        self.state.x = predicted.x;
+        self.state.y = predicted.y;
+        self.state.yaw = predicted.yaw;
+        self.state.velocity = predicted.velocity;
+        self.state.time_stamp += self.model.dt;
+
+        // P = F S S^T F^T + G M G^T + Q is A^T A for A stacking the transposed factors below;
+        // with A = Q R, R^T is a square root of P
+        let mut pre = SMatrix::<f64, 10, 4>::zeros();
+        pre.fixed_view_mut::<4, 4>(0, 0)
+            .copy_from(&(f * self.sqrt_covariance).transpose());
+        pre.fixed_view_mut::<2, 4>(4, 0)
+            .copy_from(&(g * matrix_square_root(&self.control_noise)).transpose());
+        pre.fixed_view_mut::<4, 4>(6, 0)
+            .copy_from(&matrix_square_root(&self.process_noise).transpose());
+        self.sqrt_covariance = pre.qr().r().transpose();
+        self.check();
+    }
+
+    // correct the estimate with a GPS fix in local coordinates (x, y, and heading in z)
+    pub fn update_gps(&mut self, measurement: &XYZValues) {
+        let (expected, h) = gps_measurement_jacobian(&self.state.to_vector4());
+        let mut innovation = Vector3::new(measurement.x, measurement.y, measurement.z) - expected;
+        innovation[2] = wrap_angle(innovation[2]);
+
+        // triangularising [[R^1/2, H S], [0, S]] gives [[Sy, 0], [K', S+]], where Sy is a square
+        // root of the innovation covariance, K' Sy^-1 the gain and S+ the updated square root
+        let mut pre = SMatrix::<f64, 7, 7>::zeros();
+        pre.fixed_view_mut::<3, 3>(0, 0)
+            .copy_from(&matrix_square_root(&self.gps_noise));
+        pre.fixed_view_mut::<3, 4>(0, 3)
+            .copy_from(&(h * self.sqrt_covariance));
+        pre.fixed_view_mut::<4, 4>(3, 3)
+            .copy_from(&self.sqrt_covariance);
+        let post = pre.transpose().qr().r().transpose();
+        let Some(sy_inv) = post.fixed_view::<3, 3>(0, 0).into_owned().try_inverse() else {
+            return;
+        };
+        // with S = Sy Sy^T, y^T S^-1 y is the squared length of Sy^-1 y
+        self.nis.gps = Some((sy_inv * innovation).norm_squared());
+        let gain = post.fixed_view::<4, 3>(3, 0) * sy_inv;
+        let correction = gain * innovation;
+        self.state.x += correction[0];
+        self.state.y += correction[1];
+        self.state.yaw += correction[2];
+        self.state.velocity += correction[3];
+        self.sqrt_covariance = post.fixed_view::<4, 4>(3, 3).into_owned();
+        self.check();
+    }
+
+    // a square root with a zero or invalid diagonal entry means P has lost full rank
+    fn check(&mut self) {
+        let scale = self.sqrt_covariance.amax().max(1e-300);
+        let degenerate = self
+            .sqrt_covariance
+            .diagonal()
+            .iter()
+            .any(|d| !d.is_finite() || d.abs() <= 1e-12 * scale);
+        if degenerate {
+            self.covariance_faults.push(self.state.time_stamp);
+        }
+    }
+}
+
+impl Estimator for SquareRootFilter {
+    fn name(&self) -> &'static str {
+        EstimatorKind::SquareRoot.name()
+    }
+    fn predict(&mut self, acceleration: f64, steering_angle: f64) {
+        SquareRootFilter::predict(self, acceleration, steering_angle);
+    }
+    fn update_gps(&mut self, measurement: &XYZValues) {
+        SquareRootFilter::update_gps(self, measurement);
+    }
+    fn state(&self) -> CarState {
+        self.state
+    }
+    fn covariance(&self) -> Matrix4<f64> {
+        self.sqrt_covariance * self.sqrt_covariance.transpose()
+    }
+    fn covariance_faults(&self) -> &[f64] {
+        &self.covariance_faults
+    }
+    fn nis(&self) -> Nis {
+        self.nis
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kalman_filter::is_positive_definite;
+    use crate::sensors::GPS::fix;
+
+    #[test]
+    fn test_matches_standard_ekf() {
+        let initial = CarState {
+            velocity: 5.0,
+            ..CarState::new()
+        };
+        let mut ekf = KalmanFilter::new(&initial, 2.0, 0.5, 0.1);
+        let mut joseph = KalmanFilter::new(&initial, 2.0, 0.5, 0.1);
+        joseph.joseph_form = true;
+        let mut sqrt = SquareRootFilter::from_filter(&ekf);
+        for k in 0..50 {
+            let measurement = fix(0.5 * k as f64, 0.1 * k as f64, 0.02);
+            for filter in [&mut ekf, &mut joseph] {
+                filter.predict(0.1, 0.05);
+                filter.update_gps(&measurement);
+            }
+            sqrt.predict(0.1, 0.05);
+            sqrt.update_gps(&measurement);
+        }
+        assert!((sqrt.covariance() - ekf.covariance).amax() < 1e-9);
+        assert!((joseph.covariance - ekf.covariance).amax() < 1e-9);
+        assert!((sqrt.state.to_vector4() - ekf.state.to_vector4()).amax() < 1e-9);
+    }
+
+    #[test]
+    fn test_precise_gps_and_covariance_faults() {
+        let initial = CarState::new();
+        let mut ekf = KalmanFilter::new(&initial, 2.0, 0.5, 0.1);
+        ekf.covariance = Matrix4::from_diagonal(&nalgebra::Vector4::new(1e6, 1e6, 1e2, 1e6));
+        ekf.gps_noise = Matrix3::identity() * 1e-14;
+        ekf.process_noise = Matrix4::identity() * 1e-12;
+        let mut sqrt = SquareRootFilter::from_filter(&ekf);
+        let mut joseph = KalmanFilter::new(&initial, 2.0, 0.5, 0.1);
+        joseph.covariance = ekf.covariance;
+        joseph.gps_noise = ekf.gps_noise;
+        joseph.process_noise = ekf.process_noise;
+        joseph.joseph_form = true;
+        joseph.enforce_symmetry = true;
+        for k in 0..200 {
+            let measurement = fix(0.0, 0.0, 0.0);
+            for filter in [&mut ekf, &mut joseph] {
+                filter.predict(0.0, 0.01 * k as f64);
+                filter.update_gps(&measurement);
+            }
+            sqrt.predict(0.0, 0.01 * k as f64);
+            sqrt.update_gps(&measurement);
+        }
+        // the plain update loses positive definiteness with such a precise GPS
+        assert!(!ekf.covariance_faults.is_empty());
+        assert!(joseph.covariance_faults.is_empty());
+        assert!(sqrt.covariance_faults.is_empty());
+        let covariance = sqrt.covariance();
+        assert_eq!(covariance, covariance.transpose());
+        assert!(covariance
+            .diagonal()
+            .iter()
+            .all(|variance| *variance >= 0.0));
+
+        // an asymmetric covariance is reported, unless symmetry is enforced
+        let mut broken = KalmanFilter::new(&initial, 2.0, 0.5, 0.1);
+        broken.covariance[(0, 1)] = 0.5;
+        broken.predict(0.0, 0.0);
+        assert_eq!(broken.covariance_faults.len(), 1);
+        let mut symmetrised = KalmanFilter::new(&initial, 2.0, 0.5, 0.1);
+        symmetrised.enforce_symmetry = true;
+        symmetrised.covariance[(0, 1)] = 0.5;
+        symmetrised.predict(0.0, 0.0);
+        assert!(symmetrised.covariance_faults.is_empty());
+        assert!(is_positive_definite(&symmetrised.covariance));
+    }
+}
@@ -1,4 +1,4 @@
-use nalgebra::Matrix4;
+use nalgebra::{Matrix4, Vector4};
 use piston_window::color;
 use std::fmt;
 use std::time::{Duration, SystemTime};
@@ -14,6 +14,11 @@ pub fn get_time_stamp() -> f64 {
     Duration::new(seconds, nanos).as_secs_f64()
 }
 
+// wrap an angle into [-pi, pi)
+pub fn wrap_angle(angle: f64) -> f64 {
+    (angle + std::f64::consts::PI).rem_euclid(2.0 * std::f64::consts::PI) - std::f64::consts::PI
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum CarColor {
     Red,
@@ -84,6 +89,90 @@ impl Rectangular {
             self.color.to_rgba(),
         );
     }
+
+    pub fn corners(&self) -> [(f64, f64); 4] {
+        [
+            (self.x1, self.y1),
+            (self.x2, self.y2),
+            (self.x3, self.y3),
+            (self.x4, self.y4),
+        ]
+    }
+}
+
+pub const DEFAULT_LENGTH: f64 = 40.0;
+pub const DEFAULT_WIDTH: f64 = 20.0;
+
+// the point of the vehicle that the pose (x, y) refers to
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ReferencePoint {
+    Center,
+    // distance from the rear axle forward to the geometric center
+    RearAxle(f64),
+}
+
+// oriented footprint of a vehicle: `length` runs along the heading, `width` across it
+#[derive(Debug, Copy, Clone)]
+pub struct Footprint {
+    pub x: f64,
+    pub y: f64,
+    pub heading: f64,
+    pub length: f64,
+    pub width: f64,
+    pub reference: ReferencePoint,
+}
+
+impl Footprint {
+    pub fn new(x: f64, y: f64, heading: f64, length: f64, width: f64, reference: ReferencePoint) -> Self {
+        Self {
+            x,
+            y,
+            heading,
+            length,
+            width,
+            reference,
+        }
+    }
+
+    // geometric center of the footprint in world coordinates
+    pub fn center(&self) -> (f64, f64) {
+        match self.reference {
+            ReferencePoint::Center => (self.x, self.y),
+            ReferencePoint::RearAxle(offset) => (
+                self.x + offset * self.heading.cos(),
+                self.y + offset * self.heading.sin(),
+            ),
+        }
+    }
+
+    // corners in world coordinates, ordered rear-right, rear-left, front-left, front-right
+    pub fn corners(&self) -> [(f64, f64); 4] {
+        let (cx, cy) = self.center();
+        let half_length = self.length / 2.0;
+        let half_width = self.width / 2.0;
+        let sin = self.heading.sin();
+        let cos = self.heading.cos();
+
+        // rotate about the vehicle center, then translate into the world frame
+        let to_world = |dx: f64, dy: f64| (cx + dx * cos - dy * sin, cy + dx * sin + dy * cos);
+
+        [
+            to_world(-half_length, -half_width),
+            to_world(-half_length, half_width),
+            to_world(half_length, half_width),
+            to_world(half_length, -half_width),
+        ]
+    }
+
+    pub fn to_rectangular(self, color: Option<CarColor>) -> Rectangular {
+        let mut rect = Rectangular::new(color);
+        let [c1, c2, c3, c4] = self.corners();
+        (rect.x1, rect.y1) = c1;
+        (rect.x2, rect.y2) = c2;
+        (rect.x3, rect.y3) = c3;
+        (rect.x4, rect.y4) = c4;
+        rect
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -107,8 +196,8 @@ impl CarState {
             y: 0.0,
             yaw: 0.0,
             velocity: 0.0,
-            width: Some(40.0),
-            length: Some(20.0),
+            width: Some(DEFAULT_WIDTH),
+            length: Some(DEFAULT_LENGTH),
         }
     }
 }
@@ -165,30 +254,20 @@ impl std::ops::Mul<f64> for CarState {
 
 impl CarState {
     pub fn to_rectangular(&mut self, color: Option<CarColor>) -> Rectangular {
-        let width = self.width.unwrap_or(40.0);
-        let length = self.length.unwrap_or(20.0);
-        let mut rect = Rectangular::new(color);
-        let x1 = self.x - (width / 2.0);
-        let y1 = self.y - (length / 2.0);
-        let x3 = self.x + (width / 2.0);
-        let y3 = self.y + (length / 2.0);
-        let x2 = self.x - (width / 2.0);
-        let y2 = self.y + (length / 2.0);
-        let x4 = self.x + (width / 2.0);
-        let y4 = self.y - (length / 2.0);
-
-        // Rotate the four corners of the car based on the yaw.
-        let sin = self.yaw.sin();
-        let cos = self.yaw.cos();
-
-        let rotate = |x: f64, y: f64| (x * cos - y * sin, x * sin + y * cos);
-
-        (rect.x1, rect.y1) = rotate(x1, y1);
-        (rect.x2, rect.y2) = rotate(x2, y2);
-        (rect.x3, rect.y3) = rotate(x3, y3);
-        (rect.x4, rect.y4) = rotate(x4, y4);
-        rect
+        self.to_footprint(ReferencePoint::Center).to_rectangular(color)
+    }
+
+    pub fn to_footprint(self, reference: ReferencePoint) -> Footprint {
+        Footprint {
+            x: self.x,
+            y: self.y,
+            heading: self.yaw,
+            length: self.length.unwrap_or(DEFAULT_LENGTH),
+            width: self.width.unwrap_or(DEFAULT_WIDTH),
+            reference,
+        }
     }
+
     pub fn to_matrixv4(&self) -> Matrix4<f64> {
         let mut matrix = Matrix4::identity();
         matrix[(0, 0)] = self.x;
@@ -198,6 +277,10 @@ impl CarState {
         matrix
     }
 
+    pub fn to_vector4(self) -> Vector4<f64> {
+        Vector4::new(self.x, self.y, self.yaw, self.velocity)
+    }
+
     pub fn from_matrixv4(matrix: Matrix4<f64>) -> Self {
         Self {
             dt: 0.1,
@@ -255,4 +338,49 @@ mod tests {
         let rectangular = car_state.to_rectangular(Some(CarColor::Red));
         println!("{:?}", rectangular);
     }
+
+    fn assert_close(a: (f64, f64), b: (f64, f64)) {
+        assert!((a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_footprint_rotates_about_center() {
+        let mut car_state = CarState::new();
+        car_state.x = 100.0;
+        car_state.y = 200.0;
+        car_state.length = Some(40.0);
+        car_state.width = Some(20.0);
+
+        car_state.yaw = 0.0;
+        let corners = car_state.to_footprint(ReferencePoint::Center).corners();
+        assert_close(corners[0], (80.0, 190.0));
+        assert_close(corners[2], (120.0, 210.0));
+
+        car_state.yaw = std::f64::consts::FRAC_PI_2;
+        let corners = car_state.to_footprint(ReferencePoint::Center).corners();
+        assert_close(corners[0], (110.0, 180.0));
+        assert_close(corners[2], (90.0, 220.0));
+
+        // the center of the drawn rectangle must stay on the car for any yaw
+        car_state.yaw = 1.234;
+        let rect = car_state.to_rectangular(None);
+        assert_close(((rect.x1 + rect.x3) / 2.0, (rect.y1 + rect.y3) / 2.0), (100.0, 200.0));
+        assert_close(((rect.x2 + rect.x4) / 2.0, (rect.y2 + rect.y4) / 2.0), (100.0, 200.0));
+    }
+
+    #[test]
+    fn test_footprint_rear_axle_reference() {
+        let footprint = Footprint::new(
+            50.0,
+            -30.0,
+            std::f64::consts::FRAC_PI_2,
+            40.0,
+            20.0,
+            ReferencePoint::RearAxle(10.0),
+        );
+        assert_close(footprint.center(), (50.0, -20.0));
+        let corners = footprint.corners();
+        assert_close(corners[0], (60.0, -40.0));
+        assert_close(corners[2], (40.0, 0.0));
+    }
 }
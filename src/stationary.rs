@@ -0,0 +1,187 @@
+use std::collections::VecDeque;
+
+use crate::sensors::IMU::IMU9Axis;
+use crate::state::wrap_angle;
+
+// decides whether the vehicle stands still: over the latest `window` samples the accelerometer
+// and the gyro barely vary, and the wheel encoder neither counts nor reports a speed. Driving
+// straight at a constant speed is just as quiet on the IMU, so the encoder is what tells the two
+// apart
+#[derive(Debug, Clone)]
+pub struct StationaryDetector {
+    // number of quiet samples needed before the vehicle counts as stationary
+    pub window: usize,
+    // largest variances over the window of the acceleration, summed over both axes, and of the
+    // yaw rate
+    pub accel_variance: f64,
+    pub gyro_variance: f64,
+    // largest encoder speed that counts as standing still
+    pub speed_threshold: f64,
+    // (acceleration x, acceleration y, yaw rate) of the latest samples
+    samples: VecDeque<[f64; 3]>,
+    last_count: Option<i32>,
+    stationary: bool,
+}
+
+impl StationaryDetector {
+    pub fn new() -> Self {
+        Self {
+            window: 5,
+            accel_variance: 0.01,
+            gyro_variance: 0.001,
+            speed_threshold: 0.01,
+            samples: VecDeque::new(),
+            last_count: None,
+            stationary: false,
+        }
+    }
+
+    // takes the latest IMU sample with the encoder count and speed, and returns whether the
+    // vehicle is stationary
+    pub fn update(&mut self, imu: &IMU9Axis, encoder_count: i32, encoder_speed: f64) -> bool {
+        self.samples.push_back([imu.acce_x, imu.acce_y, imu.gyro_z]);
+        while self.samples.len() > self.window {
+            self.samples.pop_front();
+        }
+        let wheels_still = self.last_count.is_none_or(|count| count == encoder_count)
+            && encoder_speed.abs() < self.speed_threshold;
+        self.last_count = Some(encoder_count);
+        self.stationary = wheels_still && self.samples.len() >= self.window && {
+            let [accel_x, accel_y, gyro] = variances(self.samples.iter());
+            accel_x + accel_y <= self.accel_variance && gyro <= self.gyro_variance
+        };
+        self.stationary
+    }
+
+    pub fn is_stationary(&self) -> bool {
+        self.stationary
+    }
+}
+
+impl Default for StationaryDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn means<'a, const N: usize>(samples: impl Iterator<Item = &'a [f64; N]>) -> [f64; N] {
+    let mut sum = [0.0; N];
+    let mut count = 0;
+    for sample in samples {
+        for (sum, value) in sum.iter_mut().zip(sample) {
+            *sum += value;
+        }
+        count += 1;
+    }
+    sum.map(|sum| sum / count.max(1) as f64)
+}
+
+fn variances<'a, const N: usize>(samples: impl Iterator<Item = &'a [f64; N]> + Clone) -> [f64; N] {
+    let mean = means(samples.clone());
+    let squares: Vec<[f64; N]> = samples
+        .map(|sample| {
+            let mut square = [0.0; N];
+            for i in 0..N {
+                square[i] = (sample[i] - mean[i]).powi(2);
+            }
+            square
+        })
+        .collect();
+    means(squares.iter())
+}
+
+// what a stretch of samples taken at rest tells about the IMU: the gyro reads its bias, the
+// accelerometer (reported in the world frame) its biases and the magnetometer the heading of the
+// sensor axes. Each mean comes with the variance of the mean
+#[derive(Debug, Copy, Clone)]
+pub struct StaticAlignment {
+    pub heading: f64,
+    pub heading_variance: f64,
+    // mean world frame acceleration (x, y)
+    pub acceleration: [f64; 2],
+    pub acceleration_variance: [f64; 2],
+    pub yaw_rate: f64,
+    pub yaw_rate_variance: f64,
+}
+
+impl StaticAlignment {
+    // None without any samples
+    pub fn from_samples(samples: &[IMU9Axis]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let count = samples.len() as f64;
+        // headings are averaged as unit vectors, so that they do not wrap around at pi
+        let [sin, cos] = means(
+            samples
+                .iter()
+                .map(|sample| {
+                    let heading = sample.heading();
+                    [heading.sin(), heading.cos()]
+                })
+                .collect::<Vec<_>>()
+                .iter(),
+        );
+        let heading = sin.atan2(cos);
+        let readings: Vec<[f64; 4]> = samples
+            .iter()
+            .map(|sample| {
+                let deviation = wrap_angle(sample.heading() - heading);
+                [deviation, sample.acce_x, sample.acce_y, sample.gyro_z]
+            })
+            .collect();
+        let mean = means(readings.iter());
+        let variance = variances(readings.iter());
+        // the variance of a single sample is a poor estimate of the spread of a few, so it is
+        // kept above a floor before dividing by the count
+        let of_mean = |variance: f64| variance.max(1e-6) / count;
+        Some(Self {
+            heading,
+            heading_variance: of_mean(variance[0]),
+            acceleration: [mean[1], mean[2]],
+            acceleration_variance: [of_mean(variance[1]), of_mean(variance[2])],
+            yaw_rate: mean[3],
+            yaw_rate_variance: of_mean(variance[3]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensor_measurement::SensorSet;
+    use crate::state::CarState;
+
+    #[test]
+    fn test_detects_standstill_but_not_constant_speed() {
+        let mut car = CarState {
+            velocity: 0.0,
+            dt: 0.1,
+            time_stamp: 0.0,
+            ..CarState::new()
+        };
+        let mut sensors = SensorSet::new(&car);
+        sensors.seed(3);
+        let mut stationary = Vec::new();
+        for step in 0..70 {
+            // at rest for 2 s, then accelerating, cruising and braking to a stop for 1 s each
+            let acceleration = match step {
+                20..=29 => 1.0,
+                40..=49 => -1.0,
+                _ => 0.0,
+            };
+            car.velocity = (car.velocity + acceleration * car.dt).max(0.0);
+            car.x += car.velocity * car.dt;
+            car.time_stamp += car.dt;
+            sensors.from_carstate(&car);
+            stationary.push(sensors.stationary.is_stationary());
+        }
+        // the window has to fill before the first standstill is recognised
+        assert!(!stationary[..4].iter().any(|s| *s));
+        assert!(stationary[5..20].iter().all(|s| *s));
+        // moving, whether accelerating or at constant speed, until braking ends in the last step
+        assert!(!stationary[20..49].iter().any(|s| *s));
+        assert!(stationary[55..].iter().all(|s| *s));
+        // the dead-reckoned speed is held at zero instead of integrating the readings
+        assert_eq!(sensors.imu.previous_velocity, 0.0);
+    }
+}
@@ -0,0 +1,301 @@
+use nalgebra::Matrix4;
+
+use crate::car::Car;
+use crate::collision::{detect_collisions, Contact};
+use crate::controller::{ControlSource, Controller};
+use crate::kalman_filter::{Estimator, EstimatorConfig};
+use crate::sensor_measurement::SensorSet;
+use crate::sensors::GPS::XYZValues;
+use crate::state::{CarColor, CarState, Footprint, Rectangular};
+
+// a car together with everything that drives, observes and estimates it
+pub struct Vehicle {
+    pub id: usize,
+    pub car: Car,
+    pub controller: Box<dyn Controller>,
+    pub control_source: ControlSource,
+    pub sensors: SensorSet,
+    pub estimator: Box<dyn Estimator>,
+    pub ground_truth_rect: Rectangular,
+    pub measured_rect: Rectangular,
+    pub history: VehicleHistory,
+    // (acceleration, steering_angle) applied instead of the controller's commands while set,
+    // e.g. by a person driving the car
+    pub manual_control: Option<(f64, f64)>,
+}
+
+// the states of a vehicle after every step so far, and the GPS fixes in the order they were
+// delivered to its estimator
+#[derive(Debug, Clone, Default)]
+pub struct VehicleHistory {
+    pub ground_truth: Vec<CarState>,
+    pub dead_reckoning: Vec<CarState>,
+    pub estimate: Vec<CarState>,
+    pub gps_fixes: Vec<XYZValues>,
+}
+
+// what a renderer or logger needs to know about one vehicle after a step
+#[derive(Debug, Copy, Clone)]
+pub struct VehicleSnapshot {
+    pub id: usize,
+    pub time: f64,
+    pub ground_truth: CarState,
+    pub measured: CarState,
+    pub estimate: CarState,
+    pub covariance: Matrix4<f64>,
+    pub ground_truth_rect: Rectangular,
+    pub measured_rect: Rectangular,
+    pub estimate_rect: Rectangular,
+    pub latest_gps_fix: Option<XYZValues>,
+    // standard deviation of the noise on each GPS coordinate
+    pub gps_noise_std_dev: f64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Body {
+    Vehicle(usize),
+    Obstacle(usize),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct WorldCollision {
+    pub time: f64,
+    pub first: Body,
+    pub second: Body,
+    pub contact: Contact,
+    // the two bodies were not in contact after the previous step
+    pub started: bool,
+}
+
+// owns every vehicle and static obstacle and steps them together on one clock
+pub struct World {
+    pub time: f64,
+    pub dt: f64,
+    pub vehicles: Vec<Vehicle>,
+    pub obstacles: Vec<Footprint>,
+    pub collisions: Vec<WorldCollision>,
+    // estimator given to vehicles added from now on
+    pub estimator: EstimatorConfig,
+    // base seed for sensor and actuator noise; each vehicle derives its own streams from it
+    pub seed: Option<u64>,
+}
+
+impl World {
+    pub fn new(dt: f64) -> Self {
+        Self {
+            time: 0.0,
+            dt,
+            vehicles: Vec::new(),
+            obstacles: Vec::new(),
+            collisions: Vec::new(),
+            estimator: EstimatorConfig::default(),
+            seed: None,
+        }
+    }
+
+    // adds a car driven by `controller`, giving it its own sensors and estimator; returns its id
+    pub fn add_vehicle(&mut self, mut car: Car, controller: Box<dyn Controller>) -> usize {
+        let id = self.vehicles.len();
+        car.model.dt = self.dt;
+        car.state.dt = self.dt;
+        car.state.time_stamp = self.time;
+        let estimator = self.estimator.build(
+            &car.state,
+            car.model.wheelbase,
+            car.model.max_steer,
+            self.dt,
+        );
+        let mut sensors = SensorSet::new(&car.state);
+        if let Some(seed) = self.seed {
+            let seed = seed.wrapping_add(16 * id as u64);
+            sensors.seed(seed);
+            car.throttle.seed(seed.wrapping_add(3));
+            car.steering.seed(seed.wrapping_add(4));
+        }
+        let ground_truth_rect = car.state.to_rectangular(car.color);
+        self.vehicles.push(Vehicle {
+            id,
+            sensors,
+            estimator,
+            measured_rect: ground_truth_rect,
+            ground_truth_rect,
+            car,
+            controller,
+            control_source: ControlSource::GroundTruth,
+            history: VehicleHistory::default(),
+            manual_control: None,
+        });
+        id
+    }
+
+    pub fn add_obstacle(&mut self, footprint: Footprint) -> usize {
+        self.obstacles.push(footprint);
+        self.obstacles.len() - 1
+    }
+
+    // advances every vehicle by one `dt` and returns the collisions present after the step
+    pub fn step(&mut self) -> &[WorldCollision] {
+        self.time += self.dt;
+        for vehicle in self.vehicles.iter_mut() {
+            let observed = match vehicle.control_source {
+                ControlSource::GroundTruth => vehicle.car.state,
+                ControlSource::Estimate => vehicle.estimator.state(),
+            };
+            let (acceleration, steering_angle) = match vehicle.manual_control {
+                Some(command) => command,
+                None => vehicle.controller.control(self.time, &observed),
+            };
+            vehicle.car.step(acceleration, steering_angle);
+            vehicle.car.state.time_stamp = self.time;
+            vehicle.ground_truth_rect = vehicle.car.state.to_rectangular(vehicle.car.color);
+
+            vehicle.measured_rect = vehicle.sensors.get_observed_state(&vehicle.car.state);
+
+            vehicle.estimator.predict(acceleration, steering_angle);
+            let imu = vehicle.sensors.imu.get_imu_data(None);
+            if vehicle.sensors.stationary.is_stationary() {
+                vehicle.estimator.update_stationary(&imu);
+            } else {
+                vehicle.estimator.update_imu(&imu);
+            }
+            vehicle
+                .estimator
+                .update_odometry(vehicle.sensors.encoder.get_velocity());
+            let gps_fixes = vehicle.sensors.take_gps_fixes(self.time);
+            vehicle.estimator.update_gps_fixes(&gps_fixes);
+
+            let history = &mut vehicle.history;
+            history.ground_truth.push(vehicle.car.state);
+            history.dead_reckoning.push(vehicle.sensors.measured_state);
+            history.estimate.push(vehicle.estimator.state());
+            history.gps_fixes.extend(gps_fixes);
+        }
+
+        let bodies: Vec<Body> = (0..self.vehicles.len())
+            .map(Body::Vehicle)
+            .chain((0..self.obstacles.len()).map(Body::Obstacle))
+            .collect();
+        let boxes: Vec<Rectangular> = self
+            .vehicles
+            .iter()
+            .map(|vehicle| vehicle.ground_truth_rect)
+            .chain(
+                self.obstacles
+                    .iter()
+                    .map(|obstacle| obstacle.to_rectangular(None)),
+            )
+            .collect();
+        let time = self.time;
+        let previous = std::mem::take(&mut self.collisions);
+        self.collisions = detect_collisions(&boxes)
+            .into_iter()
+            .map(|collision| {
+                let (first, second) = (bodies[collision.first], bodies[collision.second]);
+                WorldCollision {
+                    time,
+                    first,
+                    second,
+                    contact: collision.contact,
+                    started: !previous
+                        .iter()
+                        .any(|before| before.first == first && before.second == second),
+                }
+            })
+            .collect();
+        &self.collisions
+    }
+
+    pub fn snapshots(&self) -> Vec<VehicleSnapshot> {
+        self.vehicles
+            .iter()
+            .map(|vehicle| VehicleSnapshot {
+                id: vehicle.id,
+                time: self.time,
+                ground_truth: vehicle.car.state,
+                measured: vehicle.sensors.measured_state,
+                estimate: vehicle.estimator.state(),
+                covariance: vehicle.estimator.covariance(),
+                ground_truth_rect: vehicle.ground_truth_rect,
+                measured_rect: vehicle.measured_rect,
+                estimate_rect: vehicle
+                    .estimator
+                    .state()
+                    .to_rectangular(Some(CarColor::Green)),
+                latest_gps_fix: vehicle.history.gps_fixes.last().copied(),
+                gps_noise_std_dev: vehicle.sensors.gps.noise_std_dev(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::ConstantControl;
+    use crate::state::ReferencePoint;
+
+    #[test]
+    fn test_world_steps_vehicles_together() {
+        let mut world = World::new(0.1);
+        let a = Car::new(0.0, 0.0, 0.0, 20.0, 40.0, 5.0, 2.0, 0.5, 0.1, None);
+        let b = Car::new(0.0, 100.0, 0.0, 20.0, 40.0, 0.0, 2.0, 0.5, 0.1, None);
+        world.add_vehicle(a, Box::new(ConstantControl::new(0.0, 0.0)));
+        world.add_vehicle(b, Box::new(ConstantControl::new(1.0, 0.0)));
+        for _ in 0..10 {
+            world.step();
+        }
+        let snapshots = world.snapshots();
+        assert_eq!(snapshots.len(), 2);
+        assert!((snapshots[0].time - 1.0).abs() < 1e-9);
+        assert!((snapshots[0].ground_truth.x - 5.0).abs() < 1e-9);
+        assert!(snapshots[1].ground_truth.velocity > 0.9);
+        assert_eq!(world.vehicles[0].history.ground_truth.len(), 10);
+        assert_eq!(world.vehicles[0].history.estimate.len(), 10);
+        assert!(world.collisions.is_empty());
+    }
+
+    #[test]
+    fn test_world_reports_obstacle_collision() {
+        let mut world = World::new(0.1);
+        let car = Car::new(0.0, 0.0, 0.0, 20.0, 40.0, 10.0, 2.0, 0.5, 0.1, None);
+        world.add_vehicle(car, Box::new(ConstantControl::new(0.0, 0.0)));
+        world.add_obstacle(Footprint::new(
+            60.0,
+            0.0,
+            0.0,
+            10.0,
+            10.0,
+            ReferencePoint::Center,
+        ));
+        let mut hit = None;
+        for _ in 0..50 {
+            if let Some(collision) = world.step().first() {
+                hit = Some(*collision);
+                break;
+            }
+        }
+        let hit = hit.expect("car should reach the obstacle");
+        assert_eq!(hit.first, Body::Vehicle(0));
+        assert_eq!(hit.second, Body::Obstacle(0));
+        assert!(hit.started);
+        // still overlapping on the next step, but no longer a new contact
+        let ongoing = world.step().first().copied().expect("car is still in the obstacle");
+        assert!(!ongoing.started);
+    }
+
+    #[test]
+    fn test_manual_control_overrides_controller() {
+        let mut world = World::new(0.1);
+        let car = Car::new(0.0, 0.0, 0.0, 20.0, 40.0, 0.0, 2.0, 0.5, 0.1, None);
+        world.add_vehicle(car, Box::new(ConstantControl::new(0.0, 0.0)));
+        world.vehicles[0].manual_control = Some((1.0, 0.0));
+        for _ in 0..10 {
+            world.step();
+        }
+        let vehicle = &world.vehicles[0];
+        assert!(vehicle.car.state.velocity > 0.9);
+        // the estimator keeps running on the sensors
+        assert_eq!(vehicle.history.estimate.len(), 10);
+        assert!(vehicle.estimator.state().x > 0.0);
+    }
+}